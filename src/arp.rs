@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+use crate::noise::Noise;
+use crate::NoiseKind;
+
+/// ----------  Arpeggiator ----------
+/// Order the held keys are stepped through.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ArpMode {
+    #[default]
+    Up,
+    Down,
+    UpDown, // the top and bottom notes are not repeated
+    Random,
+}
+
+impl ArpMode {
+    pub const ALL: [ArpMode; 4] = [ArpMode::Up, ArpMode::Down, ArpMode::UpDown, ArpMode::Random];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ArpMode::Up => "Up",
+            ArpMode::Down => "Down",
+            ArpMode::UpDown => "Up/Down",
+            ArpMode::Random => "Random",
+        }
+    }
+}
+
+/// Arpeggiator settings. While `on`, held keys no longer play directly;
+/// the arpeggiator plays them one at a time, over `octaves` octaves.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Arp {
+    pub on: bool,
+    pub mode: ArpMode,
+    pub octaves: u8, // 1..=4
+    pub rate: f32,   // steps per second
+    pub sync: bool,  // follow the tempo instead of `rate`
+    pub beats: f32,  // step length in quarter notes when synced
+    pub gate: f32,   // 0..1, part of each step the note is held for
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        Self { on: false, mode: ArpMode::Up, octaves: 1, rate: 8.0, sync: true, beats: 0.25, gate: 0.5 }
+    }
+}
+
+impl Arp {
+    /// Steps per second at `bpm`, at most 64 a beat when synced.
+    fn step_rate(&self, bpm: f32) -> f32 {
+        if self.sync { bpm / 60.0 / self.beats.max(1.0 / 64.0) } else { self.rate.max(0.01) }
+    }
+}
+
+/// Notes a pattern player wants started and stopped after a control block.
+#[derive(Default)]
+pub(crate) struct NoteChange {
+    pub off: Option<u8>,
+    pub on: Option<(u8, u8)>, // note, velocity
+}
+
+/// The running arpeggiator: the keys it holds and where it is in the pattern.
+pub(crate) struct Arpeggiator {
+    held: u128,           // one bit per key down
+    velocity: [u8; 128],  // what each held key was struck with
+    pos: usize,           // steps played since the pattern started
+    phase: f32,           // 0..1 through the current step
+    sounding: Option<u8>, // the note it is playing, until its gate closes
+    rng: Noise,
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Self {
+        Self { held: 0, velocity: [0; 128], pos: 0, phase: 0.0, sounding: None, rng: Noise::new(0x2545_F491) }
+    }
+}
+
+impl Arpeggiator {
+    /// A first key starts the pattern on the next block.
+    pub fn press(&mut self, note: u8, velocity: u8) {
+        if self.held == 0 { self.restart(); }
+        self.held |= 1 << note;
+        self.velocity[note as usize] = velocity;
+    }
+
+    pub fn release(&mut self, note: u8) { self.held &= !(1u128 << note); }
+
+    /// Begins the pattern again from its first step.
+    pub fn restart(&mut self) {
+        self.pos = 0;
+        self.phase = 1.0;
+    }
+
+    /// Lets go of every key; returns the note left sounding, if any.
+    pub fn clear(&mut self) -> Option<u8> {
+        self.held = 0;
+        self.sounding.take()
+    }
+
+    /// Key and octave of pattern step `k`, given `count` keys down.
+    fn note_at(&self, k: usize, count: usize) -> u8 {
+        let key = (0..128u8).filter(|&n| self.held >> n & 1 == 1).nth(k % count).unwrap_or(0);
+        (key as usize + 12 * (k / count)).min(127) as u8
+    }
+
+    /// Moves `dt` seconds on, closing the gate and starting the next step
+    /// when their times come.
+    pub fn advance(&mut self, arp: &Arp, dt: f32, bpm: f32) -> NoteChange {
+        let mut step = NoteChange::default();
+        self.phase += arp.step_rate(bpm) * dt;
+        if self.phase >= arp.gate.clamp(0.01, 1.0) && self.phase < 1.0 { step.off = self.sounding.take(); }
+        if self.phase < 1.0 { return step; }
+        self.phase = self.phase.fract();
+        step.off = step.off.or(self.sounding.take());
+        let count = self.held.count_ones() as usize;
+        if count == 0 { return step; }
+
+        let len = count * arp.octaves.clamp(1, 4) as usize;
+        let k = match arp.mode {
+            ArpMode::Up => self.pos % len,
+            ArpMode::Down => len - 1 - self.pos % len,
+            ArpMode::UpDown if len < 2 => 0,
+            ArpMode::UpDown => {
+                let p = self.pos % (2 * len - 2);
+                if p < len { p } else { 2 * len - 2 - p }
+            }
+            ArpMode::Random => ((self.rng.sample(NoiseKind::White) * 0.5 + 0.5) * len as f32) as usize % len,
+        };
+        self.pos += 1;
+        let note = self.note_at(k, count);
+        let key = self.note_at(k % count, count);
+        self.sounding = Some(note);
+        step.on = Some((note, self.velocity[key as usize]));
+        step
+    }
+}
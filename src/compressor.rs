@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Compressor ----------
+/// Bus compressor settings.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Compressor {
+    pub on: bool,
+    pub threshold: f32, // dB, -60..0
+    pub ratio: f32,     // 1..20, input dB over the threshold per output dB
+    pub attack: f32,    // ms
+    pub release: f32,   // ms
+    pub makeup: f32,    // dB, 0..24
+}
+
+impl Default for Compressor {
+    fn default() -> Self { Self { on: false, threshold: -18.0, ratio: 4.0, attack: 5.0, release: 120.0, makeup: 0.0 } }
+}
+
+/// Feed-forward, stereo-linked peak compressor working in decibels.
+pub(crate) struct BusCompressor {
+    env: f32,       // smoothed gain reduction, dB
+    reduction: f32, // deepest gain reduction since last taken, dB
+    sr: f32,
+}
+
+impl BusCompressor {
+    pub fn new(sr: f32) -> Self { Self { env: 0.0, reduction: 0.0, sr } }
+
+    /// The deepest gain reduction since the last call, in dB (0 when idle).
+    pub fn take_reduction(&mut self) -> f32 { std::mem::take(&mut self.reduction) }
+
+    /// Compresses a stereo block in place.
+    pub fn process(&mut self, settings: &Compressor, l: &mut [f32], r: &mut [f32]) {
+        if !settings.on { self.env = 0.0; return; }
+        let coef = |ms: f32| (-1.0 / (ms.max(0.1) * 0.001 * self.sr)).exp();
+        let (attack, release) = (coef(settings.attack), coef(settings.release));
+        let slope = 1.0 - 1.0 / settings.ratio.max(1.0);
+        let makeup = settings.makeup;
+        for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+            let level = 20.0 * a.abs().max(b.abs()).max(1e-6).log10();
+            let target = (level - settings.threshold).max(0.0) * slope;
+            let k = if target > self.env { attack } else { release };
+            self.env = target + (self.env - target) * k;
+            self.reduction = self.reduction.max(self.env);
+            let gain = 10f32.powf((makeup - self.env) / 20.0);
+            *a *= gain;
+            *b *= gain;
+        }
+    }
+}
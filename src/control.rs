@@ -0,0 +1,176 @@
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::Arc;
+use triple_buffer::{triple_buffer, Input, Output};
+use web_time::Instant;
+
+use crate::monitor::{self, Levels, Meters, Monitor, Tap};
+use crate::record::{self, Capture, Recorder};
+use crate::params::{Param, Switch};
+use crate::{FMSynth, Patch};
+
+/// ----------  Events ----------
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    NoteOn { note: u8, velocity: u8, channel: u8 }, // channel 0..15
+    NoteOff { note: u8 },
+    Gate(bool),     // the UI's NOTE ON/OFF button
+    ModWheel(f32),  // 0..1
+    PitchBend(f32), // -1..1
+    Sustain(bool),  // damper pedal, CC64
+    /// Per-channel expression from MIDI. With MPE on, member channels
+    /// (1..15) reach only the notes started on them; otherwise, like the
+    /// master channel 0, they act on the whole synth.
+    ChannelBend { channel: u8, value: f32 },     // -1..1
+    ChannelPressure { channel: u8, value: f32 }, // 0..1
+    Slide { channel: u8, value: f32 },           // CC74, 0..1
+    /// Any other controller; goes to the UI's MIDI map, not the engine.
+    ControlChange { channel: u8, cc: u8, value: u8 },
+    /// A parameter set remotely, in its own units; also for the UI.
+    SetParam(Param, f32),
+    /// A mode or on/off switch set remotely, see `Switch::set`.
+    SetSwitch(Switch, f32),
+    /// Tempo of an external MIDI clock, BPM, sent while its pulses arrive.
+    ClockTempo(f32),
+    Start,    // sequencer transport
+    Continue,
+    Stop,
+}
+
+const QUEUE_LEN: usize = 1024;
+/// How far the DSP load meter falls towards a lighter block, per block.
+const LOAD_SMOOTHING: f32 = 0.05;
+/// Gap between callbacks, in blocks, taken as a dropout.
+const XRUN_GAP: f32 = 2.0;
+/// Output samples kept for display.
+const SCOPE_LEN: usize = 4096;
+
+/// Splits `synth` into a UI-side `Controller` and an audio-side `Engine`.
+/// Patches travel through a triple buffer and events through a bounded
+/// queue, so neither side ever waits for the other.
+pub fn channel(synth: FMSynth) -> (Controller, Engine) {
+    let (patch_in, patch_out) = triple_buffer(&synth.patch);
+    let (tx, rx) = crossbeam_channel::bounded(QUEUE_LEN);
+    let cc = crossbeam_channel::bounded(QUEUE_LEN);
+    let (played_tx, played_rx) = crossbeam_channel::bounded(QUEUE_LEN);
+    let (tap, scope) = monitor::monitor(SCOPE_LEN);
+    let (capture, recorder) = record::recorder(synth.sample_rate());
+    let meters = Arc::new(Meters::default());
+    let levels = Levels::new(synth.sample_rate());
+    (Controller { patch: patch_in, events: tx, cc, played: played_rx, scope, recorder, meters: meters.clone() },
+     Engine { synth, patch: patch_out, events: rx, played: played_tx, frames: 0, tap, capture, meters, levels, load: 0.0, last_call: None,
+              #[cfg(feature = "link")] link: None })
+}
+
+/// ----------  UI side ----------
+pub struct Controller {
+    patch: Input<Patch>,
+    events: Sender<Event>,
+    cc: (Sender<Event>, Receiver<Event>), // controller changes bound for the UI
+    played: Receiver<(u64, Event)>,
+    scope: Monitor,
+    recorder: Recorder,
+    meters: Arc<Meters>,
+}
+
+impl Controller {
+    pub fn publish(&mut self, patch: &Patch) { self.patch.write(patch.clone()); }
+
+    /// Drops the event if the audio thread has fallen that far behind.
+    pub fn send(&self, event: Event) { let _ = self.events.try_send(event); }
+
+    /// Another producer, e.g. for the MIDI input thread.
+    pub fn sender(&self) -> Sender<Event> { self.events.clone() }
+
+    /// Where MIDI and OSC input put `Event::ControlChange`s,
+    /// `Event::SetParam`s and `Event::SetSwitch`es for the UI.
+    pub fn cc_sender(&self) -> Sender<Event> { self.cc.0.clone() }
+
+    /// Controller changes received since the last call.
+    pub fn cc_events(&self) -> impl Iterator<Item = Event> + '_ { self.cc.1.try_iter() }
+
+    /// Events the engine has played since the last call, each with the
+    /// output frame it took effect on, for recording performances.
+    pub fn played(&self) -> impl Iterator<Item = (u64, Event)> + '_ { self.played.try_iter() }
+
+    /// The most recent output, refreshed on every call.
+    pub fn scope(&mut self) -> &Monitor {
+        self.scope.update();
+        &self.scope
+    }
+
+    /// Records the output to WAV files while playing.
+    pub fn recorder(&mut self) -> &mut Recorder { &mut self.recorder }
+
+    /// Levels from the last block the engine rendered.
+    pub fn meters(&self) -> &Meters { &self.meters }
+}
+
+/// ----------  Audio side ----------
+pub struct Engine {
+    pub synth: FMSynth,
+    patch: Output<Patch>,
+    events: Receiver<Event>,
+    played: Sender<(u64, Event)>,
+    frames: u64, // rendered so far
+    tap: Tap,
+    capture: Capture,
+    meters: Arc<Meters>,
+    levels: Levels,
+    load: f32, // smoothed fraction of each block's real-time budget spent rendering it
+    last_call: Option<(Instant, f32)>, // previous block: when it was asked for, its length in seconds
+    #[cfg(feature = "link")]
+    link: Option<crate::link::LinkClock>,
+}
+
+impl Engine {
+    /// Follows `link`'s tempo and beat whenever it is enabled.
+    #[cfg(feature = "link")]
+    pub fn set_link(&mut self, link: crate::link::Link) { self.link = Some(crate::link::LinkClock::new(link)); }
+
+    /// Rebuilds the synth for a new output rate, keeping its patch; notes
+    /// and effect tails start over. Called whenever a stream (re)starts, so
+    /// the wait for its first block isn't taken for an xrun.
+    pub fn set_sample_rate(&mut self, sr: f32) {
+        self.last_call = None;
+        if sr == self.synth.sample_rate() { return; }
+        let patch = std::mem::take(&mut self.synth.patch);
+        self.synth = FMSynth::new(sr);
+        self.synth.patch = patch;
+        self.levels = Levels::new(sr);
+    }
+
+    /// Renders interleaved frames, see `FMSynth::render_block`.
+    pub fn render_block(&mut self, out: &mut [f32], channels: usize) {
+        let start = Instant::now();
+        let frames = out.len() / channels.max(1);
+        let budget = frames as f32 / self.synth.sample_rate();
+        // A callback arriving long after the previous block ran out means
+        // the device went without audio in between
+        let late = self.last_call.replace((start, budget))
+            .is_some_and(|(last, length)| (start - last).as_secs_f32() > length * XRUN_GAP);
+        // Swap rather than clone: the stale patch goes back to the UI side,
+        // which frees it, so the audio thread never allocates.
+        if self.patch.update() {
+            std::mem::swap(&mut self.synth.patch, self.patch.output_buffer_mut());
+        }
+        while let Ok(event) = self.events.try_recv() {
+            self.synth.handle(event);
+            let _ = self.played.try_send((self.frames, event));
+        }
+        #[cfg(feature = "link")]
+        if let Some((bpm, beat)) = self.link.as_mut().and_then(|l| l.capture()) { self.synth.follow_beat(bpm, beat); }
+        self.synth.render_block(out, channels);
+        self.tap.write_frames(out, channels);
+        self.capture.write_frames(out, channels);
+        self.meters.set_gain_reduction(self.synth.take_gain_reduction());
+        self.levels.measure(out, channels, self.synth.take_clips(), &self.meters);
+        self.frames += frames as u64;
+
+        // Rises at once, so a single overloaded block shows, then eases off
+        let load = if budget > 0.0 { start.elapsed().as_secs_f32() / budget } else { 0.0 };
+        self.load = if load > self.load { load } else { self.load + (load - self.load) * LOAD_SMOOTHING };
+        self.meters.set_dsp_load(self.load);
+        // One dropout per callback, however it was noticed
+        if late || load > 1.0 { self.meters.add_xrun(); }
+    }
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Bitcrusher ----------
+/// Master bitcrusher settings: amplitude quantization plus sample-and-hold
+/// rate reduction, for lo-fi processing of the whole patch.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Bitcrusher {
+    pub on: bool,
+    pub bits: f32, // 1..16, fractional values step smoothly between depths
+    pub rate: f32, // Hz the output is resampled to, without filtering
+    pub mix: f32,  // 0..1, dry to wet
+}
+
+impl Default for Bitcrusher {
+    fn default() -> Self { Self { on: false, bits: 8.0, rate: 11025.0, mix: 1.0 } }
+}
+
+/// The running crusher: the held sample and progress towards the next one.
+pub(crate) struct Crusher {
+    held: [f32; 2],
+    phase: f32, // 0..1 until the next sample is taken
+    sr: f32,
+}
+
+impl Crusher {
+    pub fn new(sr: f32) -> Self { Self { held: [0.0; 2], phase: 1.0, sr } }
+
+    /// Crushes a stereo block in place.
+    pub fn process(&mut self, settings: &Bitcrusher, l: &mut [f32], r: &mut [f32]) {
+        if !settings.on { self.phase = 1.0; return; }
+        let levels = 2f32.powf(settings.bits.clamp(1.0, 16.0) - 1.0);
+        let inc = (settings.rate / self.sr).clamp(0.0, 1.0);
+        let mix = settings.mix.clamp(0.0, 1.0);
+        for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+            self.phase += inc;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+                self.held = [*a, *b].map(|x| (x * levels).round() / levels);
+            }
+            *a += (self.held[0] - *a) * mix;
+            *b += (self.held[1] - *b) * mix;
+        }
+    }
+}
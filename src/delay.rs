@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Delay ----------
+/// Longest delay time, in seconds; synced times beyond it are capped.
+pub const MAX_DELAY: f32 = 2.0;
+/// Time constant of the glide to a new delay time, in seconds.
+const TIME_SMOOTHING: f32 = 0.05;
+
+/// Stereo delay settings.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Delay {
+    pub on: bool,
+    pub time: f32,       // ms, when not synced
+    pub sync: bool,      // follow the tempo instead of `time`
+    pub beats: f32,      // delay in quarter notes when synced
+    pub feedback: f32,   // 0..0.95
+    pub ping_pong: bool, // echoes bounce between left and right
+    pub mix: f32,        // 0..1, dry to wet
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Self { on: false, time: 375.0, sync: true, beats: 0.75, feedback: 0.4, ping_pong: false, mix: 0.3 }
+    }
+}
+
+impl Delay {
+    /// Delay time in seconds at `bpm`.
+    pub fn seconds(&self, bpm: f32) -> f32 {
+        let t = if self.sync { self.beats * 60.0 / bpm.max(1.0) } else { self.time / 1000.0 };
+        t.clamp(0.001, MAX_DELAY)
+    }
+}
+
+/// The running delay: one line per channel, sized for `MAX_DELAY`.
+pub(crate) struct StereoDelay {
+    lines: [Vec<f32>; 2],
+    pos: usize,
+    delay: f32, // smoothed delay time in samples
+    was_on: bool,
+    sr: f32,
+}
+
+impl StereoDelay {
+    pub fn new(sr: f32) -> Self {
+        let len = (MAX_DELAY * sr) as usize + 2;
+        Self { lines: [vec![0.0; len], vec![0.0; len]], pos: 0, delay: 0.0, was_on: false, sr }
+    }
+
+    /// Sample `delay` samples back on line `ch`, linearly interpolated.
+    fn tap(&self, ch: usize, delay: f32) -> f32 {
+        let line = &self.lines[ch];
+        let len = line.len();
+        let back = delay.clamp(1.0, (len - 2) as f32);
+        let (whole, frac) = (back as usize, back.fract());
+        let a = line[(self.pos + len - whole) % len];
+        let b = line[(self.pos + len - whole - 1) % len];
+        a + (b - a) * frac
+    }
+
+    /// Adds the echoes to a stereo block in place. In ping-pong mode the
+    /// input is summed into the left line and each echo crosses over.
+    pub fn process(&mut self, settings: &Delay, bpm: f32, l: &mut [f32], r: &mut [f32]) {
+        let target = settings.seconds(bpm) * self.sr;
+        if !settings.on { self.was_on = false; return; }
+        if !self.was_on {
+            for line in &mut self.lines { line.fill(0.0); }
+            self.delay = target;
+            self.was_on = true;
+        }
+        let feedback = settings.feedback.clamp(0.0, 0.95);
+        let mix = settings.mix.clamp(0.0, 1.0);
+        let k = 1.0 - (-1.0 / (TIME_SMOOTHING * self.sr)).exp();
+        let len = self.lines[0].len();
+        for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+            self.delay += (target - self.delay) * k;
+            let wet = [self.tap(0, self.delay), self.tap(1, self.delay)];
+            let input = if settings.ping_pong { [(*a + *b) * 0.5, 0.0] } else { [*a, *b] };
+            let back = if settings.ping_pong { [wet[1], wet[0]] } else { wet };
+            for ch in 0..2 { self.lines[ch][self.pos] = input[ch] + back[ch] * feedback; }
+            self.pos = (self.pos + 1) % len;
+            *a = *a * (1.0 - mix) + wet[0] * mix;
+            *b = *b * (1.0 - mix) + wet[1] * mix;
+        }
+    }
+}
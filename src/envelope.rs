@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Envelope ----------
+/// ADSR with a curvature per timed stage: 0 is a straight ramp, positive
+/// values move fast first and settle slowly (RC-style), negative values the
+/// opposite.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    #[serde(default)]
+    pub attack_curve: f32,  // -1..1
+    #[serde(default)]
+    pub decay_curve: f32,   // -1..1
+    #[serde(default)]
+    pub release_curve: f32, // -1..1
+    #[serde(default)]
+    pub hard_retrigger: bool, // dip to zero before restarting instead of attacking from the current level
+    #[serde(skip)]
+    pub(crate) phase: f32,
+    #[serde(skip)]
+    pub(crate) level: f32,
+    #[serde(skip)]
+    pub(crate) active: bool,
+    #[serde(skip)]
+    from: f32,     // level when the stage began
+    #[serde(skip)]
+    progress: f32, // 0..1 through the stage
+}
+
+/// Seconds a hard retrigger takes to fall to zero before the attack.
+const DECLICK: f32 = 0.002;
+
+/// Maps linear stage progress `x` through a curve of the given curvature.
+fn shape(x: f32, curve: f32) -> f32 {
+    if curve.abs() < 1e-3 { return x; }
+    let k = curve * 6.0;
+    (1.0 - (-k * x).exp()) / (1.0 - (-k).exp())
+}
+
+impl Envelope {
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain,
+            release,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
+            hard_retrigger: false,
+            phase: 0.0,
+            level: 0.0,
+            active: false,
+            from: 0.0,
+            progress: 0.0,
+        }
+    }
+    fn enter(&mut self, phase: f32) { self.phase = phase; self.from = self.level; self.progress = 0.0; }
+    /// Attacks from wherever the level is, so retriggers don't click; a hard
+    /// retrigger first ramps to zero over `DECLICK`.
+    pub fn note_on(&mut self) {
+        if !self.active { self.level = 0.0; }
+        self.active = true;
+        self.enter(if self.hard_retrigger && self.level > 0.0 { 4.0 } else { 0.0 });
+    }
+    pub fn note_off(&mut self)  { self.enter(3.0); }          // release
+    pub fn advance(&mut self, dt: f32) {
+        if !self.active { return; }
+        let (time, target, curve) = match self.phase {
+            0.0 => (self.attack, 1.0, self.attack_curve),
+            1.0 => (self.decay, self.sustain, self.decay_curve),
+            3.0 => (self.release, 0.0, self.release_curve),
+            4.0 => (DECLICK, 0.0, 0.0),
+            _ => return, // sustain
+        };
+        self.progress = (self.progress + dt / time.max(1e-4)).min(1.0);
+        self.level = self.from + (target - self.from) * shape(self.progress, curve);
+        if self.progress < 1.0 { return; }
+        match self.phase {
+            0.0 => self.enter(1.0),
+            1.0 => self.enter(2.0),
+            4.0 => self.enter(0.0),
+            _ => self.active = false,
+        }
+    }
+
+    /// Copies the running state (not the settings) from `other`.
+    pub(crate) fn take_state(&mut self, other: &Envelope) {
+        self.phase = other.phase;
+        self.level = other.level;
+        self.active = other.active;
+        self.from = other.from;
+        self.progress = other.progress;
+    }
+
+    /// (time, level) points of a note held for the attack, decay and then
+    /// `hold` seconds before release, sampled every `dt` by running a copy of
+    /// this envelope through `advance`.
+    pub fn trace(&self, hold: f32, dt: f32) -> Vec<(f32, f32)> {
+        let mut env = *self;
+        let off_at = self.attack + self.decay + hold;
+        let mut t = 0.0;
+        let mut points = vec![(0.0, 0.0)];
+        env.note_on();
+        while env.active && t < off_at + self.release + 1.0 {
+            if t >= off_at && env.phase != 3.0 { env.note_off(); }
+            env.advance(dt);
+            t += dt;
+            points.push((t, env.level));
+        }
+        points
+    }
+}
+
+/// ----------  Rate/level envelope ----------
+pub const RL_STAGES: usize = 4;
+
+/// Which envelope drives an operator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EnvKind {
+    #[default]
+    Adsr,
+    RateLevel,
+}
+
+/// DX-style envelope: on key-on it starts from the last level and glides
+/// through stages 0..RL_STAGES-1, holding at the second-to-last level (or
+/// looping back to `loop_to`); key-off runs the final stage. Each stage's
+/// rate is given as the time it takes to reach its level. A final level
+/// above silence is then faded out over the release time again, so the
+/// voice can stop without a click.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct RateLevel {
+    pub times: [f32; RL_STAGES],  // seconds per stage
+    pub levels: [f32; RL_STAGES], // target level of each stage
+    pub loop_to: Option<usize>,   // stage to jump back to while held
+    #[serde(skip)]
+    stage: usize,
+    #[serde(skip)]
+    from: f32,    // level when the stage began
+    #[serde(skip)]
+    elapsed: f32, // seconds into the stage
+    #[serde(skip)]
+    pub(crate) level: f32,
+    #[serde(skip)]
+    pub(crate) active: bool,
+}
+
+impl Default for RateLevel {
+    fn default() -> Self {
+        Self {
+            times: [0.01, 0.3, 0.5, 0.3],
+            levels: [1.0, 0.7, 0.5, 0.0],
+            loop_to: None,
+            stage: 0,
+            from: 0.0,
+            elapsed: 0.0,
+            level: 0.0,
+            active: false,
+        }
+    }
+}
+
+impl RateLevel {
+    const SUSTAIN: usize = RL_STAGES - 2;
+    const RELEASE: usize = RL_STAGES - 1;
+    /// Past the release: fading from a nonzero final level to silence.
+    const TAIL: usize = RL_STAGES;
+    /// Level a release may stop at without a click.
+    const SILENT: f32 = 1e-4;
+
+    fn enter(&mut self, stage: usize) { self.stage = stage; self.from = self.level; self.elapsed = 0.0; }
+
+    pub fn note_on(&mut self) {
+        if !self.active { self.level = self.levels[Self::RELEASE]; }
+        self.active = true;
+        self.enter(0);
+    }
+
+    pub fn note_off(&mut self) { self.enter(Self::RELEASE); }
+
+    pub fn advance(&mut self, dt: f32) {
+        if !self.active { return; }
+        let time = self.times[self.stage.min(Self::RELEASE)].max(1e-4);
+        let target = if self.stage == Self::TAIL { 0.0 } else { self.levels[self.stage] };
+        // Holding at the sustain level
+        if self.stage == Self::SUSTAIN && self.elapsed >= time && self.loop_to.is_none() { return; }
+        self.elapsed += dt;
+        let x = (self.elapsed / time).min(1.0);
+        self.level = self.from + (target - self.from) * x;
+        if x < 1.0 { return; }
+        match self.stage {
+            Self::RELEASE if self.level.abs() > Self::SILENT => self.enter(Self::TAIL),
+            Self::RELEASE | Self::TAIL => self.active = false,
+            Self::SUSTAIN => if let Some(k) = self.loop_to.filter(|&k| k < Self::SUSTAIN) { self.enter(k) },
+            s => self.enter(s + 1),
+        }
+    }
+
+    pub(crate) fn take_state(&mut self, other: &RateLevel) {
+        self.stage = other.stage;
+        self.from = other.from;
+        self.elapsed = other.elapsed;
+        self.level = other.level;
+        self.active = other.active;
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+/// ----------  Parametric EQ ----------
+pub const EQ_BANDS: usize = 4;
+
+/// Shape of each band, by position: shelves at the ends, peaks between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BandKind {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+impl BandKind {
+    pub fn of(band: usize) -> Self {
+        match band {
+            0 => BandKind::LowShelf,
+            b if b == EQ_BANDS - 1 => BandKind::HighShelf,
+            _ => BandKind::Peak,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BandKind::LowShelf => "Low Shelf",
+            BandKind::Peak => "Peak",
+            BandKind::HighShelf => "High Shelf",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct EqBand {
+    pub freq: f32, // Hz, 20..20000
+    pub gain: f32, // dB, ±18
+    pub q: f32,    // 0.1..10; shelves read it as their slope
+}
+
+/// EQ settings; a band at 0 dB does nothing.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Eq {
+    pub on: bool,
+    pub bands: [EqBand; EQ_BANDS],
+}
+
+impl Default for Eq {
+    fn default() -> Self {
+        let band = |freq| EqBand { freq, gain: 0.0, q: 0.707 };
+        Self { on: false, bands: [band(100.0), band(500.0), band(2500.0), band(8000.0)] }
+    }
+}
+
+/// Normalized biquad coefficients: b0, b1, b2, a1, a2.
+type Coefs = [f32; 5];
+
+/// RBJ cookbook coefficients for `band` shaped as `kind` at `sr`.
+fn coefs(kind: BandKind, band: &EqBand, sr: f32) -> Coefs {
+    let a = 10f32.powf(band.gain.clamp(-18.0, 18.0) / 40.0);
+    let w = TAU * band.freq.clamp(20.0, 0.45 * sr) / sr;
+    let (sin, cos) = w.sin_cos();
+    let alpha = sin / (2.0 * band.q.clamp(0.1, 10.0));
+    let [b0, b1, b2, a0, a1, a2] = match kind {
+        BandKind::Peak => [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        BandKind::LowShelf | BandKind::HighShelf => {
+            let k = 2.0 * a.sqrt() * alpha;
+            let s = if kind == BandKind::LowShelf { 1.0 } else { -1.0 }; // high shelf mirrors the cosine terms
+            [a * ((a + 1.0) - s * (a - 1.0) * cos + k),
+             s * 2.0 * a * ((a - 1.0) - s * (a + 1.0) * cos),
+             a * ((a + 1.0) - s * (a - 1.0) * cos - k),
+             (a + 1.0) + s * (a - 1.0) * cos + k,
+             -s * 2.0 * ((a - 1.0) + s * (a + 1.0) * cos),
+             (a + 1.0) + s * (a - 1.0) * cos - k]
+        }
+    };
+    [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+}
+
+/// The running EQ: one transposed direct form II biquad per band and
+/// channel, its coefficients worked out again every block.
+pub(crate) struct Equalizer {
+    state: [[[f32; 2]; EQ_BANDS]; 2],
+    sr: f32,
+}
+
+impl Equalizer {
+    pub fn new(sr: f32) -> Self { Self { state: [[[0.0; 2]; EQ_BANDS]; 2], sr } }
+
+    /// Filters a stereo block in place.
+    pub fn process(&mut self, settings: &Eq, l: &mut [f32], r: &mut [f32]) {
+        if !settings.on { self.state = [[[0.0; 2]; EQ_BANDS]; 2]; return; }
+        for (i, band) in settings.bands.iter().enumerate() {
+            if band.gain.abs() < 0.01 { continue; }
+            let [b0, b1, b2, a1, a2] = coefs(BandKind::of(i), band, self.sr);
+            for (ch, buf) in [&mut *l, &mut *r].into_iter().enumerate() {
+                let z = &mut self.state[ch][i];
+                for x in buf.iter_mut() {
+                    let y = b0 * *x + z[0];
+                    z[0] = b1 * *x - a1 * y + z[1];
+                    z[1] = b2 * *x - a2 * y;
+                    *x = y;
+                }
+            }
+        }
+    }
+}
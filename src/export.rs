@@ -0,0 +1,173 @@
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufWriter;
+#[cfg(not(target_arch = "wasm32"))]
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoder, VorbisEncoderBuilder};
+
+/// ----------  Audio file formats ----------
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum AudioFormat {
+    #[default]
+    Wav,
+    Flac,
+    Ogg, // Vorbis
+}
+
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 3] = [AudioFormat::Wav, AudioFormat::Flac, AudioFormat::Ogg];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "WAV",
+            AudioFormat::Flac => "FLAC",
+            AudioFormat::Ogg => "OGG",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Ogg => "ogg",
+        }
+    }
+
+    /// The format with file extension `ext`, in any case.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        AudioFormat::ALL.into_iter().find(|f| f.extension().eq_ignore_ascii_case(ext))
+    }
+
+    /// Bit depths on offer; 32 is float WAV. Ogg Vorbis has none.
+    pub fn bit_depths(self) -> &'static [u16] {
+        match self {
+            AudioFormat::Wav => &[16, 24, 32],
+            AudioFormat::Flac => &[16, 24],
+            AudioFormat::Ogg => &[],
+        }
+    }
+}
+
+/// How rendered or recorded audio is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportOptions {
+    pub format: AudioFormat,
+    pub bits: u16,    // WAV and FLAC
+    pub quality: f32, // Ogg Vorbis, -0.2..1 (about 80 kbit/s at 0.5)
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self { Self { format: AudioFormat::Wav, bits: 16, quality: 0.5 } }
+}
+
+impl ExportOptions {
+    /// The format `path`'s extension names (WAV if none does), keeping the
+    /// other settings.
+    pub fn for_path(self, path: &Path) -> Self {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        Self { format: AudioFormat::from_extension(ext).unwrap_or_default(), ..self }
+    }
+
+    /// A bit depth the format supports, the nearest one up if need be.
+    fn bits(&self) -> u16 {
+        let depths = self.format.bit_depths();
+        depths.iter().copied().find(|&b| b >= self.bits).or(depths.last().copied()).unwrap_or(16)
+    }
+}
+
+/// ----------  Writer ----------
+enum Sink {
+    Wav(WavWriter<BufWriter<File>>, u16),
+    /// FLAC is encoded in one go when finished, from samples kept in memory.
+    Flac { path: PathBuf, samples: Vec<i32>, bits: u16, sr: u32 },
+    #[cfg(not(target_arch = "wasm32"))]
+    Ogg(Box<VorbisEncoder<BufWriter<File>>>, [Vec<f32>; 2]),
+}
+
+/// Writes interleaved stereo samples to a file in any `AudioFormat`.
+pub struct AudioWriter {
+    sink: Sink,
+}
+
+/// `s` (-1..1) as a `bits`-bit integer sample.
+pub(crate) fn to_int(s: f32, bits: u16) -> i32 {
+    let max = ((1i64 << (bits - 1)) - 1) as f32;
+    (s.clamp(-1.0, 1.0) * max) as i32
+}
+
+impl AudioWriter {
+    pub fn create(path: &Path, sr: u32, options: &ExportOptions) -> Result<Self, Box<dyn Error>> {
+        let bits = options.bits();
+        let sink = match options.format {
+            AudioFormat::Wav => {
+                let sample_format = if bits == 32 { SampleFormat::Float } else { SampleFormat::Int };
+                let spec = WavSpec { channels: 2, sample_rate: sr, bits_per_sample: bits, sample_format };
+                Sink::Wav(WavWriter::create(path, spec)?, bits)
+            }
+            AudioFormat::Flac => {
+                File::create(path)?; // fail now rather than after the take
+                Sink::Flac { path: path.to_path_buf(), samples: Vec::new(), bits, sr }
+            }
+            // libvorbis is C, which the browser build leaves out
+            #[cfg(target_arch = "wasm32")]
+            AudioFormat::Ogg => return Err("Ogg Vorbis export is not available in the browser".into()),
+            #[cfg(not(target_arch = "wasm32"))]
+            AudioFormat::Ogg => {
+                let file = BufWriter::new(File::create(path)?);
+                let (sr, channels) = (NonZeroU32::new(sr).ok_or("Zero sample rate")?, NonZeroU8::new(2).unwrap());
+                let mut builder = VorbisEncoderBuilder::new(sr, channels, file)?;
+                builder.bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr {
+                    target_quality: options.quality.clamp(-0.2, 1.0),
+                });
+                Sink::Ogg(Box::new(builder.build()?), [Vec::new(), Vec::new()])
+            }
+        };
+        Ok(Self { sink })
+    }
+
+    /// Appends interleaved left/right `samples`.
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), Box<dyn Error>> {
+        if samples.is_empty() { return Ok(()); }
+        match &mut self.sink {
+            Sink::Wav(wav, 32) => for &s in samples { wav.write_sample(s)?; },
+            Sink::Wav(wav, bits) => for &s in samples { wav.write_sample(to_int(s, *bits))?; },
+            Sink::Flac { samples: kept, bits, .. } => kept.extend(samples.iter().map(|&s| to_int(s, *bits))),
+            #[cfg(not(target_arch = "wasm32"))]
+            Sink::Ogg(encoder, [l, r]) => {
+                l.clear();
+                r.clear();
+                for frame in samples.chunks_exact(2) {
+                    l.push(frame[0]);
+                    r.push(frame[1]);
+                }
+                encoder.encode_audio_block([&l[..], &r[..]])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the file.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self.sink {
+            Sink::Wav(wav, _) => wav.finalize()?,
+            Sink::Flac { path, samples, bits, sr } => {
+                let config = flacenc::config::Encoder::default().into_verified().map_err(|(_, e)| e.to_string())?;
+                let source = flacenc::source::MemSource::from_samples(&samples, 2, bits as usize, sr as usize);
+                let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+                    .map_err(|e| e.to_string())?;
+                let mut bytes = flacenc::bitsink::ByteSink::new();
+                stream.write(&mut bytes).map_err(|e| e.to_string())?;
+                fs::write(path, bytes.as_slice())?;
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Sink::Ogg(encoder, _) => { encoder.finish()?; }
+        }
+        Ok(())
+    }
+}
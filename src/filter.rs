@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::Envelope;
+
+/// ----------  Filter ----------
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum FilterMode {
+    #[default]
+    LowPass,
+    HighPass,
+    BandPass,
+    Formant, // three vowel formants, morphing A → E → I → O → U
+}
+
+impl FilterMode {
+    pub const ALL: [FilterMode; 4] = [FilterMode::LowPass, FilterMode::HighPass, FilterMode::BandPass, FilterMode::Formant];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterMode::LowPass => "Low Pass",
+            FilterMode::HighPass => "High Pass",
+            FilterMode::BandPass => "Band Pass",
+            FilterMode::Formant => "Formant",
+        }
+    }
+}
+
+/// Per-voice filter settings, applied to each voice after the operators.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Filter {
+    pub on: bool,
+    pub mode: FilterMode,
+    pub cutoff: f32,     // Hz; unused by Formant, which the envelope and key tracking shift instead
+    pub resonance: f32,  // 0..1, self-oscillation near 1
+    pub drive: f32,      // 0..1, input saturation
+    pub envelope: Envelope,
+    pub env_amount: f32, // octaves the envelope moves the cutoff at full level, ±8
+    #[serde(default)]
+    pub key_track: f32,  // 0..1, how closely the cutoff follows the note, around middle C
+    #[serde(default)]
+    pub vowel: f32,      // Formant: 0..4, A E I O U
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self { on: false, mode: FilterMode::LowPass, cutoff: 2000.0, resonance: 0.2, drive: 0.0,
+               envelope: Envelope::new(0.01, 0.3, 0.0, 0.3), env_amount: 0.0, key_track: 0.0, vowel: 0.0 }
+    }
+}
+
+/// Input gain at full drive.
+const MAX_DRIVE: f32 = 10.0;
+/// Note whose cutoff key tracking leaves unchanged (middle C).
+const KEY_TRACK_CENTER: f32 = 60.0;
+/// Q of the formant bands with no resonance and with full resonance.
+const FORMANT_Q: f32 = 4.0;
+const MAX_FORMANT_Q: f32 = 20.0;
+
+/// Formant frequencies (Hz) and gains of the vowels A, E, I, O and U, after
+/// the usual tables for a male voice.
+const FORMANTS: usize = 3;
+const VOWELS: [([f32; FORMANTS], [f32; FORMANTS]); 5] = [
+    ([800.0, 1150.0, 2900.0], [1.0, 0.501, 0.025]),
+    ([350.0, 2000.0, 2800.0], [1.0, 0.1, 0.178]),
+    ([270.0, 2140.0, 2950.0], [1.0, 0.251, 0.050]),
+    ([450.0, 800.0, 2830.0], [1.0, 0.282, 0.079]),
+    ([325.0, 700.0, 2530.0], [1.0, 0.158, 0.018]),
+];
+
+/// Formant frequencies and gains at `vowel` (0..4), interpolated between
+/// the neighbouring vowels.
+fn vowel_formants(vowel: f32) -> ([f32; FORMANTS], [f32; FORMANTS]) {
+    let v = vowel.clamp(0.0, (VOWELS.len() - 1) as f32);
+    let i = (v as usize).min(VOWELS.len() - 2);
+    let t = v - i as f32;
+    let (a, b) = (VOWELS[i], VOWELS[i + 1]);
+    (std::array::from_fn(|f| a.0[f] + (b.0[f] - a.0[f]) * t), std::array::from_fn(|f| a.1[f] + (b.1[f] - a.1[f]) * t))
+}
+
+/// Simper's trapezoidal state-variable filter, which stays stable under
+/// fast cutoff sweeps: coefficients for one corner frequency and damping.
+#[derive(Clone, Copy)]
+struct Svf {
+    k: f32, // damping, 2 (none) down towards 0 (self-oscillation)
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl Svf {
+    fn new(cutoff: f32, k: f32, dt: f32) -> Self {
+        let g = (PI * cutoff.clamp(20.0, 0.45 / dt) * dt).tan();
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        Self { k, a1, a2: g * a1, a3: g * g * a1 }
+    }
+
+    /// One sample through the integrators `ic`; returns (band, low).
+    fn tick(&self, ic: &mut [f32; 2], v0: f32) -> (f32, f32) {
+        let v3 = v0 - ic[1];
+        let v1 = self.a1 * ic[0] + self.a2 * v3;
+        let v2 = ic[1] + self.a2 * ic[0] + self.a3 * v3;
+        ic[0] = 2.0 * v1 - ic[0];
+        ic[1] = 2.0 * v2 - ic[1];
+        (v1, v2)
+    }
+}
+
+/// One voice's filter: its envelope and stereo filter states.
+#[derive(Clone, Copy)]
+pub(crate) struct VoiceFilter {
+    env: Envelope,
+    ic: [[f32; 2]; 2],                  // per channel: the SVF's integrators
+    formant: [[[f32; 2]; FORMANTS]; 2], // per channel and formant, the same
+}
+
+impl VoiceFilter {
+    pub fn new(settings: &Filter) -> Self {
+        Self { env: settings.envelope, ic: [[0.0; 2]; 2], formant: [[[0.0; 2]; FORMANTS]; 2] }
+    }
+
+    /// Starts the envelope; the filter itself starts from silence if the
+    /// voice was idle.
+    pub fn note_on(&mut self) {
+        if !self.env.active {
+            self.ic = [[0.0; 2]; 2];
+            self.formant = [[[0.0; 2]; FORMANTS]; 2];
+        }
+        self.env.note_on();
+    }
+
+    pub fn note_off(&mut self) { self.env.note_off(); }
+
+    /// Takes over the patch's envelope settings, keeping its running state.
+    pub fn follow(&mut self, settings: &Filter) {
+        let mut env = settings.envelope;
+        env.take_state(&self.env);
+        self.env = env;
+    }
+
+    /// Filters one stereo sample, `dt` seconds long, of a voice sounding
+    /// `pitch` (MIDI note number, fractional while gliding), if any.
+    pub fn process(&mut self, settings: &Filter, x: [f32; 2], dt: f32, pitch: Option<f32>) -> [f32; 2] {
+        self.env.advance(dt);
+        let key = pitch.map_or(0.0, |p| (p - KEY_TRACK_CENTER) / 12.0 * settings.key_track.clamp(0.0, 1.0));
+        let shift = 2f32.powf(settings.env_amount.clamp(-8.0, 8.0) * self.env.level + key);
+        let resonance = settings.resonance.clamp(0.0, 1.0);
+        let drive = 1.0 + (MAX_DRIVE - 1.0) * settings.drive.clamp(0.0, 1.0);
+        let input = x.map(|s| if settings.drive > 0.0 { (s * drive).tanh() } else { s });
+
+        if settings.mode == FilterMode::Formant {
+            // Narrow bands, each scaled to unity gain at its peak
+            let (freqs, gains) = vowel_formants(settings.vowel);
+            let k = 1.0 / (FORMANT_Q + (MAX_FORMANT_Q - FORMANT_Q) * resonance);
+            let svfs = freqs.map(|f| Svf::new(f * shift, k, dt));
+            return std::array::from_fn(|ch| {
+                (0..FORMANTS).map(|f| gains[f] * k * svfs[f].tick(&mut self.formant[ch][f], input[ch]).0).sum()
+            });
+        }
+        let svf = Svf::new(settings.cutoff * shift, 2.0 - 1.98 * resonance, dt);
+        std::array::from_fn(|ch| {
+            let (band, low) = svf.tick(&mut self.ic[ch], input[ch]);
+            match settings.mode {
+                FilterMode::LowPass => low,
+                FilterMode::HighPass => input[ch] - svf.k * band - low,
+                _ => band,
+            }
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Effects chain ----------
+/// The effects between the voices and the master output stage.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    Eq,
+    ModFx,
+    Delay,
+    Reverb,
+    Compressor,
+    Bitcrusher,
+    Widener,
+}
+
+pub const EFFECTS: usize = 7;
+
+impl Effect {
+    pub const ALL: [Effect; EFFECTS] = [
+        Effect::Eq, Effect::ModFx, Effect::Delay, Effect::Reverb, Effect::Compressor, Effect::Bitcrusher, Effect::Widener,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Eq => "EQ",
+            Effect::ModFx => "Modulation FX",
+            Effect::Delay => "Delay",
+            Effect::Reverb => "Reverb",
+            Effect::Compressor => "Compressor",
+            Effect::Bitcrusher => "Bitcrusher",
+            Effect::Widener => "Stereo Width",
+        }
+    }
+}
+
+/// The order effects run in, each exactly once. Stored as a list; one
+/// read from a preset drops repeats and appends whatever it leaves out.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<Effect>", into = "Vec<Effect>")]
+pub struct FxChain([Effect; EFFECTS]);
+
+impl Default for FxChain {
+    fn default() -> Self { Self(Effect::ALL) }
+}
+
+impl From<Vec<Effect>> for FxChain {
+    fn from(listed: Vec<Effect>) -> Self {
+        let mut order = Vec::with_capacity(EFFECTS);
+        for e in listed.into_iter().chain(Effect::ALL) {
+            if !order.contains(&e) { order.push(e); }
+        }
+        Self(std::array::from_fn(|i| order[i]))
+    }
+}
+
+impl From<FxChain> for Vec<Effect> {
+    fn from(chain: FxChain) -> Self { chain.0.to_vec() }
+}
+
+impl FxChain {
+    pub fn order(&self) -> [Effect; EFFECTS] { self.0 }
+
+    /// Swaps the effects at positions `a` and `b`.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if a < EFFECTS && b < EFFECTS { self.0.swap(a, b); }
+    }
+}
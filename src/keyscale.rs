@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Level scaling ----------
+/// Octaves from the breakpoint at which a side reaches its full depth.
+const SCALE_SPAN: f32 = 4.0;
+
+/// How level scaling grows with distance from the breakpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ScaleCurve {
+    #[default]
+    Linear,
+    Exp, // gentle near the breakpoint, steep towards the ends
+}
+
+impl ScaleCurve {
+    pub const ALL: [ScaleCurve; 2] = [ScaleCurve::Linear, ScaleCurve::Exp];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleCurve::Linear => "Lin",
+            ScaleCurve::Exp => "Exp",
+        }
+    }
+
+    fn eval(self, x: f32) -> f32 {
+        match self {
+            ScaleCurve::Linear => x,
+            ScaleCurve::Exp => x * x,
+        }
+    }
+}
+
+/// DX-style keyboard level scaling: an operator gets louder or quieter as
+/// notes move away from `breakpoint`, with its own depth and curve per side.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct LevelScaling {
+    pub breakpoint: u8,     // MIDI note
+    pub left_depth: f32,    // -1..1, level change SCALE_SPAN octaves below
+    pub left_curve: ScaleCurve,
+    pub right_depth: f32,   // -1..1, level change SCALE_SPAN octaves above
+    pub right_curve: ScaleCurve,
+}
+
+impl Default for LevelScaling {
+    fn default() -> Self {
+        Self { breakpoint: 60, left_depth: 0.0, left_curve: ScaleCurve::Linear,
+               right_depth: 0.0, right_curve: ScaleCurve::Linear }
+    }
+}
+
+impl LevelScaling {
+    /// Level multiplier for `note`; the UI gate (`None`) is left alone.
+    pub fn gain(&self, note: Option<u8>) -> f32 {
+        let Some(note) = note else { return 1.0 };
+        let octaves = (note as f32 - self.breakpoint as f32) / 12.0;
+        let (depth, curve) = if octaves < 0.0 { (self.left_depth, self.left_curve) }
+                             else { (self.right_depth, self.right_curve) };
+        let x = (octaves.abs() / SCALE_SPAN).min(1.0);
+        (1.0 + depth * curve.eval(x)).max(0.0)
+    }
+}
+
+/// ----------  Rate scaling ----------
+/// Note around which rate scaling leaves envelope times alone (C4).
+const RATE_CENTRE: f32 = 60.0;
+
+/// How much faster envelopes run for `note`: with `amount` 1 every octave
+/// above C4 doubles the rates and every octave below halves them.
+pub(crate) fn rate_factor(amount: f32, note: Option<u8>) -> f32 {
+    let Some(note) = note else { return 1.0 };
+    2.0_f32.powf(amount * (note as f32 - RATE_CENTRE) / 12.0)
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+use crate::modmatrix::{ModDest, OpMod};
+use crate::{Waveform, MAX_OPS};
+
+pub const LFO_COUNT: usize = 2;
+
+/// ----------  LFO ----------
+/// LFO settings; the running phase lives in the synth so publishing a new
+/// patch doesn't restart the sweep. Besides its own `target`, every LFO is
+/// also available as a mod matrix source.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Lfo {
+    pub rate: f32,    // Hz
+    pub depth: f32,   // 0..1, scaled per target
+    pub shape: Waveform,
+    pub target: ModDest,
+    pub ops: Vec<bool>, // operators the LFO reaches, missing ones are not
+    #[serde(default)]
+    pub pressure: f32,  // depth added at full aftertouch
+    #[serde(default)]
+    pub sync: bool,     // follow the tempo instead of `rate`
+    #[serde(default = "default_beats")]
+    pub beats: f32,     // cycle length in quarter notes when synced
+}
+
+fn default_beats() -> f32 { 1.0 }
+
+impl Lfo {
+    /// Cycle length in quarter notes when synced, at most 64 cycles a beat.
+    pub(crate) fn cycle_beats(&self) -> f32 { self.beats.max(1.0 / 64.0) }
+}
+
+impl Default for Lfo {
+    fn default() -> Self {
+        Self { rate: 5.0, depth: 0.0, shape: Waveform::Sine, target: ModDest::Freq, ops: vec![true; 4], pressure: 0.0,
+               sync: false, beats: default_beats() }
+    }
+}
+
+/// Samples every LFO (bipolar, before depth) at its current phase, then
+/// advances the phases by `dt`; synced LFOs run at `bpm`.
+pub(crate) fn advance(lfos: &[Lfo; LFO_COUNT], phases: &mut [f32; LFO_COUNT], dt: f32, bpm: f32) -> [f32; LFO_COUNT] {
+    let mut values = [0.0; LFO_COUNT];
+    for ((lfo, phase), v) in lfos.iter().zip(phases.iter_mut()).zip(values.iter_mut()) {
+        *v = lfo.shape.eval(*phase * TAU, 0.0);
+        let rate = if lfo.sync { bpm / 60.0 / lfo.cycle_beats() } else { lfo.rate };
+        *phase = (*phase + rate * dt).fract();
+    }
+    values
+}
+
+/// What the LFOs' own targets do to each operator, with `aftertouch`
+/// (0..1) deepening each by its `pressure` amount.
+pub(crate) fn targets(lfos: &[Lfo; LFO_COUNT], values: &[f32; LFO_COUNT], aftertouch: f32) -> [OpMod; MAX_OPS] {
+    let mut mods = [OpMod::default(); MAX_OPS];
+    for (lfo, v) in lfos.iter().zip(values) {
+        for (m, _) in mods.iter_mut().zip(&lfo.ops).filter(|(_, &on)| on) {
+            m.push(lfo.target, v * (lfo.depth + lfo.pressure * aftertouch));
+        }
+    }
+    mods
+}
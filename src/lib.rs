@@ -0,0 +1,72 @@
+//! FM synthesis engine: envelopes, operators and the polyphonic `FMSynth`.
+//! The egui/cpal front end in `main.rs` is just one user of this crate;
+//! `FMSynth::render_block` is all a host needs to pull audio out of it, and
+//! `control::channel` hands it to a realtime thread without locks.
+
+mod arp;
+mod compressor;
+mod crusher;
+mod delay;
+mod envelope;
+mod eq;
+mod filter;
+mod fx;
+mod keyscale;
+mod lfo;
+mod macros;
+mod master;
+mod modfx;
+mod modmatrix;
+mod noise;
+mod operator;
+mod oversample;
+mod reverb;
+mod seq;
+mod sub;
+mod synth;
+mod tuning;
+mod waveform;
+mod widener;
+
+pub mod control;
+pub mod export;
+#[cfg(feature = "link")]
+pub mod link;
+pub mod midi;
+pub mod midifile;
+pub mod osc;
+pub mod monitor;
+pub mod morph;
+pub mod params;
+pub mod preset;
+pub mod random;
+pub mod record;
+pub mod render;
+pub mod script;
+pub mod sysex;
+
+pub use arp::{Arp, ArpMode};
+pub use compressor::Compressor;
+pub use control::Event;
+pub use crusher::Bitcrusher;
+pub use delay::{Delay, MAX_DELAY};
+pub use envelope::{EnvKind, Envelope, RateLevel, RL_STAGES};
+pub use eq::{BandKind, Eq, EqBand, EQ_BANDS};
+pub use filter::{Filter, FilterMode};
+pub use fx::{Effect, FxChain, EFFECTS};
+pub use keyscale::{LevelScaling, ScaleCurve};
+pub use lfo::{Lfo, LFO_COUNT};
+pub use macros::{Macro, MacroTarget, MACROS};
+pub use master::{ClipCurve, Master};
+pub use modfx::{ModFx, ModFxKind};
+pub use modmatrix::{ModDest, ModSlot, ModSource, MOD_SLOTS};
+pub use noise::NoiseKind;
+pub use operator::{coarse_ratio, ModMode, Operator, COARSE_RATIOS};
+pub use preset::{Preset, PresetInfo};
+pub use reverb::Reverb;
+pub use seq::{Euclid, Sequence, Step, SEQ_STEPS};
+pub use sub::SubOsc;
+pub use synth::{midi_to_freq, FMSynth, GlideMode, NotePriority, Patch, Routing, MAX_OPS, MAX_VOICES};
+pub use tuning::{KeyMap, Scale, Tuning};
+pub use waveform::{Waveform, Wavetable, MAX_TABLE_LEN};
+pub use widener::{Widener, MAX_HAAS};
@@ -0,0 +1,41 @@
+use rusty_link::{AblLink, SessionState};
+use std::sync::Arc;
+
+/// ----------  Ableton Link ----------
+/// Beats Link lines phases up over: one 4/4 bar.
+const QUANTUM: f64 = 4.0;
+
+/// A Link session, shared between the UI (switching it on, counting peers)
+/// and the audio thread, which takes tempo and beat from it every block.
+#[derive(Clone)]
+pub struct Link(Arc<AblLink>);
+
+impl Link {
+    /// Joins no session until enabled.
+    pub fn new(bpm: f32) -> Self { Self(Arc::new(AblLink::new(bpm as f64))) }
+
+    pub fn enable(&self, on: bool) { self.0.enable(on); }
+
+    pub fn is_enabled(&self) -> bool { self.0.is_enabled() }
+
+    pub fn peers(&self) -> u64 { self.0.num_peers() }
+}
+
+/// The audio thread's view: reads the session without allocating.
+pub(crate) struct LinkClock {
+    link: Link,
+    state: SessionState,
+}
+
+impl LinkClock {
+    pub fn new(link: Link) -> Self { Self { link, state: SessionState::new() } }
+
+    /// Session tempo and beat right now, or `None` while Link is off.
+    pub fn capture(&mut self) -> Option<(f32, f64)> {
+        let link = &self.link.0;
+        if !link.is_enabled() { return None; }
+        link.capture_audio_session_state(&mut self.state);
+        let now = link.clock_micros();
+        Some((self.state.tempo() as f32, self.state.beat_at_time(now, QUANTUM)))
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use crate::params::Param;
+use crate::Patch;
+
+pub const MACROS: usize = 4;
+
+/// ----------  Macro controls ----------
+/// One parameter a macro moves, and how.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MacroTarget {
+    pub param: Param,
+    pub min: f32,   // value with the macro at 0, in the parameter's units
+    pub max: f32,   // and at 1
+    pub curve: f32, // -1..1; 0 is linear, above it starts slow, below it starts fast
+}
+
+impl MacroTarget {
+    /// Spans the whole of `param`'s range.
+    pub fn new(param: Param) -> Self {
+        let range = param.range();
+        Self { param, min: *range.start(), max: *range.end(), curve: 0.0 }
+    }
+
+    /// The target's value with the macro at `amount` (0..1).
+    pub fn value(&self, amount: f32) -> f32 {
+        let shaped = amount.clamp(0.0, 1.0).powf(4f32.powf(self.curve.clamp(-1.0, 1.0)));
+        self.min + (self.max - self.min) * shaped
+    }
+}
+
+/// A knob that sets several parameters at once, saved with the patch.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String, // empty shows as "Macro n"
+    pub value: f32,   // 0..1
+    pub targets: Vec<MacroTarget>,
+}
+
+/// Sets macro `i` to `value` and moves its targets to match. Macros never
+/// drive other macros, so none can end up setting itself.
+pub fn apply(patch: &mut Patch, i: usize, value: f32) {
+    let Some(m) = patch.macros.get_mut(i) else { return };
+    m.value = value.clamp(0.0, 1.0);
+    let (value, targets) = (m.value, m.targets.clone());
+    for target in targets.iter().filter(|t| !matches!(t.param, Param::Macro(_))) {
+        target.param.set_value(patch, target.value(value));
+    }
+}
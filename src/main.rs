@@ -1,261 +1,2643 @@
-
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crossbeam_channel::Sender;
 use egui::Slider;
 use eframe::egui;
-use std::f32::consts::PI;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
 use std::time::Duration;
+use web_time::Instant;
+
+use fm_synth::control::{self, Controller, Engine};
+use fm_synth::midi::{CcMap, ReceiveChannel};
+use fm_synth::midifile::{self, Playback, Song};
+use fm_synth::export::{AudioFormat, ExportOptions};
+use fm_synth::random::{self, Rng};
+use fm_synth::record;
+use fm_synth::script::Script;
+use fm_synth::sysex;
+use fm_synth::monitor::Meters;
+use fm_synth::morph;
+use fm_synth::params::{LfoParam, OpParam, Param};
+use fm_synth::preset;
+use fm_synth::{
+    midi, render, ArpMode, BandKind, ClipCurve, coarse_ratio, EnvKind, Envelope, Event, FilterMode, FMSynth, GlideMode, LevelScaling, MacroTarget, ModDest, ModFxKind, ModMode, ModSource, NoiseKind, NotePriority, Operator,
+    KeyMap, Patch, Preset, PresetInfo, RateLevel, Scale, ScaleCurve, Sequence, Tuning, Waveform, Wavetable, EFFECTS, MACROS, MAX_HAAS, MAX_OPS, MAX_VOICES, RL_STAGES, COARSE_RATIOS,
+};
 
-/// ----------  Envelopes & Operators ----------
-#[derive(Clone, Copy)]
-struct Envelope {
-    attack: f32,
-    decay: f32,
-    sustain: f32,
-    release: f32,
-    phase: f32,
-    level: f32,
-    active: bool,
+/// ----------  Computer keyboard ----------
+/// Two-row piano layout starting at C: the home row plays white keys, the
+/// row above the black keys in between. Z/X shift the octave.
+const PIANO_KEYS: [(egui::Key, u8); 17] = [
+    (egui::Key::A, 0), (egui::Key::W, 1), (egui::Key::S, 2), (egui::Key::E, 3),
+    (egui::Key::D, 4), (egui::Key::F, 5), (egui::Key::T, 6), (egui::Key::G, 7),
+    (egui::Key::Y, 8), (egui::Key::H, 9), (egui::Key::U, 10), (egui::Key::J, 11),
+    (egui::Key::K, 12), (egui::Key::O, 13), (egui::Key::L, 14), (egui::Key::P, 15),
+    (egui::Key::Semicolon, 16),
+];
+
+/// ----------  MIDI learn ----------
+/// Controller bindings, plus the parameter waiting for a controller if any.
+/// The bindings live in a mapping file that is read at startup and rewritten
+/// whenever they change.
+struct Learn {
+    map: CcMap,
+    pending: Option<Param>,
+    path: PathBuf,
+    saved: CcMap, // what `path` holds
 }
 
-impl Envelope {
-    fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
-        Self {
-            attack,
-            decay,
-            sustain,
-            release,
-            phase: 0.0,
-            level: 0.0,
-            active: false,
+impl Learn {
+    fn open(path: PathBuf) -> Self {
+        let map = if path.exists() {
+            CcMap::load(&path).unwrap_or_else(|e| {
+                eprintln!("Loading MIDI map failed: {}", e);
+                CcMap::default()
+            })
+        } else {
+            CcMap::default()
+        };
+        Self { saved: map.clone(), map, pending: None, path }
+    }
+
+    /// Writes the bindings back to the mapping file if they changed.
+    fn persist(&mut self) {
+        if self.map == self.saved { return; }
+        if let Err(e) = self.map.save(&self.path) { eprintln!("Saving MIDI map failed: {}", e); }
+        self.saved = self.map.clone();
+    }
+
+    /// Gives a slider a right-click menu for binding it to the next incoming
+    /// CC, and highlights it while it waits.
+    fn attach(&mut self, response: egui::Response, param: Param) -> egui::Response {
+        response.context_menu(|ui| {
+            if ui.button("MIDI learn").clicked() { self.pending = Some(param); ui.close_menu(); }
+            if let Some(cc) = self.map.cc_for(param) {
+                if ui.button(format!("Forget CC {}", cc)).clicked() { self.map.unbind(param); ui.close_menu(); }
+            }
+        });
+        if self.pending == Some(param) { response.highlight().on_hover_text("Move a controller…") } else { response }
+    }
+
+    /// Binds or applies the controller changes received since the last
+    /// frame, and takes parameters and switches set over OSC.
+    fn handle(&mut self, patch: &mut Patch, ctrl: &Controller) {
+        for event in ctrl.cc_events() {
+            match event {
+                Event::ControlChange { cc, value, .. } => {
+                    if let Some(param) = self.pending.take() { self.map.bind(cc, param); }
+                    self.map.apply(patch, cc, value);
+                }
+                Event::SetParam(param, value) => param.set_value(patch, value),
+                Event::SetSwitch(switch, value) => switch.set(patch, value),
+                _ => {}
+            }
         }
     }
-    fn note_on(&mut self)   { self.phase = 0.0; self.level = 0.0; self.active = true; }
-    fn note_off(&mut self)  { self.phase = 3.0; }          // release
-    fn advance(&mut self, dt: f32) {
-        if !self.active { return; }
-        match self.phase {
-            0.0 => {
-                self.level += dt / self.attack;
-                if self.level >= 1.0 { self.level = 1.0; self.phase = 1.0; }
+
+    fn editor(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("File: {}", self.path.display()));
+            if ui.button("Import").clicked() {
+                if let Some(path) = pick_file("MIDI map", &["json"]) {
+                    match CcMap::load(&path) {
+                        Ok(map) => self.map = map,
+                        Err(e) => eprintln!("Loading MIDI map failed: {}", e),
+                    }
+                }
             }
-            1.0 => {
-                self.level -= dt * (1.0 - self.sustain) / self.decay;
-                if self.level <= self.sustain { self.level = self.sustain; self.phase = 2.0; }
+            if ui.button("Export").clicked() {
+                if let Some(path) = save_file("MIDI map", &["json"]) {
+                    if let Err(e) = self.map.save(&path) { eprintln!("Saving MIDI map failed: {}", e); }
+                }
             }
-            2.0 => {}
-            3.0 => {
-                self.level -= dt * self.sustain / self.release;
-                if self.level <= 0.0 { self.level = 0.0; self.active = false; }
+        });
+        if self.map.bindings.is_empty() { ui.label("Right-click a slider and choose MIDI learn."); }
+        let mut remove = None;
+        egui::Grid::new("midi_map").show(ui, |ui| {
+            for (i, b) in self.map.bindings.iter_mut().enumerate() {
+                ui.label(b.param.name());
+                ui.add(egui::DragValue::new(&mut b.cc).clamp_range(0..=127).prefix("CC "));
+                if ui.button("Remove").clicked() { remove = Some(i); }
+                ui.end_row();
             }
-            _ => {}
+        });
+        if let Some(i) = remove { self.map.bindings.remove(i); }
+        if let Some(param) = self.pending {
+            ui.horizontal(|ui| {
+                ui.label(format!("Learning {}…", param.name()));
+                if ui.button("Cancel").clicked() { self.pending = None; }
+            });
         }
     }
 }
 
-#[derive(Clone)]
-struct Operator {
-    freq: f32,
-    phase: f32,
-    amp: f32,
-    envelope: Envelope,
-    ratio: f32,       // modulation ratio
-    feedback: f32,    // self‑feedback [0..1]
-    sync: bool,       // hard‑sync
-    bit_depth: u8,    // 8–16 for bit‑crushing
+/// ----------  MIDI input ----------
+/// How often the port list is rescanned for devices coming and going.
+const RESCAN: Duration = Duration::from_secs(1);
+
+/// The input port in use. A port that disappears is dropped and reopened as
+/// soon as it shows up again.
+struct MidiIn {
+    conn: Option<midi::Connection>,
+    virtual_port: Option<midi::Connection>,
+    wanted: Option<String>, // (part of) the name of the port to stay on
+    lost: bool,             // `wanted` was unplugged while in use
+    ports: Vec<String>,
+    scanned: Instant,
+    events: Sender<Event>,
+    controls: Sender<Event>,
+    receive: ReceiveChannel,
 }
 
-impl Operator {
-    fn new(freq: f32, amp: f32, env: Envelope,
-           ratio: f32, feedback: f32, sync: bool, bit_depth: u8) -> Self {
-        Self { freq, phase: 0.0, amp, envelope: env,
-               ratio, feedback, sync, bit_depth }
+impl MidiIn {
+    /// Opens `port`, or the first port there is.
+    fn new(ctrl: &Controller, port: Option<String>) -> Self {
+        let mut input = Self { conn: None, virtual_port: None, wanted: port.clone(), lost: false, ports: Vec::new(), scanned: Instant::now(),
+                               events: ctrl.sender(), controls: ctrl.cc_sender(), receive: ReceiveChannel::default() };
+        input.open(port.as_deref());
+        input.rescan();
+        input
     }
 
-    fn crush(&self, sample: f32) -> f32 {
-        let step = 2.0_f32.powi(-(self.bit_depth as i32));
-        ((sample / step).round() * step).clamp(-1.0, 1.0)
+    fn open(&mut self, port: Option<&str>) {
+        self.conn = None; // let go of the old port first
+        match midi::connect(port, self.events.clone(), self.controls.clone(), self.receive.clone()) {
+            Ok(conn) => {
+                self.wanted = Some(conn.port.clone());
+                self.conn = Some(conn);
+                self.lost = false;
+            }
+            Err(e) => eprintln!("MIDI disabled: {}", e),
+        }
     }
 
-    fn hard_sync(&self, phase: f32) -> f32 {
-        if self.sync { phase % (2.0 * PI) } else { phase }
+    fn close(&mut self) {
+        self.conn = None;
+        self.wanted = None;
+        self.lost = false;
     }
 
-    fn sample(&mut self, dt: f32, mod_in: f32) -> f32 {
-        let mod_freq = self.freq * self.ratio + mod_in * self.freq;
-        let fb = self.feedback * self.phase;
-        self.phase += 2.0 * PI * mod_freq * dt + fb;
-        self.phase = self.hard_sync(self.phase);
+    fn rescan(&mut self) {
+        self.ports = midi::ports().unwrap_or_default();
+        self.scanned = Instant::now();
+    }
 
-        self.envelope.advance(dt);
-        let env = self.envelope.level;
+    /// Follows devices being unplugged and plugged back in.
+    fn poll(&mut self) {
+        if self.scanned.elapsed() < RESCAN { return; }
+        self.rescan();
+        if let Some(conn) = &self.conn {
+            if !self.ports.contains(&conn.port) {
+                eprintln!("MIDI input lost: {}", conn.port);
+                self.conn = None;
+                self.lost = true;
+            }
+        }
+        let Some(wanted) = self.wanted.clone() else { return };
+        if self.conn.is_none() && self.ports.iter().any(|p| p.contains(&wanted)) { self.open(Some(&wanted)); }
+    }
 
-        let raw = self.amp * env * self.phase.sin();
-        let clipped = raw.clamp(-0.9, 0.9);
-        self.crush(clipped)
+    fn editor(&mut self, ui: &mut egui::Ui) {
+        match (&self.conn, &self.wanted) {
+            (Some(conn), _) => ui.label(format!("Connected: {}", conn.port)),
+            (None, Some(wanted)) if self.lost => ui.label(format!("Lost {}, waiting for it to come back…", wanted)),
+            (None, Some(wanted)) => ui.label(format!("Waiting for {}…", wanted)),
+            (None, None) => ui.label("Not connected"),
+        };
+        let connected = self.conn.as_ref().map(|c| c.port.clone());
+        let mut open = None;
+        for port in &self.ports {
+            ui.horizontal(|ui| {
+                ui.label(port);
+                if connected.as_ref() == Some(port) {
+                    if ui.button("Disconnect").clicked() { open = Some(None); }
+                } else if ui.button("Connect").clicked() {
+                    open = Some(Some(port.clone()));
+                }
+            });
+        }
+        match open {
+            Some(Some(port)) => self.open(Some(&port)),
+            Some(None) => self.close(),
+            None => {}
+        }
+        if self.ports.is_empty() { ui.label("No MIDI inputs found."); }
+        #[cfg(unix)]
+        {
+            let mut on = self.virtual_port.is_some();
+            if ui.checkbox(&mut on, format!("Virtual port \"{}\"", midi::VIRTUAL_PORT)).changed() {
+                self.virtual_port = None;
+                if on {
+                    match midi::create_virtual(self.events.clone(), self.controls.clone(), self.receive.clone()) {
+                        Ok(conn) => self.virtual_port = Some(conn),
+                        Err(e) => eprintln!("Creating the virtual MIDI port failed: {}", e),
+                    }
+                }
+            }
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Rescan").clicked() { self.rescan(); }
+            let mut channel = self.receive.get();
+            ui.label("Channel:");
+            egui::ComboBox::from_id_source("receive_channel")
+                .selected_text(channel.map_or("Omni".into(), |c| format!("{}", c + 1)))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut channel, None, "Omni");
+                    for c in 0..16 { ui.selectable_value(&mut channel, Some(c), format!("{}", c + 1)); }
+                });
+            if channel != self.receive.get() { self.receive.set(channel); }
+        });
     }
 }
 
-/// ----------  Synth ----------
-struct FMSynth {
-    ops: [Operator; 4], // 0: carrier, 1: mod1, 2: mod2, 3: mod3
-    sr: f32,
+/// ----------  MIDI file player ----------
+/// A loaded MIDI file, played live through the engine or rendered offline
+/// with the current patch.
+#[derive(Default)]
+struct SongPlayer {
+    song: Option<(PathBuf, Arc<Song>)>,
+    playback: Option<Playback>,
 }
 
-impl FMSynth {
-    fn new(sr: f32) -> Self {
-        let env = Envelope::new(0.01, 0.05, 0.6, 0.2);
-        let ratios = [1.0, 1.618, 2.414, 3.732];
-        let ops = [
-            Operator::new(440.0, 1.0, env.clone(), ratios[0], 0.0, false, 16),
-            Operator::new(220.0, 0.8, env.clone(), ratios[1], 0.05, true, 12),
-            Operator::new(110.0, 0.6, env.clone(), ratios[2], 0.1, true, 10),
-            Operator::new( 55.0, 0.4, env.clone(), ratios[3], 0.15, true, 8),
-        ];
-        Self { ops, sr }
+impl SongPlayer {
+    fn editor(&mut self, ui: &mut egui::Ui, ctrl: &Controller, patch: &Patch, export: &ExportOptions, sr: f32) {
+        if self.playback.as_ref().is_some_and(Playback::is_finished) { self.playback = None; }
+        ui.horizontal(|ui| {
+            if ui.button("Load").clicked() {
+                if let Some(path) = pick_file("MIDI file", &["mid", "midi"]) {
+                    self.playback = None;
+                    match Song::load(&path) {
+                        Ok(song) => self.song = Some((path, Arc::new(song))),
+                        Err(e) => eprintln!("Loading MIDI file failed: {}", e),
+                    }
+                }
+            }
+            let Some((path, song)) = &self.song else { ui.label("No file loaded"); return };
+            ui.label(format!("{} ({:.1} s)", path.file_name().unwrap_or_default().to_string_lossy(), song.duration()));
+            if self.playback.is_some() {
+                if ui.button("■ Stop").clicked() { self.playback = None; }
+            } else if ui.button("▶ Play").clicked() {
+                self.playback = Some(midifile::play(song.clone(), ctrl.sender()));
+            }
+            if ui.button(format!("Render {}", export.format.name())).clicked() {
+                let format = export.format;
+                if let Some(out) = save_file(format.name(), &[format.extension()]) {
+                    let mut synth = FMSynth::new(sr);
+                    synth.patch = patch.clone();
+                    if let Err(e) = render::render_song(&mut synth, song, &out, export) { eprintln!("Rendering failed: {}", e); }
+                }
+            }
+            if ui.button("Render stems").on_hover_text("One file per operator heard at the output").clicked() {
+                let format = export.format;
+                if let Some(out) = save_file(format.name(), &[format.extension()]) {
+                    if let Err(e) = render::render_stems(patch, sr, Some(song), &out, export, 0.0) { eprintln!("Rendering failed: {}", e); }
+                }
+            }
+        });
     }
+}
 
-    fn note_on(&mut self)   { for o in &mut self.ops { o.envelope.note_on(); } }
-    fn note_off(&mut self)  { for o in &mut self.ops { o.envelope.note_off(); } }
+/// ----------  Performance recorder ----------
+/// Collects what the engine plays (keyboard, MIDI input, files) into a song
+/// that starts at the first event and can be saved as a MIDI file.
+#[derive(Default)]
+struct Recorder {
+    take: Option<(Option<u64>, Song)>, // while recording: first event's frame, events so far
+    held: u128,                        // notes down in the take
+    last: Option<Song>,                // the finished take
+}
 
-    fn render_block(&mut self, out: &mut [f32]) {
-        let dt = 1.0 / self.sr;
-        for s in out.iter_mut() {
-            let m3 = self.ops[3].sample(dt, 0.0);
-            let m2 = self.ops[2].sample(dt, m3);
-            let m1 = self.ops[1].sample(dt, m2);
-            *s = self.ops[0].sample(dt, m1);
+impl Recorder {
+    /// Drains the engine's played events, keeping them while recording.
+    fn poll(&mut self, ctrl: &Controller, sr: f32) {
+        for (frame, event) in ctrl.played() {
+            let Some((start, song)) = &mut self.take else { continue };
+            if midi::encode(event).is_none() { continue; }
+            let start = *start.get_or_insert(frame);
+            match event {
+                Event::NoteOn { note, .. } => self.held |= 1 << note.min(127),
+                Event::NoteOff { note } => self.held &= !(1u128 << note.min(127)),
+                _ => {}
+            }
+            song.events.push((frame.saturating_sub(start) as f64 / sr as f64, event));
         }
     }
+
+    /// Ends the take, letting go of notes still down at its last event.
+    fn stop(&mut self) {
+        let Some((_, mut song)) = self.take.take() else { return };
+        let end = song.duration();
+        for note in (0..128u8).filter(|&n| self.held >> n & 1 == 1) { song.events.push((end, Event::NoteOff { note })); }
+        self.held = 0;
+        self.last = Some(song);
+    }
+
+    fn editor(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some((_, song)) = &self.take {
+                let events = song.events.len();
+                if ui.button("■ Stop recording").clicked() { self.stop(); }
+                ui.label(format!("Recording… {} events", events));
+                return;
+            }
+            if ui.button("● Record MIDI").clicked() { self.take = Some((None, Song::default())); }
+            let Some(song) = &self.last else { return };
+            ui.label(format!("Take: {:.1} s", song.duration()));
+            if ui.button("Save MIDI").clicked() {
+                if let Some(path) = save_file("MIDI file", &["mid"]) {
+                    if let Err(e) = song.save(&path) { eprintln!("Saving MIDI file failed: {}", e); }
+                }
+            }
+        });
+    }
 }
 
 /// ----------  UI App ----------
 struct App {
-    synth: Arc<Mutex<FMSynth>>,
+    patch: Patch, // the UI's working copy, published every frame
+    ctrl: Controller,
     note_on: bool,
+    mod_wheel: f32,
+    bend: f32,
+    octave: i32,                  // octave of the A key, C4 = 4
+    held: HashMap<egui::Key, u8>, // keys down → the note they started
+    spectrum: Spectrum,
+    snap_ratios: bool,            // ratio editors drop their fine part
+    op_clipboard: Option<Operator>,
+    learn: Learn,
+    midi_in: MidiIn,
+    song: SongPlayer,
+    recorder: Recorder,
+    export: ExportOptions, // for recordings and renders
+    cycle: CycleExport,
+    xruns: XrunLog,
+    script: ScriptEditor,
+    rng: Rng,
+    evolver: Evolver,
+    morph: Morph,
+    compare: Compare,
+    session: Option<PathBuf>, // where to autosave, if anywhere
+    session_saved: Instant,
+    window: Option<[f32; 4]>, // position and size as last seen
+    info: PresetInfo,         // saved along with the patch
+    tags: String,             // info.tags as typed
+    browser: PresetBrowser,
+    toast: Option<Toast>,
+    #[cfg(feature = "link")]
+    link: Option<fm_synth::link::Link>,
+    audio: Audio,
 }
 
-impl Default for App {
-    fn default() -> Self { Self { synth: Arc::new(Mutex::new(FMSynth::new(44100.0))), note_on: false } }
+impl App {
+    fn new(patch: Patch, mut ctrl: Controller, audio: Audio, midi_port: Option<String>, midi_map: PathBuf) -> Self {
+        let midi_in = MidiIn::new(&ctrl, midi_port);
+        // The recorder starts at the engine's default rate, not the stream's
+        ctrl.recorder().set_sample_rate(audio.sr);
+        Self { patch, ctrl, note_on: false, mod_wheel: 0.0, bend: 0.0, octave: 4, held: HashMap::new(),
+               spectrum: Spectrum::new(), snap_ratios: false,
+               op_clipboard: None, learn: Learn::open(midi_map), midi_in, song: SongPlayer::default(),
+               recorder: Recorder::default(), export: ExportOptions::default(),
+               cycle: CycleExport::default(), xruns: XrunLog::new(), script: ScriptEditor::default(),
+               rng: Rng::from_time(), evolver: Evolver::default(),
+               morph: Morph::default(), compare: Compare::default(),
+               session: None, session_saved: Instant::now(), window: None,
+               info: PresetInfo::default(), tags: String::new(), browser: PresetBrowser::new(PathBuf::from(PRESET_DIR)), toast: None,
+               #[cfg(feature = "link")] link: None, audio }
+    }
+
+    /// Plays the QWERTY piano. Auto-repeat is ignored and every key remembers
+    /// its own note, so chords and octave changes mid-chord release cleanly.
+    fn keyboard_input(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() { return; }
+        let events = ctx.input(|i| i.events.clone());
+        for event in events {
+            // Key-ups never arrive once the window loses focus
+            if let egui::Event::WindowFocused(false) = event {
+                for (_, note) in self.held.drain() { self.ctrl.send(Event::NoteOff { note }); }
+                continue;
+            }
+            let egui::Event::Key { key, physical_key, pressed, repeat, .. } = event else { continue };
+            let key = physical_key.unwrap_or(key);
+            match (key, pressed) {
+                (egui::Key::Z, true) if !repeat => self.octave = (self.octave - 1).max(-1),
+                (egui::Key::X, true) if !repeat => self.octave = (self.octave + 1).min(9),
+                (_, true) if repeat || self.held.contains_key(&key) => {}
+                (_, true) => {
+                    let Some(&(_, offset)) = PIANO_KEYS.iter().find(|(k, _)| *k == key) else { continue };
+                    let note = ((self.octave + 1) * 12 + offset as i32).clamp(0, 127) as u8;
+                    self.held.insert(key, note);
+                    self.ctrl.send(Event::NoteOn { note, velocity: 100, channel: 0 });
+                }
+                (_, false) => {
+                    if let Some(note) = self.held.remove(&key) { self.ctrl.send(Event::NoteOff { note }); }
+                }
+            }
+        }
+    }
+
+    /// Records the output to a timestamped file in the working directory.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn record_button(&mut self, ui: &mut egui::Ui) {
+        let export = self.export;
+        let rec = self.ctrl.recorder();
+        if rec.is_recording() {
+            if ui.button("■ Stop").clicked() {
+                if let Err(e) = rec.stop() { eprintln!("Recording failed: {}", e); }
+            }
+            ui.label(egui::RichText::new("● REC").color(egui::Color32::RED));
+        } else {
+            if ui.button("● Record").clicked() {
+                let path = record::timestamped_path(Path::new("."), export.format.extension());
+                if let Err(e) = rec.start(path, &export) { eprintln!("Recording failed: {}", e); }
+            }
+            if let Some(path) = rec.path() { ui.label(format!("Saved {}", path.display())); }
+        }
+    }
+
+    /// Takes are written at one rate throughout, so a new stream ends the
+    /// one running.
+    fn stream_changed(&mut self) {
+        let rec = self.ctrl.recorder();
+        if let Err(e) = rec.stop() { eprintln!("Recording failed: {}", e); }
+        rec.set_sample_rate(self.audio.sr);
+    }
+
+    fn save_session(&mut self) {
+        self.session_saved = Instant::now();
+        let Some(path) = &self.session else { return };
+        let session = Session {
+            patch: Some(self.patch.clone()),
+            host: Some(self.audio.host.name().into()),
+            device: self.audio.device.as_ref().map(ToString::to_string),
+            rate: self.audio.rate,
+            buffer: self.audio.buffer,
+            midi_port: self.midi_in.wanted.clone(),
+            receive: self.midi_in.receive.get(),
+            window: self.window,
+        };
+        if let Err(e) = session.save(path) { eprintln!("Saving session failed: {}", e); }
+    }
+
+    fn save_patch(&mut self) {
+        let Some(path) = save_file("Patch", &["json"]) else { return };
+        let preset = Preset { info: self.info.clone(), patch: self.patch.clone() };
+        if let Err(e) = preset.save(&path) { eprintln!("Saving patch failed: {}", e); }
+        self.browser.rescan();
+    }
+
+    fn load_patch(&mut self) {
+        let Some(path) = pick_file("Patch", &["json"]) else { return };
+        match Preset::load(&path) {
+            Ok(preset) => self.open_preset(preset),
+            Err(e) => eprintln!("Loading patch failed: {}", e),
+        }
+    }
+
+    /// Puts the patch on the clipboard as text for sharing without a file.
+    fn copy_patch(&self, ui: &egui::Ui) {
+        let preset = Preset { info: self.info.clone(), patch: self.patch.clone() };
+        match preset.to_share_string() {
+            Ok(text) => ui.output_mut(|o| o.copied_text = text),
+            Err(e) => eprintln!("Copying patch failed: {}", e),
+        }
+    }
+
+    fn paste_patch(&mut self) {
+        let Some(text) = clipboard_text() else { return };
+        match Preset::from_share_string(&text) {
+            Ok(preset) => self.open_preset(preset),
+            Err(e) => eprintln!("Pasting patch failed: {}", e),
+        }
+    }
+
+    /// Loads a preset file dropped on the window as the patch, or with Shift
+    /// held, or several at once, adds them to the browser's folder.
+    fn dropped_files(&mut self, ctx: &egui::Context) {
+        let (files, hovering, to_bank) =
+            ctx.input(|i| (i.raw.dropped_files.clone(), !i.raw.hovered_files.is_empty(), i.modifiers.shift));
+        if hovering {
+            self.toast = Some(Toast::new("Drop to load the patch, hold Shift to add it to the preset folder"));
+        }
+        if files.is_empty() { return; }
+        let mut presets = Vec::new();
+        for file in &files {
+            match dropped_presets(file) {
+                Ok(found) => presets.extend(found),
+                Err(e) => {
+                    self.toast = Some(Toast::new(format!("Couldn't load {}: {}", dropped_name(file), e)));
+                    return;
+                }
+            }
+        }
+        self.take_presets(presets, to_bank);
+    }
+
+    /// Loads a lone preset as the patch; several, or any `to_bank`, go into
+    /// the browser's folder instead.
+    fn take_presets(&mut self, mut presets: Vec<(String, Preset)>, to_bank: bool) {
+        if presets.len() == 1 && !to_bank {
+            let (name, preset) = presets.remove(0);
+            self.open_preset(preset);
+            self.toast = Some(Toast::new(format!("Loaded {}", name)));
+            return;
+        }
+        let added = presets.iter().map(|(name, preset)| preset::add(&self.browser.dir, name, preset))
+            .collect::<Result<Vec<_>, _>>();
+        self.browser.rescan();
+        self.toast = Some(Toast::new(match added {
+            Ok(added) => format!("Added {} presets to {}", added.len(), self.browser.dir.display()),
+            Err(e) => format!("Adding presets to {} failed: {}", self.browser.dir.display(), e),
+        }));
+    }
+
+    /// Reads a TX81Z, DX21 or DX100 voice or bank dump.
+    fn import_sysex(&mut self) {
+        let Some(path) = pick_file("SysEx", &["syx"]) else { return };
+        let name = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+        match std::fs::read(&path).map_err(Box::from).and_then(|bytes| sysex_presets(&name, &bytes)) {
+            Ok(presets) => self.take_presets(presets, false),
+            Err(e) => eprintln!("Importing SysEx failed: {}", e),
+        }
+    }
+
+    fn open_preset(&mut self, preset: Preset) {
+        self.tags = preset.info.tags.join(", ");
+        self.info = preset.info;
+        self.patch = preset.patch;
+    }
+
+    /// Name, category, author and tags saved with the patch.
+    fn info_editor(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("preset_info").show(ui, |ui| {
+            for (label, text) in [("Name:", &mut self.info.name), ("Category:", &mut self.info.category),
+                                  ("Author:", &mut self.info.author)] {
+                ui.label(label);
+                ui.text_edit_singleline(text);
+                ui.end_row();
+            }
+            ui.label("Tags:");
+            if ui.text_edit_singleline(&mut self.tags).on_hover_text("Separated by commas").changed() {
+                self.info.tags = self.tags.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect();
+            }
+            ui.end_row();
+        });
+    }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        self.keyboard_input(ctx);
+        self.midi_in.poll();
+        self.learn.handle(&mut self.patch, &self.ctrl);
+        self.script.poll(&mut self.patch);
+        self.recorder.poll(&self.ctrl, self.audio.sr);
+        self.xruns.poll(self.ctrl.meters());
+        if self.audio.poll() { self.stream_changed(); }
+        #[cfg(target_arch = "wasm32")]
+        if ctx.input(|i| i.pointer.any_pressed()) { self.audio.resume(); }
+        if let Some((outer, inner)) = ctx.input(|i| i.viewport().outer_rect.zip(i.viewport().inner_rect)) {
+            self.window = Some([outer.min.x, outer.min.y, inner.width(), inner.height()]);
+        }
+        if self.session_saved.elapsed() >= AUTOSAVE { self.save_session(); }
+        self.dropped_files(ctx);
+        if let Some(toast) = &self.toast {
+            if !toast.show(ctx) { self.toast = None; }
+        }
+        ctx.request_repaint(); // keep the scope moving
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("FM Synth Beast Control");
+            ui.label(format!("Keyboard: A–; play, Z/X octave (now C{})", self.octave));
+
+            ui.horizontal(|ui| {
+                if ui.button("Save Patch").clicked() { self.save_patch(); }
+                if ui.button("Load Patch").clicked() { self.load_patch(); }
+                if ui.button("Import SysEx").on_hover_text("Load a TX81Z, DX21 or DX100 voice, or add a bank to the preset folder").clicked() {
+                    self.import_sysex();
+                }
+                if ui.button("Copy Patch").on_hover_text("Copy the patch to the clipboard as text to share").clicked() {
+                    self.copy_patch(ui);
+                }
+                if ui.button("Paste Patch").on_hover_text("Load a patch shared as text from the clipboard").clicked() {
+                    self.paste_patch();
+                }
+                if ui.button("Init").on_hover_text("Start over from a single sine carrier").clicked() {
+                    self.patch = Patch::init();
+                }
+                if ui.button("Randomize").on_hover_text("New operator ratios, levels, envelopes and feedback").clicked() {
+                    random::randomize(&mut self.patch, &mut self.rng);
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                self.record_button(ui);
+                ui.separator();
+                self.compare.editor(ui, &mut self.patch);
+            });
+            ui.collapsing("Presets", |ui| {
+                if let Some(preset) = self.browser.editor(ui) { self.open_preset(preset); }
+            });
+            ui.collapsing("Preset Info", |ui| self.info_editor(ui));
+            level_meters(ui, self.ctrl.meters());
+            dsp_load_meter(ui, self.ctrl.meters());
+            ui.collapsing(format!("Dropouts ({})", self.xruns.total), |ui| self.xruns.editor(ui));
+
+            ui.collapsing("Audio Output", |ui| {
+                if self.audio.editor(ui) { self.stream_changed(); }
+            });
+            ui.collapsing("Export Format", |ui| export_editor(ui, &mut self.export));
+            ui.collapsing("Single Cycle", |ui| self.cycle.editor(ui, &self.patch, self.export.bits));
+            ui.collapsing("Oscilloscope", |ui| draw_scope(ui, self.ctrl.scope().samples()));
+            ui.collapsing("Spectrum", |ui| self.spectrum.draw(ui, self.ctrl.scope().samples(), self.audio.sr));
+            ui.collapsing("MIDI Map", |ui| self.learn.editor(ui));
+            ui.collapsing("MIDI Input", |ui| self.midi_in.editor(ui));
+            ui.collapsing("MIDI File", |ui| {
+                self.song.editor(ui, &self.ctrl, &self.patch, &self.export, self.audio.sr);
+                self.recorder.editor(ui);
+            });
+            ui.collapsing("Script", |ui| self.script.editor(ui, &mut self.patch));
+            ui.collapsing("Mutate & Breed", |ui| self.evolver.editor(ui, &mut self.patch, &mut self.rng));
+            ui.collapsing("Morph", |ui| self.morph.editor(ui, &mut self.patch));
+            ui.collapsing("Tuning", |ui| tuning_editor(ui, &mut self.patch));
 
             // Operator panels
-            let mut synth = self.synth.lock().unwrap();
-            for (i, op) in synth.ops.iter_mut().enumerate() {
-                ui.collapsing(format!("Operator {}", i), |ui| {
+            let patch = &mut self.patch;
+            let learn = &mut self.learn;
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut patch.note_mode, "Note mode");
+                ui.label("A4:"); learn.attach(ui.add(Slider::new(&mut patch.a4, 415.0..=466.0).suffix(" Hz")), Param::A4);
+            });
+            ui.horizontal(|ui| {
+                let mut n = patch.ops.len();
+                ui.label("Operators:");
+                if ui.add(egui::DragValue::new(&mut n).clamp_range(1..=MAX_OPS)).changed() { patch.set_op_count(n); }
+                ui.checkbox(&mut self.snap_ratios, "Snap ratios to harmonics");
+            });
+            let (note_mode, snap_ratios) = (patch.note_mode, self.snap_ratios);
+            let clipboard = &mut self.op_clipboard;
+            let mut swap = None;
+            let op_count = patch.ops.len();
+            let base = patch.ops[0].freq;
+            let carriers: Vec<bool> = (0..op_count).map(|i| patch.routing.outputs(i)).collect();
+            for (i, op) in patch.ops.iter_mut().enumerate() {
+                ui.collapsing(format!("Operator {}", i + 1), |ui| {
+                    ui.horizontal(|ui| {
+                        ui.toggle_value(&mut op.mute, "Mute");
+                        ui.toggle_value(&mut op.solo, "Solo");
+                        if ui.button("Copy").clicked() { *clipboard = Some(op.clone()); }
+                        if ui.add_enabled(clipboard.is_some(), egui::Button::new("Paste")).clicked() {
+                            if let Some(copied) = clipboard { *op = copied.clone(); }
+                        }
+                        // Carriers come back at full level, modulators silent
+                        if ui.button("Reset").clicked() { *op = Operator::init(base, if carriers[i] { 1.0 } else { 0.0 }); }
+                        egui::ComboBox::from_id_source(("swap", i))
+                            .selected_text("Swap with…")
+                            .show_ui(ui, |ui| {
+                                for j in (0..op_count).filter(|&j| j != i) {
+                                    if ui.button(format!("Operator {}", j + 1)).clicked() { swap = Some((i, j)); }
+                                }
+                            });
+                    });
                     ui.horizontal(|ui| {
-                        ui.label("Freq:"); ui.add(Slider::new(&mut op.freq, 20.0..=2000.0));
+                        ui.label("Wave:");
+                        egui::ComboBox::from_id_source(("wave", i))
+                            .selected_text(op.waveform.name())
+                            .show_ui(ui, |ui| {
+                                for w in Waveform::ALL { ui.selectable_value(&mut op.waveform, w, w.name()); }
+                            });
+                        if ui.button("Load WAV…").clicked() {
+                            if let Some(path) = pick_file("WAV", &["wav"]) {
+                                match Wavetable::load(&path) {
+                                    Ok(table) => op.table = Some(table),
+                                    Err(e) => eprintln!("Loading waveform failed: {}", e),
+                                }
+                            }
+                        }
+                        if let Some(table) = &op.table {
+                            ui.label(table.name());
+                            if ui.small_button("✕").clicked() { op.table = None; }
+                        }
+                        ui.label("Noise:");
+                        for k in NoiseKind::ALL { ui.selectable_value(&mut op.noise, k, k.name()); }
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Amp:"); ui.add(Slider::new(&mut op.amp, 0.0..=2.0));
+                        // In note mode the key sets the frequency
+                        ui.label("Freq:");
+                        let freq = ui.add_enabled(!note_mode, Slider::new(&mut op.freq, 20.0..=2000.0));
+                        learn.attach(freq, Param::Op(i, OpParam::Freq));
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Ratio:"); ui.add(Slider::new(&mut op.ratio, 0.1..=5.0));
+                        ui.label("Amp:"); learn.attach(ui.add(Slider::new(&mut op.amp, 0.0..=2.0)), Param::Op(i, OpParam::Amp));
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Feedback:"); ui.add(Slider::new(&mut op.feedback, 0.0..=0.5));
+                        ui.label("Vel → Level:"); learn.attach(ui.add(Slider::new(&mut op.velocity_sens, -1.0..=1.0)), Param::Op(i, OpParam::VelocitySens));
                     });
+                    ui.horizontal(|ui| ratio_editor(ui, learn, &mut op.ratio, i, snap_ratios));
                     ui.horizontal(|ui| {
-                        ui.checkbox(&mut op.sync, "Sync");
+                        ui.label("Detune:"); learn.attach(ui.add(Slider::new(&mut op.detune, -50.0..=50.0).suffix(" ct")), Param::Op(i, OpParam::Detune));
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Bit Depth:"); ui.add(Slider::new(&mut op.bit_depth, 8u8..=16));
+                        ui.label("Pan:"); learn.attach(ui.add(Slider::new(&mut op.pan, -1.0..=1.0)), Param::Op(i, OpParam::Pan));
+                    });
+                    ui.collapsing("Level Scaling", |ui| level_scaling_editor(ui, learn, &mut op.level_scaling, i));
+                    ui.horizontal(|ui| {
+                        ui.label("Rate Scaling:"); learn.attach(ui.add(Slider::new(&mut op.rate_scaling, 0.0..=1.0)), Param::Op(i, OpParam::RateScaling));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Feedback:"); learn.attach(ui.add(Slider::new(&mut op.feedback, 0.0..=0.5)), Param::Op(i, OpParam::Feedback));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut op.sync, "Sync")
+                            .on_hover_text("Hard sync: restarts the wave every cycle of the note's pitch; try a fractional ratio");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Bit Depth:"); learn.attach(ui.add(Slider::new(&mut op.bit_depth, 8u8..=16)), Param::Op(i, OpParam::BitDepth));
                     });
 
+                    ui.horizontal(|ui| {
+                        ui.label("Envelope:");
+                        ui.selectable_value(&mut op.env_kind, EnvKind::Adsr, "ADSR");
+                        ui.selectable_value(&mut op.env_kind, EnvKind::RateLevel, "Rate/Level");
+                    });
+                    if op.env_kind == EnvKind::RateLevel {
+                        rate_level_editor(ui, learn, &mut op.rate_level, i);
+                        return;
+                    }
+
                     // Envelope sliders
                     let e = &mut op.envelope;
                     ui.horizontal(|ui| {
-                        ui.label("Attack"); ui.add(Slider::new(&mut e.attack, 0.001..=2.0));
+                        ui.label("Attack"); learn.attach(ui.add(Slider::new(&mut e.attack, 0.001..=2.0)), Param::Op(i, OpParam::Attack));
+                        learn.attach(ui.add(Slider::new(&mut e.attack_curve, -1.0..=1.0).text("curve")), Param::Op(i, OpParam::AttackCurve));
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Decay"); ui.add(Slider::new(&mut e.decay, 0.001..=2.0));
+                        ui.label("Decay"); learn.attach(ui.add(Slider::new(&mut e.decay, 0.001..=2.0)), Param::Op(i, OpParam::Decay));
+                        learn.attach(ui.add(Slider::new(&mut e.decay_curve, -1.0..=1.0).text("curve")), Param::Op(i, OpParam::DecayCurve));
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Sustain"); ui.add(Slider::new(&mut e.sustain, 0.0..=1.0));
+                        ui.label("Sustain"); learn.attach(ui.add(Slider::new(&mut e.sustain, 0.0..=1.0)), Param::Op(i, OpParam::Sustain));
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Release"); ui.add(Slider::new(&mut e.release, 0.001..=2.0));
+                        ui.label("Release"); learn.attach(ui.add(Slider::new(&mut e.release, 0.001..=2.0)), Param::Op(i, OpParam::Release));
+                        learn.attach(ui.add(Slider::new(&mut e.release_curve, -1.0..=1.0).text("curve")), Param::Op(i, OpParam::ReleaseCurve));
                     });
+                    ui.checkbox(&mut e.hard_retrigger, "Hard retrigger");
+                    envelope_editor(ui, e, egui::Id::new(("env", i)));
                 });
                 ui.separator();
             }
+            if let Some((a, b)) = swap { patch.swap_ops(a, b); }
+
+            // LFOs
+            ui.collapsing("LFOs", |ui| {
+                for (i, lfo) in patch.lfos.iter_mut().enumerate() {
+                    ui.label(format!("LFO {}", i + 1));
+                    ui.horizontal(|ui| {
+                        ui.label("Rate:");
+                        if lfo.sync {
+                            egui::ComboBox::from_id_source(("lfo_beats", i))
+                                .selected_text(beats_name(lfo.beats))
+                                .show_ui(ui, |ui| {
+                                    for b in LFO_BEATS { ui.selectable_value(&mut lfo.beats, b, beats_name(b)); }
+                                });
+                        } else {
+                            learn.attach(ui.add(Slider::new(&mut lfo.rate, 0.01..=20.0).logarithmic(true).suffix(" Hz")),
+                                         Param::Lfo(i, LfoParam::Rate));
+                        }
+                        ui.checkbox(&mut lfo.sync, "Sync");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Depth:"); learn.attach(ui.add(Slider::new(&mut lfo.depth, 0.0..=1.0)), Param::Lfo(i, LfoParam::Depth));
+                        ui.label("AT → Depth:"); learn.attach(ui.add(Slider::new(&mut lfo.pressure, 0.0..=1.0)), Param::Lfo(i, LfoParam::Pressure));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Shape:");
+                        egui::ComboBox::from_id_source(("lfo_shape", i))
+                            .selected_text(lfo.shape.name())
+                            .show_ui(ui, |ui| {
+                                for w in Waveform::ALL { ui.selectable_value(&mut lfo.shape, w, w.name()); }
+                            });
+                        ui.label("Target:");
+                        egui::ComboBox::from_id_source(("lfo_target", i))
+                            .selected_text(lfo.target.name())
+                            .show_ui(ui, |ui| {
+                                for t in ModDest::ALL { ui.selectable_value(&mut lfo.target, t, t.name()); }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        for (op, on) in lfo.ops.iter_mut().enumerate() { ui.checkbox(on, format!("Op {}", op + 1)); }
+                    });
+                    ui.separator();
+                }
+            });
+
+            // Mod matrix: one source → destination slot per row
+            ui.collapsing("Mod Matrix", |ui| {
+                egui::Grid::new("mod_matrix").show(ui, |ui| {
+                    for label in ["Source", "Dest", "Op", "Depth"] { ui.label(label); }
+                    ui.end_row();
+                    for (i, slot) in patch.mod_slots.iter_mut().enumerate() {
+                        egui::ComboBox::from_id_source(("mod_src", i))
+                            .selected_text(slot.source.name())
+                            .show_ui(ui, |ui| {
+                                for src in ModSource::all(op_count) { ui.selectable_value(&mut slot.source, src, src.name()); }
+                            });
+                        egui::ComboBox::from_id_source(("mod_dest", i))
+                            .selected_text(slot.dest.name())
+                            .show_ui(ui, |ui| {
+                                for d in ModDest::ALL { ui.selectable_value(&mut slot.dest, d, d.name()); }
+                            });
+                        egui::ComboBox::from_id_source(("mod_op", i))
+                            .selected_text(format!("Op {}", slot.op + 1))
+                            .show_ui(ui, |ui| {
+                                for op in 0..op_count { ui.selectable_value(&mut slot.op, op, format!("Op {}", op + 1)); }
+                            });
+                        learn.attach(ui.add(Slider::new(&mut slot.depth, -1.0..=1.0)), Param::ModDepth(i));
+                        ui.end_row();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Mod Wheel:");
+                    if ui.add(Slider::new(&mut self.mod_wheel, 0.0..=1.0)).changed() {
+                        self.ctrl.send(Event::ModWheel(self.mod_wheel));
+                    }
+                });
+            });
+
+            // Macros: one knob driving several parameters at once
+            ui.collapsing("Macros", |ui| {
+                for i in 0..MACROS {
+                    if i > 0 { ui.separator(); }
+                    macro_editor(ui, learn, patch, i);
+                }
+            });
+
+            // Routing matrix: row = destination, column = source; × makes
+            // a connection ring-modulate
+            ui.collapsing("Routing", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Modulation:");
+                    ui.selectable_value(&mut patch.mod_mode, ModMode::Frequency, "FM");
+                    ui.selectable_value(&mut patch.mod_mode, ModMode::Phase, "PM");
+                });
+                draw_algorithm(ui, patch);
+                egui::Grid::new("routing").show(ui, |ui| {
+                    ui.label("");
+                    for src in 0..op_count { ui.label(format!("Op {}", src + 1)); }
+                    ui.label("Out");
+                    ui.end_row();
+                    let r = &mut patch.routing;
+                    for dst in 0..op_count {
+                        ui.label(format!("→ Op {}", dst + 1));
+                        for src in 0..op_count {
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut r.mods[dst][src], "");
+                                let ring = egui::SelectableLabel::new(r.ring[dst][src], "×");
+                                if ui.add_enabled(r.mods[dst][src], ring).clicked() { r.ring[dst][src] ^= true; }
+                            });
+                        }
+                        ui.checkbox(&mut r.output[dst], "");
+                        ui.end_row();
+                    }
+                });
+            });
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Voices:"); learn.attach(ui.add(Slider::new(&mut patch.max_voices, 8..=MAX_VOICES)), Param::Voices);
+                ui.label("Oversampling:");
+                for os in [1, 2, 4] { ui.selectable_value(&mut patch.oversample, os, format!("{os}x")); }
+                ui.checkbox(&mut patch.dc_block, "DC Blocker");
+                ui.label("Spread:"); learn.attach(ui.add(Slider::new(&mut patch.spread, 0.0..=1.0)), Param::Spread);
+            });
+
+            // Pitch bend wheel: springs back to centre when let go
+            ui.horizontal(|ui| {
+                ui.label("Bend:");
+                let wheel = ui.add(Slider::new(&mut self.bend, -1.0..=1.0));
+                if wheel.drag_stopped() { self.bend = 0.0; }
+                if wheel.changed() || wheel.drag_stopped() { self.ctrl.send(Event::PitchBend(self.bend)); }
+                ui.label("Range:"); learn.attach(ui.add(Slider::new(&mut patch.bend_range, 0.0..=24.0).suffix(" st")), Param::BendRange);
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut patch.mpe, "MPE");
+                ui.label("Note Bend:");
+                let range = ui.add_enabled(patch.mpe, Slider::new(&mut patch.mpe_bend_range, 0.0..=96.0).suffix(" st"));
+                learn.attach(range, Param::NoteBendRange);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Tempo:"); learn.attach(ui.add(Slider::new(&mut patch.tempo, 40.0..=300.0).suffix(" BPM")), Param::Tempo);
+                ui.label("(MIDI clock overrides)");
+                #[cfg(feature = "link")]
+                if let Some(link) = &self.link {
+                    let mut on = link.is_enabled();
+                    if ui.checkbox(&mut on, "Ableton Link").changed() { link.enable(on); }
+                    if on { ui.label(format!("{} peers", link.peers())); }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Glide:"); learn.attach(ui.add(Slider::new(&mut patch.glide, 0.0..=2.0).suffix(" s")), Param::Glide);
+                ui.selectable_value(&mut patch.glide_mode, GlideMode::Always, "Always");
+                ui.selectable_value(&mut patch.glide_mode, GlideMode::Legato, "Legato only");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut patch.mono, "Mono");
+                ui.add_enabled_ui(patch.mono, |ui| {
+                    ui.label("Priority:");
+                    ui.selectable_value(&mut patch.priority, NotePriority::Last, "Last");
+                    ui.selectable_value(&mut patch.priority, NotePriority::Low, "Low");
+                    ui.selectable_value(&mut patch.priority, NotePriority::High, "High");
+                    ui.checkbox(&mut patch.legato, "Legato");
+                });
+            });
+
+            ui.collapsing("Arpeggiator", |ui| {
+                let arp = &mut patch.arp;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut arp.on, "On");
+                    for m in ArpMode::ALL { ui.selectable_value(&mut arp.mode, m, m.name()); }
+                    ui.label("Octaves:");
+                    ui.add(egui::DragValue::new(&mut arp.octaves).clamp_range(1..=4));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Rate:");
+                    if arp.sync {
+                        egui::ComboBox::from_id_source("arp_beats")
+                            .selected_text(beats_name(arp.beats))
+                            .show_ui(ui, |ui| {
+                                for b in STEP_BEATS { ui.selectable_value(&mut arp.beats, b, beats_name(b)); }
+                            });
+                    } else {
+                        learn.attach(ui.add(Slider::new(&mut arp.rate, 0.5..=32.0).logarithmic(true).suffix(" Hz")), Param::ArpRate);
+                    }
+                    ui.checkbox(&mut arp.sync, "Sync");
+                    ui.label("Gate:"); learn.attach(ui.add(Slider::new(&mut arp.gate, 0.05..=1.0)), Param::ArpGate);
+                });
+            });
+
+            ui.collapsing("Sequencer", |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut patch.seq.on, "On");
+                    if ui.button("▶ Play").clicked() { self.ctrl.send(Event::Start); }
+                    if ui.button("■ Stop").clicked() { self.ctrl.send(Event::Stop); }
+                    ui.label("(or follow MIDI Start/Stop)");
+                });
+                sequence_editor(ui, &mut patch.seq);
+            });
+
+            ui.collapsing("Sub Oscillator", |ui| {
+                let sub = &mut patch.sub;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut sub.on, "On");
+                    ui.selectable_value(&mut sub.octaves, 1, "-1 oct");
+                    ui.selectable_value(&mut sub.octaves, 2, "-2 oct");
+                    egui::ComboBox::from_id_source("sub_wave")
+                        .selected_text(sub.wave.name())
+                        .show_ui(ui, |ui| {
+                            for w in Waveform::ALL { ui.selectable_value(&mut sub.wave, w, w.name()); }
+                        });
+                    ui.label("Level:"); learn.attach(ui.add(Slider::new(&mut sub.level, 0.0..=1.0)), Param::SubLevel);
+                });
+            });
+
+            ui.collapsing("Filter", |ui| {
+                let filter = &mut patch.filter;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut filter.on, "On");
+                    for m in FilterMode::ALL { ui.selectable_value(&mut filter.mode, m, m.name()); }
+                });
+                ui.horizontal(|ui| {
+                    if filter.mode == FilterMode::Formant {
+                        ui.label("Vowel:");
+                        learn.attach(ui.add(Slider::new(&mut filter.vowel, 0.0..=4.0).show_value(false)), Param::FilterVowel);
+                        ui.label(VOWEL_NAMES[filter.vowel.round().clamp(0.0, 4.0) as usize]);
+                    } else {
+                        ui.label("Cutoff:"); learn.attach(ui.add(Slider::new(&mut filter.cutoff, 20.0..=20000.0).logarithmic(true).suffix(" Hz")), Param::FilterCutoff);
+                    }
+                    ui.label("Resonance:"); learn.attach(ui.add(Slider::new(&mut filter.resonance, 0.0..=1.0)), Param::FilterResonance);
+                    ui.label("Drive:"); learn.attach(ui.add(Slider::new(&mut filter.drive, 0.0..=1.0)), Param::FilterDrive);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Envelope amount:");
+                    learn.attach(ui.add(Slider::new(&mut filter.env_amount, -8.0..=8.0).suffix(" oct")), Param::FilterEnvAmount);
+                    ui.label("Key track:"); learn.attach(ui.add(Slider::new(&mut filter.key_track, 0.0..=1.0)), Param::FilterKeyTrack);
+                });
+                envelope_editor(ui, &mut filter.envelope, egui::Id::new("filter_env"));
+            });
+
+            ui.collapsing("FX Chain", |ui| {
+                ui.label("Effects run top to bottom, then the master stage. Untick to bypass.");
+                let mut swap = None;
+                for (i, effect) in patch.fx_chain.order().into_iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(patch.effect_on(effect), effect.name());
+                        if ui.add_enabled(i > 0, egui::Button::new("▲").small()).clicked() { swap = Some((i, i - 1)); }
+                        if ui.add_enabled(i + 1 < EFFECTS, egui::Button::new("▼").small()).clicked() { swap = Some((i, i + 1)); }
+                    });
+                }
+                if let Some((a, b)) = swap { patch.fx_chain.swap(a, b); }
+            });
+
+            ui.collapsing("EQ", |ui| {
+                ui.checkbox(&mut patch.eq.on, "On");
+                egui::Grid::new("eq").show(ui, |ui| {
+                    for (i, band) in patch.eq.bands.iter_mut().enumerate() {
+                        ui.label(BandKind::of(i).name());
+                        learn.attach(ui.add(Slider::new(&mut band.freq, 20.0..=20000.0).logarithmic(true).suffix(" Hz")), Param::EqFreq(i));
+                        learn.attach(ui.add(Slider::new(&mut band.gain, -18.0..=18.0).suffix(" dB")), Param::EqGain(i));
+                        ui.label("Q:");
+                        learn.attach(ui.add(Slider::new(&mut band.q, 0.1..=10.0).logarithmic(true)), Param::EqQ(i));
+                        ui.end_row();
+                    }
+                });
+            });
+
+            ui.collapsing("Modulation FX", |ui| {
+                let fx = &mut patch.mod_fx;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut fx.on, "On");
+                    for k in ModFxKind::ALL { ui.selectable_value(&mut fx.kind, k, k.name()); }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Rate:"); learn.attach(ui.add(Slider::new(&mut fx.rate, 0.01..=10.0).logarithmic(true).suffix(" Hz")), Param::ModFxRate);
+                    ui.label("Depth:"); learn.attach(ui.add(Slider::new(&mut fx.depth, 0.0..=1.0)), Param::ModFxDepth);
+                });
+                ui.horizontal(|ui| {
+                    if fx.kind != ModFxKind::Chorus {
+                        ui.label("Feedback:"); learn.attach(ui.add(Slider::new(&mut fx.feedback, 0.0..=0.9)), Param::ModFxFeedback);
+                    }
+                    ui.label("Mix:"); learn.attach(ui.add(Slider::new(&mut fx.mix, 0.0..=1.0)), Param::ModFxMix);
+                });
+            });
+
+            ui.collapsing("Delay", |ui| {
+                let delay = &mut patch.delay;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut delay.on, "On");
+                    ui.label("Time:");
+                    if delay.sync {
+                        egui::ComboBox::from_id_source("delay_beats")
+                            .selected_text(beats_name(delay.beats))
+                            .show_ui(ui, |ui| {
+                                for b in DELAY_BEATS { ui.selectable_value(&mut delay.beats, b, beats_name(b)); }
+                            });
+                    } else {
+                        learn.attach(ui.add(Slider::new(&mut delay.time, 1.0..=2000.0).logarithmic(true).suffix(" ms")), Param::DelayTime);
+                    }
+                    ui.checkbox(&mut delay.sync, "Sync");
+                    ui.checkbox(&mut delay.ping_pong, "Ping-pong");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Feedback:"); learn.attach(ui.add(Slider::new(&mut delay.feedback, 0.0..=0.95)), Param::DelayFeedback);
+                    ui.label("Mix:"); learn.attach(ui.add(Slider::new(&mut delay.mix, 0.0..=1.0)), Param::DelayMix);
+                });
+            });
+
+            ui.collapsing("Reverb", |ui| {
+                let reverb = &mut patch.reverb;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut reverb.on, "On");
+                    ui.label("Size:"); learn.attach(ui.add(Slider::new(&mut reverb.size, 0.0..=1.0)), Param::ReverbSize);
+                    ui.label("Damping:"); learn.attach(ui.add(Slider::new(&mut reverb.damping, 0.0..=1.0)), Param::ReverbDamping);
+                    ui.label("Mix:"); learn.attach(ui.add(Slider::new(&mut reverb.mix, 0.0..=1.0)), Param::ReverbMix);
+                });
+            });
+
+            ui.collapsing("Compressor", |ui| {
+                let comp = &mut patch.compressor;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut comp.on, "On");
+                    ui.label("Threshold:"); learn.attach(ui.add(Slider::new(&mut comp.threshold, -60.0..=0.0).suffix(" dB")), Param::CompThreshold);
+                    ui.label("Ratio:"); learn.attach(ui.add(Slider::new(&mut comp.ratio, 1.0..=20.0).logarithmic(true)), Param::CompRatio);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Attack:");
+                    learn.attach(ui.add(Slider::new(&mut comp.attack, 0.1..=100.0).logarithmic(true).suffix(" ms")), Param::CompAttack);
+                    ui.label("Release:");
+                    learn.attach(ui.add(Slider::new(&mut comp.release, 10.0..=1000.0).logarithmic(true).suffix(" ms")), Param::CompRelease);
+                    ui.label("Makeup:"); learn.attach(ui.add(Slider::new(&mut comp.makeup, 0.0..=24.0).suffix(" dB")), Param::CompMakeup);
+                });
+                ui.horizontal(|ui| {
+                    let gr = self.ctrl.meters().gain_reduction();
+                    ui.label("Gain reduction:");
+                    ui.add(egui::ProgressBar::new(gr / GR_METER_RANGE).desired_width(200.0).text(format!("-{:.1} dB", gr)));
+                });
+            });
+
+            ui.collapsing("Bitcrusher", |ui| {
+                let crusher = &mut patch.crusher;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut crusher.on, "On");
+                    ui.label("Bits:"); learn.attach(ui.add(Slider::new(&mut crusher.bits, 1.0..=16.0)), Param::CrushBits);
+                    ui.label("Rate:"); learn.attach(ui.add(Slider::new(&mut crusher.rate, 100.0..=44100.0).logarithmic(true).suffix(" Hz")), Param::CrushRate);
+                    ui.label("Mix:"); learn.attach(ui.add(Slider::new(&mut crusher.mix, 0.0..=1.0)), Param::CrushMix);
+                });
+            });
+
+            ui.collapsing("Stereo Width", |ui| {
+                let widener = &mut patch.widener;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut widener.on, "On");
+                    ui.label("Width:"); learn.attach(ui.add(Slider::new(&mut widener.width, 0.0..=2.0)), Param::Width);
+                    ui.label("Haas:"); learn.attach(ui.add(Slider::new(&mut widener.haas, 0.0..=MAX_HAAS).suffix(" ms")), Param::Haas);
+                });
+            });
+
+            ui.collapsing("Master", |ui| {
+                let master = &mut patch.master;
+                ui.horizontal(|ui| {
+                    ui.label("Volume:"); learn.attach(ui.add(Slider::new(&mut master.gain, -60.0..=12.0).suffix(" dB")), Param::MasterGain);
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut master.limiter, "Limiter");
+                    ui.label("Ceiling:");
+                    learn.attach(ui.add_enabled(master.limiter, Slider::new(&mut master.ceiling, -12.0..=0.0).suffix(" dB")), Param::MasterCeiling);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Clip:");
+                    for c in ClipCurve::ALL { ui.selectable_value(&mut master.curve, c, c.name()); }
+                });
+            });
 
             // Note button
             if ui.button(if self.note_on { "NOTE OFF" } else { "NOTE ON" }).clicked() {
                 self.note_on = !self.note_on;
-                if self.note_on { synth.note_on(); } else { synth.note_off(); }
+                self.ctrl.send(Event::Gate(self.note_on));
             }
         });
+        self.ctrl.publish(&self.patch);
+        self.learn.persist();
+    }
+
+    fn on_exit(&mut self, _: Option<&eframe::glow::Context>) { self.save_session(); }
+}
+
+/// Stereo peak meters with the RMS level alongside, and clip indicators
+/// that stay lit until clicked.
+fn level_meters(ui: &mut egui::Ui, meters: &Meters) {
+    let db = |x: f32| 20.0 * x.max(1e-6).log10();
+    for (ch, name) in ["L", "R"].into_iter().enumerate() {
+        ui.horizontal(|ui| {
+            let (peak, rms) = (db(meters.peak(ch)), db(meters.rms(ch)));
+            ui.label(name);
+            let fill = (1.0 + peak / LEVEL_METER_RANGE).clamp(0.0, 1.0);
+            let color = if peak > -3.0 { egui::Color32::from_rgb(200, 160, 40) } else { egui::Color32::from_rgb(60, 160, 80) };
+            ui.add(egui::ProgressBar::new(fill).desired_width(200.0).fill(color)
+                .text(format!("{:.1} dB  RMS {:.1} dB", peak.max(-LEVEL_METER_RANGE), rms.max(-LEVEL_METER_RANGE))));
+            let clip = if meters.clipped(ch) { egui::RichText::new("CLIP").color(egui::Color32::RED) } else { egui::RichText::new("CLIP").weak() };
+            if ui.button(clip).on_hover_text("Click to reset").clicked() { meters.clear_clips(); }
+        });
     }
 }
 
-/// ----------  Main ----------
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Audio thread
-    let host = cpal::default_host();
-    let device = host.default_output_device().expect("No default device");
-    let config = device.default_output_config()?;
+/// Share of the real-time budget the engine spends rendering, warning as
+/// it nears the point where the output breaks up.
+fn dsp_load_meter(ui: &mut egui::Ui, meters: &Meters) {
+    let load = meters.dsp_load();
+    ui.horizontal(|ui| {
+        ui.label("DSP load:");
+        let color = if load > LOAD_WARNING { egui::Color32::RED } else { egui::Color32::from_rgb(60, 160, 80) };
+        ui.add(egui::ProgressBar::new(load.min(1.0)).desired_width(200.0).fill(color).text(format!("{:.0}%", load * 100.0)));
+        if load > LOAD_WARNING {
+            ui.label(egui::RichText::new("Near overload: fewer voices or a larger buffer").color(egui::Color32::RED));
+        }
+    });
+}
 
-    let synth = Arc::new(Mutex::new(FMSynth::new(
-        config.sample_rate() as f32,
-    )));
+/// DSP load the meter starts warning at.
+const LOAD_WARNING: f32 = 0.8;
 
-    let synth_a = synth.clone();
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let mut synth = synth_a.lock().unwrap();
-                synth.render_block(data);
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::I16 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                let mut synth = synth_a.lock().unwrap();
-                let mut buf = vec![0.0f32; data.len()];
-                synth.render_block(&mut buf);
-                for (s, out) in buf.iter().zip(data.iter_mut()) {
-                    *out = (*s * i16::MAX as f32) as i16;
-                }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::U16 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
-                let mut synth = synth_a.lock().unwrap();
-                let mut buf = vec![0.0f32; data.len()];
-                synth.render_block(&mut buf);
-                for (s, out) in buf.iter().zip(data.iter_mut()) {
-                    *out = ((*s * i16::MAX as f32) as i16 as u16) + 32768;
+/// Dropouts the engine reported, each noted with when the UI saw it.
+struct XrunLog {
+    started: Instant,
+    seen: u32,                // engine count already logged
+    times: VecDeque<Instant>, // newest last
+    total: u32,               // since the last reset
+}
+
+impl XrunLog {
+    fn new() -> Self { Self { started: Instant::now(), seen: 0, times: VecDeque::new(), total: 0 } }
+
+    fn poll(&mut self, meters: &Meters) {
+        let count = meters.xruns();
+        let new = count.wrapping_sub(self.seen);
+        self.seen = count;
+        if new == 0 { return; }
+        self.total += new;
+        let now = Instant::now();
+        for _ in 0..new.min(XRUN_LOG_LEN as u32) {
+            if self.times.len() == XRUN_LOG_LEN { self.times.pop_front(); }
+            self.times.push_back(now);
+        }
+    }
+
+    fn editor(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let text = format!("Dropouts: {}", self.total);
+            ui.label(if self.total > 0 { egui::RichText::new(text).color(egui::Color32::RED) } else { egui::RichText::new(text) });
+            if ui.button("Reset").clicked() {
+                self.total = 0;
+                self.times.clear();
+            }
+        });
+        if self.times.is_empty() {
+            ui.label("None so far. If they happen, try a larger buffer or fewer voices.");
+        }
+        for t in self.times.iter().rev() {
+            let at = t.duration_since(self.started).as_secs();
+            ui.label(format!("{:02}:{:02}:{:02} into the session ({} s ago)",
+                             at / 3600, at / 60 % 60, at % 60, t.elapsed().as_secs()));
+        }
+    }
+}
+
+/// Dropout times kept for display.
+const XRUN_LOG_LEN: usize = 20;
+
+/// Range of the level meters, in dB below full scale.
+const LEVEL_METER_RANGE: f32 = 60.0;
+
+/// Vowels along the formant filter's morph.
+const VOWEL_NAMES: [&str; 5] = ["A", "E", "I", "O", "U"];
+
+/// Gain reduction that fills the compressor's meter, in dB.
+const GR_METER_RANGE: f32 = 20.0;
+
+/// ----------  Export format ----------
+/// Format, bit depth or Vorbis quality for recordings and renders.
+fn export_editor(ui: &mut egui::Ui, export: &mut ExportOptions) {
+    ui.horizontal(|ui| {
+        for f in AudioFormat::ALL { ui.selectable_value(&mut export.format, f, f.name()); }
+        let depths = export.format.bit_depths();
+        if depths.is_empty() {
+            ui.label("Quality:");
+            ui.add(Slider::new(&mut export.quality, -0.2..=1.0));
+        } else {
+            if !depths.contains(&export.bits) { export.bits = depths[0]; }
+            for &b in depths {
+                ui.selectable_value(&mut export.bits, b, if b == 32 { "32-bit float".into() } else { format!("{}-bit", b) });
+            }
+        }
+    });
+}
+
+/// ----------  Single-cycle export ----------
+const CYCLE_LENGTHS: [usize; 5] = [256, 512, 1024, 2048, 4096];
+
+/// One period of the patch at a chosen pitch, saved for wavetable synths
+/// and samplers.
+struct CycleExport {
+    note: u8,
+    length: usize, // samples per cycle
+}
+
+impl Default for CycleExport {
+    fn default() -> Self { Self { note: 60, length: 2048 } }
+}
+
+impl CycleExport {
+    fn editor(&mut self, ui: &mut egui::Ui, patch: &Patch, bits: u16) {
+        ui.horizontal(|ui| {
+            ui.label("Note:");
+            ui.add(egui::DragValue::new(&mut self.note).clamp_range(0..=127));
+            ui.label(note_name(self.note));
+            ui.label("Samples:");
+            for n in CYCLE_LENGTHS { ui.selectable_value(&mut self.length, n, n.to_string()); }
+            if ui.button("Export WAV").clicked() {
+                if let Some(out) = save_file("WAV", &["wav"]) {
+                    if let Err(e) = render::render_cycle(patch, self.note, self.length, bits, &out) {
+                        eprintln!("Exporting cycle failed: {}", e);
+                    }
                 }
-            },
-            err_fn,
-            None,
-        )?,
-        _ => panic!("Unsupported sample format"),
-    };
-    stream.play()?;
+            }
+        });
+    }
+}
 
-    // UI thread
-    let native_options = eframe::NativeOptions::default();
-    eframe::run_native(
-        "FM Synth Beast",
-        native_options,
-        Box::new(|_cc| Box::new(App { synth, note_on: false })),
-    )?;
+/// ----------  Scripts ----------
+const EXAMPLE_SCRIPT: &str = "\
+# Harmonic stack, filter swept every four beats
+for i in 0..ops {
+    /op/{i + 1}/ratio = i + 1
+    /op/{i + 1}/pan = 0.5 * sin(t + i)
+}
+/filter/cutoff = 1200 + 800 * sin(beat * pi / 2)
+";
 
-    Ok(())
+/// A patch script being edited, run once or on every frame.
+struct ScriptEditor {
+    source: String,
+    script: Option<Script>,
+    animate: bool,
+    started: Instant, // `t` counts from here while animating
+    error: Option<String>,
+}
+
+impl Default for ScriptEditor {
+    fn default() -> Self {
+        Self { source: EXAMPLE_SCRIPT.into(), script: None, animate: false, started: Instant::now(), error: None }
+    }
+}
+
+impl ScriptEditor {
+    /// Parses the source again and runs it from `t` = 0.
+    fn start(&mut self, patch: &mut Patch) {
+        self.started = Instant::now();
+        self.script = match Script::parse(&self.source) {
+            Ok(script) => Some(script),
+            Err(e) => { self.error = Some(e); return; }
+        };
+        self.error = None;
+        self.step(patch);
+    }
+
+    fn step(&mut self, patch: &mut Patch) {
+        let Some(script) = &mut self.script else { return };
+        if let Err(e) = script.run(patch, self.started.elapsed().as_secs_f32()) {
+            self.error = Some(e);
+            self.animate = false;
+        }
+    }
+
+    /// Runs the animation's next frame.
+    fn poll(&mut self, patch: &mut Patch) {
+        if self.animate { self.step(patch); }
+    }
+
+    fn editor(&mut self, ui: &mut egui::Ui, patch: &mut Patch) {
+        ui.horizontal(|ui| {
+            if ui.button("Run").clicked() { self.start(patch); }
+            if ui.checkbox(&mut self.animate, "Animate").changed() && self.animate { self.start(patch); }
+            if ui.button("Load").clicked() {
+                if let Some(path) = pick_file("Script", &["txt"]) {
+                    match std::fs::read_to_string(&path) {
+                        Ok(source) => self.source = source,
+                        Err(e) => eprintln!("Loading script failed: {}", e),
+                    }
+                }
+            }
+            if ui.button("Save").clicked() {
+                if let Some(path) = save_file("Script", &["txt"]) {
+                    if let Err(e) = std::fs::write(&path, &self.source) { eprintln!("Saving script failed: {}", e); }
+                }
+            }
+        });
+        ui.add(egui::TextEdit::multiline(&mut self.source).code_editor().desired_rows(8).desired_width(f32::INFINITY));
+        if let Some(e) = &self.error { ui.colored_label(egui::Color32::LIGHT_RED, e); }
+        ui.label("Sets /addresses like /op/1/ratio; t, beat, ops, pi; sin cos abs floor min max clamp lerp rand …");
+    }
+}
+
+/// ----------  Preset browser ----------
+/// Folder the browser starts in, unless `--presets` says otherwise.
+const PRESET_DIR: &str = "presets";
+
+/// The presets in a folder, narrowed down by search words, category and tag.
+struct PresetBrowser {
+    dir: PathBuf,
+    presets: Vec<(PathBuf, Preset)>, // as last scanned
+    scanned: bool,                   // the folder is read when first shown
+    search: String,
+    category: Option<String>,
+    tag: Option<String>,
+    current: Option<PathBuf>,        // the preset last loaded from here
+}
+
+impl PresetBrowser {
+    fn new(dir: PathBuf) -> Self {
+        Self { dir, presets: Vec::new(), scanned: false, search: String::new(), category: None, tag: None, current: None }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rescan(&mut self) {
+        self.scanned = true;
+        self.presets = preset::scan(&self.dir).unwrap_or_else(|e| {
+            eprintln!("Reading {} failed: {}", self.dir.display(), e);
+            Vec::new()
+        });
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn rescan(&mut self) { self.scanned = true; }
+
+    /// Indices of the presets that pass the filters.
+    fn shown(&self) -> Vec<usize> {
+        (0..self.presets.len()).filter(|&i| {
+            let info = &self.presets[i].1.info;
+            info.matches(&self.search)
+                && self.category.as_ref().is_none_or(|c| &info.category == c)
+                && self.tag.as_ref().is_none_or(|t| info.tags.contains(t))
+        }).collect()
+    }
+
+    /// Lists the presets; returns the one to load, if one was picked.
+    fn editor(&mut self, ui: &mut egui::Ui) -> Option<Preset> {
+        if !self.scanned { self.rescan(); }
+        ui.horizontal(|ui| {
+            ui.label(format!("Folder: {}", self.dir.display()));
+            if ui.button("Change…").clicked() {
+                if let Some(dir) = pick_folder() {
+                    self.dir = dir;
+                    self.rescan();
+                }
+            }
+            if ui.button("Rescan").clicked() { self.rescan(); }
+        });
+        let mut categories: Vec<String> = self.presets.iter().map(|(_, p)| p.info.category.clone()).filter(|c| !c.is_empty()).collect();
+        let mut tags: Vec<String> = self.presets.iter().flat_map(|(_, p)| p.info.tags.clone()).collect();
+        for list in [&mut categories, &mut tags] {
+            list.sort();
+            list.dedup();
+        }
+        ui.horizontal(|ui| {
+            ui.label("Search:");
+            ui.text_edit_singleline(&mut self.search);
+            filter_combo(ui, "preset_category", "All categories", &mut self.category, &categories);
+            filter_combo(ui, "preset_tag", "All tags", &mut self.tag, &tags);
+        });
+
+        let shown = self.shown();
+        let at = shown.iter().position(|&i| Some(&self.presets[i].0) == self.current.as_ref());
+        let mut picked = None;
+        ui.horizontal(|ui| {
+            for (label, step) in [("◀ Prev", -1), ("Next ▶", 1)] {
+                if ui.add_enabled(!shown.is_empty(), egui::Button::new(label)).clicked() {
+                    // From nothing yet loaded, Next starts at the top and Prev at the bottom
+                    let next = at.map_or(if step > 0 { 0 } else { shown.len() - 1 },
+                                         |at| (at as isize + step).rem_euclid(shown.len() as isize) as usize);
+                    picked = Some(shown[next]);
+                }
+            }
+            ui.label(format!("{} of {} presets", shown.len(), self.presets.len()));
+        });
+        egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+            egui::Grid::new("preset_list").striped(true).show(ui, |ui| {
+                for &i in &shown {
+                    let (path, preset) = &self.presets[i];
+                    if ui.selectable_label(self.current.as_ref() == Some(path), preset.name(path)).clicked() { picked = Some(i); }
+                    ui.label(&preset.info.category);
+                    ui.label(&preset.info.author);
+                    ui.label(preset.info.tags.join(", "));
+                    ui.end_row();
+                }
+            });
+        });
+        let i = picked?;
+        self.current = Some(self.presets[i].0.clone());
+        Some(self.presets[i].1.clone())
+    }
+}
+
+/// A drop-down narrowing a list to one of `options`, or `all` of them.
+fn filter_combo(ui: &mut egui::Ui, id: &str, all: &str, choice: &mut Option<String>, options: &[String]) {
+    egui::ComboBox::from_id_source(id)
+        .selected_text(choice.as_deref().unwrap_or(all))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(choice, None, all);
+            for o in options { ui.selectable_value(choice, Some(o.clone()), o); }
+        });
+}
+
+/// ----------  Dropped files ----------
+/// How long a toast message stays up.
+const TOAST_TIME: Duration = Duration::from_secs(3);
+
+/// A short message at the bottom of the window that goes away by itself.
+struct Toast {
+    text: String,
+    shown: Instant,
+}
+
+impl Toast {
+    fn new(text: impl Into<String>) -> Self { Self { text: text.into(), shown: Instant::now() } }
+
+    /// Draws the message; false once it has been up long enough.
+    fn show(&self, ctx: &egui::Context) -> bool {
+        egui::Area::new(egui::Id::new("toast"))
+            .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -24.0])
+            .show(ctx, |ui| egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(&self.text)));
+        self.shown.elapsed() < TOAST_TIME
+    }
+}
+
+/// The dropped file's path natively, or just its name in the browser.
+fn dropped_path(file: &egui::DroppedFile) -> PathBuf {
+    file.path.clone().unwrap_or_else(|| PathBuf::from(&file.name))
+}
+
+fn dropped_name(file: &egui::DroppedFile) -> String {
+    dropped_path(file).file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+}
+
+/// The presets in a dropped file, each with a name to file it under.
+fn dropped_presets(file: &egui::DroppedFile) -> Result<Vec<(String, Preset)>, Box<dyn Error>> {
+    // Native drops come as a path, browser ones with the contents
+    let bytes = match (&file.path, &file.bytes) {
+        (Some(path), _) => std::fs::read(path)?,
+        (None, Some(bytes)) => bytes.to_vec(),
+        (None, None) => return Err("nothing to read".into()),
+    };
+    let name = dropped_name(file);
+    let extension = dropped_path(file).extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("json") => {
+            let preset = Preset::parse(&bytes)?;
+            Ok(vec![(if preset.info.name.is_empty() { name } else { preset.info.name.clone() }, preset)])
+        }
+        Some("syx") => Ok(sysex_presets(&name, &bytes)?),
+        _ => Err("not a .json or .syx preset".into()),
+    }
+}
+
+/// The voices in a 4-op Yamaha SysEx dump, named after `file` when they
+/// have no name of their own.
+fn sysex_presets(file: &str, bytes: &[u8]) -> Result<Vec<(String, Preset)>, Box<dyn Error>> {
+    Ok(sysex::import(bytes)?.into_iter().enumerate().map(|(i, preset)| {
+        (if preset.info.name.is_empty() { format!("{} {}", file, i + 1) } else { preset.info.name.clone() }, preset)
+    }).collect())
+}
+
+/// ----------  A/B compare ----------
+/// Two versions of the patch to switch between while editing; the one not
+/// in the editor waits here.
+#[derive(Default)]
+struct Compare {
+    other: Option<Patch>, // until first needed, B is a copy of A
+    on_b: bool,
+}
+
+impl Compare {
+    fn switch(&mut self, patch: &mut Patch) {
+        let other = self.other.take().unwrap_or_else(|| patch.clone());
+        self.other = Some(std::mem::replace(patch, other));
+        self.on_b = !self.on_b;
+    }
+
+    fn editor(&mut self, ui: &mut egui::Ui, patch: &mut Patch) {
+        ui.label("Compare:");
+        for (b, label) in [(false, "A"), (true, "B")] {
+            if ui.selectable_label(self.on_b == b, label).clicked() && self.on_b != b { self.switch(patch); }
+        }
+        if ui.button("Copy A→B").clicked() {
+            if self.on_b {
+                if let Some(a) = &self.other { *patch = a.clone(); }
+            } else {
+                self.other = Some(patch.clone());
+            }
+        }
+    }
+}
+
+/// ----------  Mutate and breed ----------
+/// Variations on the current patch, and children of two loaded presets.
+struct Evolver {
+    amount: f32,                            // how far a mutation strays, 0..1
+    parents: [Option<(String, Patch)>; 2], // name and patch of each
+}
+
+impl Default for Evolver {
+    fn default() -> Self { Self { amount: 0.2, parents: [None, None] } }
+}
+
+impl Evolver {
+    fn editor(&mut self, ui: &mut egui::Ui, patch: &mut Patch, rng: &mut Rng) {
+        ui.horizontal(|ui| {
+            if ui.button("Mutate").clicked() { random::mutate(patch, rng, self.amount); }
+            ui.label("Amount:");
+            ui.add(Slider::new(&mut self.amount, 0.0..=1.0));
+        });
+        for (slot, parent) in ["A", "B"].into_iter().zip(&mut self.parents) {
+            preset_slot(ui, &format!("Parent {}:", slot), parent, patch);
+        }
+        let [Some((_, a)), Some((_, b))] = &self.parents else { return };
+        if ui.button("Breed").on_hover_text("Operators from either parent, other settings in between").clicked() {
+            *patch = random::breed(a, b, rng);
+        }
+    }
+}
+
+/// A preset held for breeding or morphing, loaded from a file or copied
+/// from the current patch.
+fn preset_slot(ui: &mut egui::Ui, label: &str, slot: &mut Option<(String, Patch)>, patch: &Patch) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.label(slot.as_ref().map_or("–", |(name, _)| name.as_str()));
+        if ui.button("Load").clicked() {
+            if let Some(path) = pick_file("Patch", &["json"]) {
+                match Preset::load(&path) {
+                    Ok(preset) => {
+                        let name = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+                        *slot = Some((name, preset.patch));
+                    }
+                    Err(e) => eprintln!("Loading patch failed: {}", e),
+                }
+            }
+        }
+        if ui.button("Use Current").clicked() { *slot = Some(("current patch".into(), patch.clone())); }
+    });
+}
+
+/// ----------  Preset morphing ----------
+/// A slider between two presets, setting the patch as it moves.
+#[derive(Default)]
+struct Morph {
+    presets: [Option<(String, Patch)>; 2],
+    position: f32, // 0 is A, 1 is B
+}
+
+impl Morph {
+    fn editor(&mut self, ui: &mut egui::Ui, patch: &mut Patch) {
+        for (slot, preset) in ["A", "B"].into_iter().zip(&mut self.presets) {
+            preset_slot(ui, &format!("Preset {}:", slot), preset, patch);
+        }
+        let [Some((_, a)), Some((_, b))] = &self.presets else { return };
+        ui.horizontal(|ui| {
+            ui.label("A");
+            let slider = ui.add(Slider::new(&mut self.position, 0.0..=1.0).show_value(false))
+                .on_hover_text("Switches like sync and waveform flip over halfway");
+            ui.label("B");
+            if slider.changed() { *patch = morph::morph(a, b, self.position); }
+        });
+    }
+}
+
+/// ----------  Tuning ----------
+/// Loads Scala scales and keyboard mappings into the patch; without a
+/// scale it plays equal temperament from A4.
+fn tuning_editor(ui: &mut egui::Ui, patch: &mut Patch) {
+    ui.horizontal(|ui| {
+        match &patch.tuning {
+            Some(t) => ui.label(format!("{}: {} ({} notes, repeating at {:.1} cents)",
+                                        t.name, t.scale.description, t.scale.cents.len(), t.scale.period())),
+            None => ui.label(format!("12-TET from A4 = {} Hz", patch.a4)),
+        };
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Load Scale…").on_hover_text("A Scala .scl file").clicked() {
+            if let Some(path) = pick_file("Scala scale", &["scl"]) {
+                match Scale::load(&path) {
+                    Ok(scale) => {
+                        let map = patch.tuning.take().map_or_else(|| KeyMap { freq: patch.a4, ..KeyMap::default() }, |t| t.map);
+                        let name = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+                        patch.tuning = Some(Tuning { name, scale, map });
+                    }
+                    Err(e) => eprintln!("Loading scale failed: {}", e),
+                }
+            }
+        }
+        if ui.button("Load Mapping…").on_hover_text("A Scala .kbm keyboard mapping").clicked() {
+            if let Some(path) = pick_file("Scala keyboard mapping", &["kbm"]) {
+                match KeyMap::load(&path) {
+                    Ok(map) => {
+                        let tuning = patch.tuning.get_or_insert_with(|| Tuning { name: "12-TET".into(), ..Tuning::default() });
+                        tuning.map = map;
+                    }
+                    Err(e) => eprintln!("Loading keyboard mapping failed: {}", e),
+                }
+            }
+        }
+        if ui.add_enabled(patch.tuning.is_some(), egui::Button::new("Equal Temperament")).clicked() { patch.tuning = None; }
+    });
+    let Some(tuning) = &mut patch.tuning else { return };
+    let map = &mut tuning.map;
+    ui.horizontal(|ui| {
+        ui.label("Tonic on note:");
+        ui.add(egui::DragValue::new(&mut map.middle).clamp_range(0..=127));
+        ui.label("Note");
+        ui.add(egui::DragValue::new(&mut map.reference).clamp_range(0..=127));
+        ui.label("at");
+        ui.add(egui::DragValue::new(&mut map.freq).clamp_range(1.0..=20000.0).speed(0.1).suffix(" Hz"));
+        if !map.keys.is_empty() { ui.label(format!("({}-key mapping)", map.keys.len())); }
+    });
+}
+
+/// ----------  Oscilloscope ----------
+const SCOPE_WINDOW: usize = 1024;
+
+/// Draws the newest `SCOPE_WINDOW` samples, triggered on a rising zero
+/// crossing so periodic waveforms stand still.
+fn draw_scope(ui: &mut egui::Ui, samples: &VecDeque<f32>) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 140.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(20));
+    painter.hline(rect.x_range(), rect.center().y, egui::Stroke::new(1.0, egui::Color32::from_gray(60)));
+    if samples.len() < SCOPE_WINDOW * 2 { return; }
+
+    // Search the older half so a full window always follows the trigger
+    let search = samples.len() - SCOPE_WINDOW;
+    let start = (1..search).rev()
+        .find(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+        .unwrap_or(search);
+    let points: Vec<egui::Pos2> = (0..SCOPE_WINDOW).map(|i| {
+        let x = rect.left() + rect.width() * i as f32 / (SCOPE_WINDOW - 1) as f32;
+        let y = rect.center().y - samples[start + i].clamp(-1.0, 1.0) * rect.height() * 0.5;
+        egui::pos2(x, y)
+    }).collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN)));
+}
+
+/// ----------  Ratio editor ----------
+/// Edits a ratio as a coarse harmonic plus a fine offset of 0–99 % of it.
+/// With `snap` a newly picked ratio loses its fine part so it stays harmonic.
+fn ratio_editor(ui: &mut egui::Ui, learn: &mut Learn, ratio: &mut f32, op: usize, snap: bool) {
+    let current = COARSE_RATIOS[coarse_ratio(*ratio)];
+    let mut coarse = current;
+    let mut fine = ((*ratio / coarse - 1.0) * 100.0).clamp(0.0, 99.0);
+    ui.label("Ratio:");
+    let coarse_box = egui::ComboBox::from_id_source(("coarse", op))
+        .width(50.0)
+        .selected_text(format!("{}", coarse))
+        .show_ui(ui, |ui| {
+            for c in COARSE_RATIOS { ui.selectable_value(&mut coarse, c, format!("{}", c)); }
+        });
+    learn.attach(coarse_box.response, Param::Op(op, OpParam::Ratio));
+    let fine_slider = ui.add_enabled(!snap, Slider::new(&mut fine, 0.0..=99.0).text("fine").suffix(" %"));
+    let fine_changed = fine_slider.changed();
+    learn.attach(fine_slider, Param::Op(op, OpParam::Fine));
+    // Only an edit rewrites the ratio, so ratios set before or by
+    // automation keep their fine part
+    if coarse != current {
+        *ratio = coarse * if snap { 1.0 } else { 1.0 + fine / 100.0 };
+    } else if fine_changed {
+        *ratio = coarse * (1.0 + fine / 100.0);
+    }
+}
+
+/// ----------  Tempo sync ----------
+/// Cycle lengths, in quarter notes, a synced LFO offers.
+const LFO_BEATS: [f32; 9] = [0.125, 0.25, 1.0 / 3.0, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+
+/// Step lengths, in quarter notes, the synced arpeggiator and the sequencer offer.
+const STEP_BEATS: [f32; 6] = [1.0, 0.5, 1.0 / 3.0, 0.25, 1.0 / 6.0, 0.125];
+/// Delay divisions, with the dotted values echoes are often set to.
+const DELAY_BEATS: [f32; 8] = [2.0, 1.5, 1.0, 0.75, 0.5, 0.375, 1.0 / 3.0, 0.25];
+
+/// "1/8" (or dotted "1/8.") style note value of a cycle `beats` quarter
+/// notes long.
+fn beats_name(beats: f32) -> String {
+    let whole = beats / 4.0;
+    let dotted = whole / 1.5;
+    if whole >= 1.0 { format!("{} bar", whole) }
+    else if (1.0 / whole - (1.0 / whole).round()).abs() < 1e-3 { format!("1/{}", (1.0 / whole).round()) }
+    else if (1.0 / dotted - (1.0 / dotted).round()).abs() < 1e-3 { format!("1/{}.", (1.0 / dotted).round()) }
+    else { format!("{:.2} beats", beats) }
+}
+
+/// ----------  Sequencer editor ----------
+/// One column per step: on, pitch, velocity, gate and tie, plus the
+/// Euclidean rhythm settings.
+fn sequence_editor(ui: &mut egui::Ui, seq: &mut Sequence) {
+    ui.horizontal(|ui| {
+        ui.label("Length:");
+        ui.add(egui::DragValue::new(&mut seq.length).clamp_range(1..=seq.steps.len()));
+        ui.label("Step:");
+        egui::ComboBox::from_id_source("seq_beats")
+            .selected_text(beats_name(seq.beats))
+            .show_ui(ui, |ui| {
+                for b in STEP_BEATS { ui.selectable_value(&mut seq.beats, b, beats_name(b)); }
+            });
+    });
+    ui.horizontal(|ui| {
+        let len = seq.length;
+        let e = &mut seq.euclid;
+        ui.checkbox(&mut e.on, "Euclidean");
+        ui.add_enabled_ui(e.on, |ui| {
+            ui.label("Pulses:");
+            ui.add(egui::DragValue::new(&mut e.pulses).clamp_range(0..=len));
+            ui.label("Rotation:");
+            ui.add(egui::DragValue::new(&mut e.rotation).clamp_range(0..=len.saturating_sub(1)));
+        });
+    });
+    egui::ScrollArea::horizontal().show(ui, |ui| {
+        egui::Grid::new("sequence").show(ui, |ui| {
+            ui.label("");
+            for i in 0..seq.steps.len() {
+                let label = egui::RichText::new(format!("{}", i + 1));
+                ui.label(if i < seq.length { label.strong() } else { label.weak() });
+            }
+            ui.end_row();
+            // Euclidean mode shows its own pattern in place of the switches
+            ui.label("On");
+            for i in 0..seq.steps.len() {
+                if seq.euclid.on {
+                    let mut hit = seq.plays(i) && i < seq.length;
+                    ui.add_enabled(false, egui::Checkbox::new(&mut hit, ""));
+                } else {
+                    ui.checkbox(&mut seq.steps[i].on, "");
+                }
+            }
+            ui.end_row();
+            ui.label("Note");
+            for s in &mut seq.steps {
+                ui.add(egui::DragValue::new(&mut s.note).clamp_range(0..=127).custom_formatter(|n, _| note_name(n as u8)));
+            }
+            ui.end_row();
+            ui.label("Vel");
+            for s in &mut seq.steps { ui.add(egui::DragValue::new(&mut s.velocity).clamp_range(1..=127)); }
+            ui.end_row();
+            ui.label("Gate");
+            for s in &mut seq.steps { ui.add(egui::DragValue::new(&mut s.gate).speed(0.01).clamp_range(0.05..=1.0)); }
+            ui.end_row();
+            ui.label("Tie");
+            for s in &mut seq.steps { ui.checkbox(&mut s.tie, ""); }
+            ui.end_row();
+        });
+    });
+}
+
+/// ----------  Macro editor ----------
+/// Macro `i`'s knob and name, then a row per target.
+fn macro_editor(ui: &mut egui::Ui, learn: &mut Learn, patch: &mut Patch, i: usize) {
+    ui.horizontal(|ui| {
+        let m = &mut patch.macros[i];
+        ui.add(egui::TextEdit::singleline(&mut m.name).hint_text(Param::Macro(i).name()).desired_width(100.0));
+        let mut value = m.value;
+        if learn.attach(ui.add(Slider::new(&mut value, 0.0..=1.0)), Param::Macro(i)).changed() {
+            Param::Macro(i).set_value(patch, value);
+        }
+    });
+    let op_count = patch.ops.len();
+    let mut remove = None;
+    egui::Grid::new(("macro_targets", i)).show(ui, |ui| {
+        for (t, target) in patch.macros[i].targets.iter_mut().enumerate() {
+            egui::ComboBox::from_id_source(("macro_param", i, t))
+                .selected_text(target.param.name())
+                .show_ui(ui, |ui| {
+                    // Macros don't drive each other, nor operators the patch lacks
+                    let targets = Param::all().filter(|p| match p {
+                        Param::Macro(_) => false,
+                        Param::Op(o, _) => *o < op_count,
+                        _ => true,
+                    });
+                    for p in targets {
+                        if ui.selectable_label(target.param == p, p.name()).clicked() { *target = MacroTarget::new(p); }
+                    }
+                });
+            let range = target.param.range();
+            let speed = (range.end() - range.start()) / 200.0;
+            ui.add(egui::DragValue::new(&mut target.min).clamp_range(range.clone()).speed(speed).prefix("from "));
+            ui.add(egui::DragValue::new(&mut target.max).clamp_range(range).speed(speed).prefix("to "));
+            ui.add(Slider::new(&mut target.curve, -1.0..=1.0).text("curve"));
+            if ui.button("Remove").clicked() { remove = Some(t); }
+            ui.end_row();
+        }
+    });
+    if let Some(t) = remove { patch.macros[i].targets.remove(t); }
+    if ui.button("Add Target").clicked() { patch.macros[i].targets.push(MacroTarget::new(Param::FilterCutoff)); }
+}
+
+/// ----------  Level scaling editor ----------
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// "C4" style name of a MIDI note.
+fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+fn level_scaling_editor(ui: &mut egui::Ui, learn: &mut Learn, ls: &mut LevelScaling, op: usize) {
+    ui.horizontal(|ui| {
+        ui.label("Breakpoint:");
+        let breakpoint = ui.add(egui::DragValue::new(&mut ls.breakpoint).clamp_range(0..=127)
+            .custom_formatter(|n, _| note_name(n as u8)));
+        learn.attach(breakpoint, Param::Op(op, OpParam::Breakpoint));
+    });
+    let sides = [("Left", &mut ls.left_depth, &mut ls.left_curve, OpParam::LeftDepth),
+                 ("Right", &mut ls.right_depth, &mut ls.right_curve, OpParam::RightDepth)];
+    for (name, depth, curve, param) in sides {
+        ui.horizontal(|ui| {
+            ui.label(format!("{}:", name));
+            learn.attach(ui.add(Slider::new(depth, -1.0..=1.0)), Param::Op(op, param));
+            for c in ScaleCurve::ALL { ui.selectable_value(curve, c, c.name()); }
+        });
+    }
+}
+
+/// ----------  Rate/level editor ----------
+fn rate_level_editor(ui: &mut egui::Ui, learn: &mut Learn, rl: &mut RateLevel, op: usize) {
+    egui::Grid::new(("rate_level", op)).show(ui, |ui| {
+        ui.label("");
+        for stage in 1..RL_STAGES { ui.label(format!("{}", stage)); }
+        ui.label("Rel");
+        ui.end_row();
+        ui.label("Time");
+        for (s, t) in rl.times.iter_mut().enumerate() {
+            learn.attach(ui.add(egui::DragValue::new(t).speed(0.01).clamp_range(0.001..=10.0).suffix(" s")), Param::Op(op, OpParam::StageTime(s)));
+        }
+        ui.end_row();
+        ui.label("Level");
+        for (s, l) in rl.levels.iter_mut().enumerate() {
+            learn.attach(ui.add(egui::DragValue::new(l).speed(0.01).clamp_range(0.0..=1.0)), Param::Op(op, OpParam::StageLevel(s)));
+        }
+        ui.end_row();
+    });
+    ui.horizontal(|ui| {
+        ui.label("Loop to:");
+        ui.selectable_value(&mut rl.loop_to, None, "Off");
+        for stage in 0..RL_STAGES - 2 { ui.selectable_value(&mut rl.loop_to, Some(stage), format!("{}", stage + 1)); }
+    });
+}
+
+/// ----------  Envelope editor ----------
+const ENV_MAX_TIME: f32 = 2.0; // same as the stage sliders
+const ENV_HOLD: f32 = 0.3;     // how long the sustain is drawn
+
+/// ADSR curve with draggable attack, decay/sustain and release points. Each
+/// timed stage gets up to a fixed width with square-root scaling so short,
+/// percussive times are still easy to grab.
+fn envelope_editor(ui: &mut egui::Ui, env: &mut Envelope, id: egui::Id) {
+    let size = egui::vec2(ui.available_width().min(360.0), 100.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let seg = rect.width() / 3.5; // attack, decay and release plus half for the hold
+    let width = |t: f32| seg * (t / ENV_MAX_TIME).sqrt();
+    let time = |w: f32| (ENV_MAX_TIME * (w / seg).clamp(0.0, 1.0).powi(2)).max(0.001);
+    let y = |level: f32| rect.bottom() - level * rect.height();
+    let handles = |env: &Envelope| {
+        let (wa, wd, wr) = (width(env.attack), width(env.decay), width(env.release));
+        [egui::pos2(rect.left() + wa, y(1.0)),
+         egui::pos2(rect.left() + wa + wd, y(env.sustain)),
+         egui::pos2(rect.left() + wa + wd + seg * 0.5 + wr, y(0.0))]
+    };
+
+    for (k, pos) in handles(env).into_iter().enumerate() {
+        let grab = ui.interact(egui::Rect::from_center_size(pos, egui::vec2(14.0, 14.0)), id.with(k), egui::Sense::drag());
+        let d = grab.drag_delta();
+        if d == egui::Vec2::ZERO { continue; }
+        match k {
+            0 => env.attack = time(width(env.attack) + d.x),
+            1 => {
+                env.decay = time(width(env.decay) + d.x);
+                env.sustain = (env.sustain - d.y / rect.height()).clamp(0.0, 1.0);
+            }
+            _ => env.release = time(width(env.release) + d.x),
+        }
+    }
+
+    // Time → x, piecewise per stage
+    let (a, d, r) = (env.attack, env.decay, env.release);
+    let (wa, wd, wh, wr) = (width(a), width(d), seg * 0.5, width(r));
+    let to_x = |t: f32| rect.left() + if t < a {
+        wa * t / a
+    } else if t < a + d {
+        wa + wd * (t - a) / d
+    } else if t < a + d + ENV_HOLD {
+        wa + wd + wh * (t - a - d) / ENV_HOLD
+    } else {
+        wa + wd + wh + wr * ((t - a - d - ENV_HOLD) / r).min(1.0)
+    };
+
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, egui::Color32::from_gray(20));
+    let dt = (a + d + ENV_HOLD + r) / 400.0;
+    let points: Vec<egui::Pos2> = env.trace(ENV_HOLD, dt).into_iter()
+        .map(|(t, level)| egui::pos2(to_x(t), y(level)))
+        .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.5, egui::Color32::LIGHT_YELLOW)));
+    for pos in handles(env) {
+        painter.circle_filled(pos, 4.0, egui::Color32::WHITE);
+    }
+}
+
+/// ----------  Algorithm graph ----------
+/// DX7-style chart of the routing: carriers on the bottom row feeding the
+/// output bar, each modulator one row above the highest operator it feeds.
+fn draw_algorithm(ui: &mut egui::Ui, patch: &Patch) {
+    let r = &patch.routing;
+    let n = patch.op_count();
+    if n == 0 { return; }
+
+    // Row per operator; bounded relaxation so cycles can't loop forever
+    let mut row = vec![0usize; n];
+    for _ in 0..n {
+        for dst in 0..n {
+            for src in (0..n).filter(|&src| r.modulates(dst, src) && src != dst) {
+                row[src] = row[src].max((row[dst] + 1).min(n - 1));
+            }
+        }
+    }
+
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 200.0), egui::Sense::hover());
+    let rect = response.rect;
+    let box_size = egui::vec2(48.0, 24.0);
+    let rows = row.iter().max().unwrap_or(&0) + 1;
+    let row_h = (rect.height() - 30.0) / rows as f32;
+    let centers: Vec<egui::Pos2> = (0..n).map(|i| {
+        let peers: Vec<usize> = (0..n).filter(|&j| row[j] == row[i]).collect();
+        let slot = peers.iter().position(|&j| j == i).unwrap_or(0);
+        let x = rect.left() + rect.width() * (slot as f32 + 0.5) / peers.len() as f32;
+        let y = rect.bottom() - 30.0 - row_h * (row[i] as f32 + 0.5);
+        egui::pos2(x, y)
+    }).collect();
+
+    let stroke = egui::Stroke::new(1.5, egui::Color32::LIGHT_GRAY);
+    let ring_stroke = egui::Stroke::new(1.5, egui::Color32::from_rgb(255, 160, 60));
+    let out_y = rect.bottom() - 10.0;
+    painter.hline(rect.x_range().shrink(20.0), out_y, egui::Stroke::new(3.0, egui::Color32::LIGHT_GREEN));
+    for dst in 0..n {
+        for src in (0..n).filter(|&src| r.modulates(dst, src) && src != dst) {
+            let from = centers[src] + egui::vec2(0.0, box_size.y / 2.0);
+            let to = centers[dst] - egui::vec2(0.0, box_size.y / 2.0);
+            painter.arrow(from, to - from, if r.rings(dst, src) { ring_stroke } else { stroke });
+        }
+        if r.outputs(dst) {
+            let from = centers[dst] + egui::vec2(0.0, box_size.y / 2.0);
+            painter.arrow(from, egui::vec2(0.0, out_y - from.y), stroke);
+        }
+    }
+    for (i, &c) in centers.iter().enumerate() {
+        let b = egui::Rect::from_center_size(c, box_size);
+        painter.rect(b, 4.0, egui::Color32::from_gray(40), stroke);
+        painter.text(c, egui::Align2::CENTER_CENTER, format!("Op {}", i + 1),
+                     egui::FontId::proportional(13.0), egui::Color32::WHITE);
+        // Self-feedback: a tag on the box's right edge
+        if r.modulates(i, i) || patch.ops[i].feedback > 0.0 {
+            painter.text(b.right_center() + egui::vec2(4.0, 0.0), egui::Align2::LEFT_CENTER, "↺",
+                         egui::FontId::proportional(14.0), egui::Color32::YELLOW);
+        }
+    }
+}
+
+/// ----------  Spectrum analyzer ----------
+const FFT_LEN: usize = 4096;
+
+struct Spectrum {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>, // Hann
+    input: Vec<f32>,
+    output: Vec<Complex<f32>>,
+}
+
+impl Spectrum {
+    fn new() -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_LEN);
+        let window = (0..FFT_LEN)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / FFT_LEN as f32).cos())
+            .collect();
+        Self { input: fft.make_input_vec(), output: fft.make_output_vec(), fft, window }
+    }
+
+    /// Log-frequency magnitude plot of the newest `FFT_LEN` samples, 20 Hz to
+    /// Nyquist and −100 to 0 dBFS.
+    fn draw(&mut self, ui: &mut egui::Ui, samples: &VecDeque<f32>, sr: f32) {
+        let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 140.0), egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2.0, egui::Color32::from_gray(20));
+        if samples.len() < FFT_LEN { return; }
+
+        let newest = samples.range(samples.len() - FFT_LEN..);
+        for ((x, s), w) in self.input.iter_mut().zip(newest).zip(&self.window) { *x = s * w; }
+        if self.fft.process(&mut self.input, &mut self.output).is_err() { return; }
+
+        let norm = 2.0 / self.window.iter().sum::<f32>();
+        let (lo, hi) = (20.0_f32.log10(), (sr / 2.0).log10());
+        let points: Vec<egui::Pos2> = self.output.iter().enumerate().skip(1).filter_map(|(bin, c)| {
+            let freq = bin as f32 * sr / FFT_LEN as f32;
+            if freq < 20.0 { return None; }
+            let db = (20.0 * (c.norm() * norm).max(1e-5).log10()).clamp(-100.0, 0.0);
+            let x = rect.left() + rect.width() * (freq.log10() - lo) / (hi - lo);
+            let y = rect.top() - rect.height() * db / 100.0;
+            Some(egui::pos2(x, y))
+        }).collect();
+        painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, egui::Color32::LIGHT_BLUE)));
+    }
+}
+
+/// The browser build: the same app in a canvas, playing through an
+/// AudioWorklet. Browsers hold audio back until the page is clicked.
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    let synth = FMSynth::new(44100.0); // the stream sets the context's rate
+    let patch = synth.patch.clone();
+    let (ctrl, engine) = control::channel(synth);
+    let audio = Audio::open(engine, cpal::HostId::AudioWorklet, None, None, None);
+    wasm_bindgen_futures::spawn_local(async move {
+        let app = App::new(patch, ctrl, audio, None, PathBuf::from("midi_map.json"));
+        eframe::WebRunner::new()
+            .start("fm_synth", eframe::WebOptions::default(), Box::new(move |_cc| Box::new(app)))
+            .await
+            .expect("Starting the web app failed");
+    });
+}
+
+/// ----------  Headless ----------
+/// How often the headless loop looks after MIDI devices, learned controllers
+/// and the audio device.
+#[cfg(not(target_arch = "wasm32"))]
+const HEADLESS_TICK: Duration = Duration::from_millis(10);
+
+/// Plays `patch` from MIDI input until the process is stopped. Controllers
+/// bound in the MIDI map still move their parameters, and devices coming
+/// and going are followed as in the app.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_headless(mut patch: Patch, mut ctrl: Controller, mut audio: Audio, midi_port: Option<String>, receive: Option<u8>,
+                midi_map: PathBuf) -> ! {
+    let mut midi_in = MidiIn::new(&ctrl, midi_port);
+    midi_in.receive.set(receive);
+    let mut learn = Learn::open(midi_map);
+    println!("Running headless at {} Hz; Ctrl+C quits", audio.sr);
+    loop {
+        midi_in.poll();
+        learn.handle(&mut patch, &ctrl);
+        ctrl.publish(&patch);
+        audio.poll();
+        for _ in ctrl.played() {} // nobody records them here
+        std::thread::sleep(HEADLESS_TICK);
+    }
+}
+
+/// ----------  Session ----------
+/// Where the session is kept between runs, unless `--session` says otherwise.
+#[cfg(not(target_arch = "wasm32"))]
+const SESSION_FILE: &str = "session.json";
+/// How often the app saves the session while running, besides on exit.
+const AUTOSAVE: Duration = Duration::from_secs(30);
+
+/// What the next launch picks up again: the patch being edited, the audio
+/// and MIDI setup and the window. Anything missing keeps its default.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Session {
+    patch: Option<Patch>,
+    host: Option<String>,     // audio host name
+    device: Option<String>,   // output device ID, None for the host's default
+    rate: Option<u32>,
+    buffer: Option<u32>,
+    midi_port: Option<String>,
+    receive: Option<u8>,      // MIDI channel, None for Omni
+    window: Option<[f32; 4]>, // x, y, width, height
+}
+
+impl Session {
+    /// The session saved at `path`; an empty one the first time.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load(path: &Path) -> Self {
+        let Ok(text) = std::fs::read_to_string(path) else { return Self::default() };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            eprintln!("Restoring session failed: {}", e);
+            Self::default()
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// ----------  File dialogs ----------
+/// The browser build has no file system, so there these never pick a file.
+#[cfg(not(target_arch = "wasm32"))]
+fn pick_file(filter: &str, extensions: &[&str]) -> Option<PathBuf> {
+    rfd::FileDialog::new().add_filter(filter, extensions).pick_file()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn save_file(filter: &str, extensions: &[&str]) -> Option<PathBuf> {
+    rfd::FileDialog::new().add_filter(filter, extensions).save_file()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pick_folder() -> Option<PathBuf> { rfd::FileDialog::new().pick_folder() }
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clipboard_text() -> Option<String> {
+    arboard::Clipboard::new().and_then(|mut c| c.get_text())
+        .map_err(|e| eprintln!("Reading the clipboard failed: {}", e)).ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn pick_file(_: &str, _: &[&str]) -> Option<PathBuf> { None }
+
+#[cfg(target_arch = "wasm32")]
+fn save_file(_: &str, _: &[&str]) -> Option<PathBuf> { None }
+
+#[cfg(target_arch = "wasm32")]
+fn pick_folder() -> Option<PathBuf> { None }
+
+#[cfg(target_arch = "wasm32")]
+fn clipboard_text() -> Option<String> { None }
+
+/// ----------  Main ----------
+/// Value following `flag` on the command line, e.g. `--midi <name>`.
+#[cfg(not(target_arch = "wasm32"))]
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter().position(|a| a == flag)
+        .and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // Offline rendering: no audio device, no UI. Shared options:
+    // `[--duration secs] [--bits 16|24|32] [--quality -0.2..1]`
+    let mut export = ExportOptions::default();
+    if let Some(bits) = arg_value(&args, "--bits") { export.bits = bits.parse()?; }
+    if let Some(quality) = arg_value(&args, "--quality") { export.quality = quality.parse()?; }
+    let seconds = arg_value(&args, "--duration").map(str::parse).transpose()?.unwrap_or(4.0);
+
+    // `--render out.wav|flac|ogg [--patch file.json] [--song file.mid] [--stems]`:
+    // with a song, it plays instead of a single note; with `--stems`, every
+    // operator heard at the output goes to its own file
+    if let Some(out) = arg_value(&args, "--render") {
+        let export = export.for_path(Path::new(out));
+        let mut synth = FMSynth::new(44100.0);
+        if let Some(patch) = arg_value(&args, "--patch") {
+            synth.patch = Preset::load(Path::new(patch))?.patch;
+        }
+        let song = arg_value(&args, "--song").map(|path| Song::load(Path::new(path))).transpose()?;
+        if args.iter().any(|a| a == "--stems") {
+            let written = render::render_stems(&synth.patch, synth.sample_rate(), song.as_ref(), Path::new(out), &export, seconds)?;
+            println!("Rendered {} stems", written.len());
+        } else if let Some(song) = &song {
+            render::render_song(&mut synth, song, Path::new(out), &export)?;
+        } else {
+            render::render_note(&mut synth, Path::new(out), &export, seconds)?;
+        }
+        return Ok(());
+    }
+
+    // `--render-cycle out.wav [--patch file.json] [--note 0..127] [--length samples]`:
+    // one cycle for wavetable synths
+    if let Some(out) = arg_value(&args, "--render-cycle") {
+        let patch = match arg_value(&args, "--patch") {
+            Some(path) => Preset::load(Path::new(path))?.patch,
+            None => Patch::default(),
+        };
+        let note = arg_value(&args, "--note").map(str::parse).transpose()?.unwrap_or(60);
+        let length = arg_value(&args, "--length").map(str::parse).transpose()?.unwrap_or(2048);
+        render::render_cycle(&patch, note, length, export.bits, Path::new(out))?;
+        return Ok(());
+    }
+
+    // `--render-bank <preset dir> [--out dir] [--format wav|flac|ogg]`: a demo
+    // note of every preset, each to its own file
+    if let Some(dir) = arg_value(&args, "--render-bank") {
+        if let Some(format) = arg_value(&args, "--format") {
+            export.format = AudioFormat::from_extension(format).ok_or("Unknown format")?;
+        }
+        let out = PathBuf::from(arg_value(&args, "--out").unwrap_or(dir));
+        let written = render::render_bank(Path::new(dir), &out, &export, 44100.0, seconds)?;
+        println!("Rendered {} presets to {}", written.len(), out.display());
+        return Ok(());
+    }
+
+    // `--session <file>` keeps the restored and autosaved session somewhere
+    // else; the flags below override what it restores
+    let session_path = PathBuf::from(arg_value(&args, "--session").unwrap_or(SESSION_FILE));
+    let session = Session::load(&session_path);
+
+    // `--midi <name>` picks the input port by (partial) name; the app still
+    // runs without a device and can connect one later
+    let midi_port = arg_value(&args, "--midi").map(String::from).or(session.midi_port);
+    // `--midi-map <file>` keeps the MIDI learn bindings somewhere else
+    let midi_map = PathBuf::from(arg_value(&args, "--midi-map").unwrap_or("midi_map.json"));
+
+    // `--host <name>` plays through another audio host than the system's
+    // default, e.g. ASIO or JACK (with the `asio` or `jack` feature)
+    let host = match arg_value(&args, "--host") {
+        Some(name) => cpal::available_hosts().into_iter()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown audio host {}, available: {:?}", name, cpal::available_hosts()))?,
+        None => session.host.as_deref()
+            .and_then(|name| cpal::available_hosts().into_iter().find(|id| id.name() == name))
+            .unwrap_or_else(|| cpal::default_host().id()),
+    };
+
+    // Audio thread
+    let mut synth = FMSynth::new(44100.0); // the stream sets the device's rate
+    if let Some(patch) = arg_value(&args, "--patch") {
+        synth.patch = Preset::load(Path::new(patch))?.patch;
+    } else if let Some(patch) = session.patch {
+        synth.patch = patch;
+    }
+    let patch = synth.patch.clone();
+    #[allow(unused_mut)]
+    let (ctrl, mut engine) = control::channel(synth);
+    #[cfg(feature = "link")]
+    let link = {
+        let link = fm_synth::link::Link::new(patch.tempo);
+        engine.set_link(link.clone());
+        link
+    };
+    // A device saved for another host is no use on this one
+    let device = session.device.and_then(|id| id.parse::<cpal::DeviceId>().ok()).filter(|id| id.0 == host);
+    let audio = Audio::open(engine, host, device, session.rate, session.buffer);
+
+    // `--osc <port>` takes notes (/note/on, /note/off), every parameter
+    // (/op/1/ratio, /filter/cutoff) and switch (/op/1/waveform, /delay/sync)
+    // at its address over UDP
+    let _osc = match arg_value(&args, "--osc") {
+        Some(port) => Some(fm_synth::osc::listen(port.parse()?, ctrl.sender(), ctrl.cc_sender())?),
+        None => None,
+    };
+
+    // `--headless [--patch file.json]`: no window, just the engine played
+    // from MIDI input, for synth boxes without a screen
+    if args.iter().any(|a| a == "--headless") { run_headless(patch, ctrl, audio, midi_port, session.receive, midi_map); }
+
+    // UI thread
+    let presets = arg_value(&args, "--presets").map(PathBuf::from);
+    let mut native_options = eframe::NativeOptions::default();
+    if let Some([x, y, width, height]) = session.window {
+        native_options.viewport = native_options.viewport.with_position([x, y]).with_inner_size([width, height]);
+    }
+    eframe::run_native(
+        "FM Synth Beast",
+        native_options,
+        Box::new(move |_cc| {
+            #[allow(unused_mut)]
+            let mut app = App::new(patch, ctrl, audio, midi_port, midi_map);
+            app.midi_in.receive.set(session.receive);
+            app.session = Some(session_path);
+            // `--presets <dir>` browses another folder than ./presets
+            if let Some(dir) = presets { app.browser = PresetBrowser::new(dir); }
+            #[cfg(feature = "link")]
+            { app.link = Some(link); }
+            Box::new(app)
+        }),
+    )?;
+
+    Ok(())
+}
+
+/// ----------  Audio output ----------
+/// The output stream and the device it plays on. The engine sits behind a
+/// mutex only so the next stream can take it over: streams never overlap,
+/// so the callback always finds it free.
+struct Audio {
+    engine: Arc<Mutex<Engine>>,
+    stream: Option<cpal::Stream>,
+    broken: Arc<AtomicBool>, // set by the stream's error callback once the device is gone
+    retry: Instant,          // when to try again while there is no stream
+    host: cpal::HostId,
+    device: Option<cpal::DeviceId>, // None follows the host's default
+    devices: Vec<(cpal::DeviceId, String)>, // the host's outputs as last scanned
+    rate: Option<u32>,   // requested sample rate, None for the device's own
+    buffer: Option<u32>, // requested frames per callback, None for the host's choice
+    rates: Vec<u32>,     // of SAMPLE_RATES, those the device supports
+    buffers: Vec<u32>,   // of BUFFER_SIZES, the same
+    #[cfg(feature = "jack")]
+    jack_connect: bool,  // wire the JACK ports to the system outputs
+    sr: f32,
+    channels: usize,
+}
+
+impl Audio {
+    /// Starts playing on `host`'s `device` (its default if `None`) at the
+    /// requested rate and buffer size, or keeps trying to if it isn't there.
+    fn open(engine: Engine, host: cpal::HostId, device: Option<cpal::DeviceId>, rate: Option<u32>, buffer: Option<u32>) -> Self {
+        let mut audio = Self { engine: Arc::new(Mutex::new(engine)), stream: None, broken: Arc::new(AtomicBool::new(false)),
+                               retry: Instant::now(), host, device,
+                               devices: Vec::new(), rate, buffer, rates: Vec::new(), buffers: Vec::new(),
+                               #[cfg(feature = "jack")] jack_connect: true, sr: 44100.0, channels: 2 };
+        if let Err(e) = audio.restart() {
+            eprintln!("Opening audio device failed: {}", e);
+            audio.retry = Instant::now() + AUDIO_RETRY;
+        }
+        audio.scan();
+        audio
+    }
+
+    /// Lists the current host's output devices.
+    fn scan(&mut self) {
+        let devices = cpal::host_from_id(self.host).ok().and_then(|h| h.output_devices().ok());
+        self.devices = devices.into_iter().flatten()
+            .filter_map(|d| Some((d.id().ok()?, d.description().ok()?.name().to_string())))
+            .collect();
+    }
+
+    /// Replaces the stream with one on the chosen host and device.
+    fn restart(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.stream = None; // the old stream lets go of the engine first
+        let host = cpal::host_from_id(self.host)?;
+        let device = match &self.device {
+            #[cfg(feature = "jack")]
+            _ if self.host == cpal::HostId::Jack => jack_device(self.jack_connect)?,
+            Some(id) => host.device_by_id(id).ok_or("Output device not found")?,
+            None => host.default_output_device().ok_or("No default output device")?,
+        };
+        // Other rates in the default layout and format; a rate the device
+        // lacks falls back to its own
+        let default = device.default_output_config()?;
+        let ranges: Vec<_> = device.supported_output_configs()?
+            .filter(|r| r.channels() == default.channels() && r.sample_format() == default.sample_format())
+            .collect();
+        self.rates = SAMPLE_RATES.into_iter()
+            .filter(|&sr| ranges.iter().any(|r| (r.min_sample_rate()..=r.max_sample_rate()).contains(&sr)))
+            .collect();
+        let supported = match self.rate {
+            Some(sr) => ranges.into_iter().find_map(|r| r.try_with_sample_rate(sr)).unwrap_or(default),
+            None => default,
+        };
+        let mut config = supported.config();
+        let frames = match *supported.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => min..=max,
+            cpal::SupportedBufferSize::Unknown => 0..=u32::MAX,
+        };
+        self.buffers = BUFFER_SIZES.into_iter().filter(|n| frames.contains(n)).collect();
+        if let Some(n) = self.buffer {
+            config.buffer_size = cpal::BufferSize::Fixed(n.clamp(*frames.start(), *frames.end()));
+        }
+
+        self.sr = config.sample_rate as f32;
+        self.channels = config.channels as usize;
+        self.engine.lock().unwrap_or_else(|e| e.into_inner()).set_sample_rate(self.sr);
+        self.broken.store(false, Ordering::Relaxed);
+        let stream = build_stream(&device, &config, supported.sample_format(), self.engine.clone(), self.broken.clone())?;
+        stream.play()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Starts a stream the browser kept suspended, which it allows once the
+    /// page has been clicked.
+    #[cfg(target_arch = "wasm32")]
+    fn resume(&self) {
+        if let Some(stream) = &self.stream { let _ = stream.play(); }
+    }
+
+    /// Rebuilds the stream once its device has gone (say, a USB interface
+    /// was unplugged), on the chosen device if it is still there and the
+    /// default one otherwise, retrying while neither is. Returns whether
+    /// the stream was rebuilt.
+    fn poll(&mut self) -> bool {
+        let broken = self.broken.swap(false, Ordering::Relaxed);
+        if !broken && (self.stream.is_some() || Instant::now() < self.retry) { return false; }
+        if broken { eprintln!("Audio device lost, reopening"); }
+        let mut result = self.restart();
+        if result.is_err() && self.device.is_some() {
+            self.device = None;
+            result = self.restart();
+        }
+        self.scan();
+        if let Err(e) = result {
+            self.stream = None;
+            self.retry = Instant::now() + AUDIO_RETRY;
+            if broken { eprintln!("Opening audio device failed: {}", e); }
+            return false;
+        }
+        true
+    }
+
+    /// Host, device, sample rate and buffer size pickers. Returns whether
+    /// the stream was rebuilt, possibly at another sample rate.
+    fn editor(&mut self, ui: &mut egui::Ui) -> bool {
+        let (host, device, rate, buffer) = (self.host, self.device.clone(), self.rate, self.buffer);
+        #[cfg(feature = "jack")]
+        let jack_connect = self.jack_connect;
+        ui.horizontal(|ui| {
+            ui.label("Host:");
+            egui::ComboBox::from_id_source("audio_host").selected_text(self.host.name()).show_ui(ui, |ui| {
+                for id in cpal::available_hosts() { ui.selectable_value(&mut self.host, id, id.name()); }
+            });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Device:");
+            let selected = match &self.device {
+                Some(id) => self.devices.iter().find(|(d, _)| d == id).map_or("(missing)", |(_, name)| name.as_str()),
+                None => "Default",
+            };
+            egui::ComboBox::from_id_source("audio_device").selected_text(selected).show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.device, None, "Default");
+                for (id, name) in &self.devices { ui.selectable_value(&mut self.device, Some(id.clone()), name); }
+            });
+            if ui.button("Rescan").clicked() { self.scan(); }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Sample rate:");
+            egui::ComboBox::from_id_source("audio_rate")
+                .selected_text(self.rate.map_or("Device default".into(), |sr| format!("{} Hz", sr)))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.rate, None, "Device default");
+                    for &sr in &self.rates { ui.selectable_value(&mut self.rate, Some(sr), format!("{} Hz", sr)); }
+                });
+            ui.label("Buffer:");
+            let sr = self.sr;
+            let frames = |n: u32| format!("{} ({:.1} ms)", n, n as f32 / sr * 1000.0);
+            egui::ComboBox::from_id_source("audio_buffer")
+                .selected_text(self.buffer.map_or("Default".into(), frames))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.buffer, None, "Default");
+                    for &n in &self.buffers { ui.selectable_value(&mut self.buffer, Some(n), frames(n)); }
+                });
+        });
+        ui.label(match self.stream {
+            Some(_) => format!("Playing at {} Hz, {} channels", self.sr, self.channels),
+            None => "Not playing".into(),
+        });
+        if self.host.name() == "ASIO" {
+            ui.label("ASIO: the default buffer is the one set in the driver's control panel, and only one driver can be open at a time.");
+        }
+        #[cfg(feature = "jack")]
+        if self.host == cpal::HostId::Jack {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.jack_connect, "Connect to system outputs");
+                ui.label(format!("Ports: {}_out:out_0 … out_{}", JACK_CLIENT, self.channels.saturating_sub(1)));
+            });
+            if self.jack_connect != jack_connect {
+                if let Err(e) = self.restart() { eprintln!("Opening audio device failed: {}", e); }
+                return true;
+            }
+        }
+        if (self.host, &self.device, self.rate, self.buffer) == (host, &device, rate, buffer) { return false; }
+        if self.host != host {
+            self.device = None;
+            self.scan();
+        }
+        if let Err(e) = self.restart() { eprintln!("Opening audio device failed: {}", e); }
+        true
+    }
+}
+
+/// JACK has no devices of its own: the stream registers a client named
+/// after the synth, with one output port per channel, to be routed like
+/// any other in the session graph.
+#[cfg(feature = "jack")]
+fn jack_device(connect: bool) -> Result<cpal::Device, Box<dyn std::error::Error>> {
+    let mut host = cpal::platform::JackHost::new()?;
+    host.set_connect_automatically(connect);
+    Ok(host.output_device_with_name(JACK_CLIENT).ok_or("JACK server not running")?.into())
+}
+
+/// JACK client name; cpal appends `_out`.
+#[cfg(feature = "jack")]
+const JACK_CLIENT: &str = "fm_synth";
+
+/// Sample rates and buffer sizes offered, where the device supports them.
+const SAMPLE_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+const BUFFER_SIZES: [u32; 7] = [32, 64, 128, 256, 512, 1024, 2048];
+
+/// How often to look for a device again while none can be opened.
+const AUDIO_RETRY: Duration = Duration::from_secs(1);
+
+/// An output stream on `device` with the engine rendering into it. The
+/// integer formats reuse one scratch buffer, so nothing is allocated once
+/// it has grown to the block size.
+/// A lost device or a stream that needs rebuilding raises `broken`.
+fn build_stream(device: &cpal::Device, config: &cpal::StreamConfig, format: cpal::SampleFormat,
+                engine: Arc<Mutex<Engine>>, broken: Arc<AtomicBool>) -> Result<cpal::Stream, Box<dyn std::error::Error>> {
+    let channels = config.channels as usize;
+    let render = move |out: &mut [f32]| match engine.try_lock() {
+        Ok(mut engine) => engine.render_block(out, channels),
+        Err(_) => out.fill(0.0),
+    };
+    let err_fn = move |err: cpal::StreamError| {
+        if matches!(err, cpal::StreamError::DeviceNotAvailable | cpal::StreamError::StreamInvalidated) {
+            broken.store(true, Ordering::Relaxed);
+        }
+        err_fn(err);
+    };
+    let mut buf: Vec<f32> = Vec::new();
+    let stream = match format {
+        cpal::SampleFormat::F32 => device.build_output_stream(
+            config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| render(data),
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::I16 => device.build_output_stream(
+            config,
+            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                buf.resize(data.len(), 0.0);
+                render(&mut buf);
+                for (s, out) in buf.iter().zip(data.iter_mut()) {
+                    *out = (*s * i16::MAX as f32) as i16;
+                }
+            },
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::U16 => device.build_output_stream(
+            config,
+            move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
+                buf.resize(data.len(), 0.0);
+                render(&mut buf);
+                for (s, out) in buf.iter().zip(data.iter_mut()) {
+                    *out = ((*s * i16::MAX as f32) as i32 + 32768) as u16;
+                }
+            },
+            err_fn,
+            None,
+        )?,
+        // What most ASIO drivers ask for
+        cpal::SampleFormat::I32 => device.build_output_stream(
+            config,
+            move |data: &mut [i32], _: &cpal::OutputCallbackInfo| {
+                buf.resize(data.len(), 0.0);
+                render(&mut buf);
+                for (s, out) in buf.iter().zip(data.iter_mut()) {
+                    *out = (*s as f64 * i32::MAX as f64) as i32;
+                }
+            },
+            err_fn,
+            None,
+        )?,
+        format => return Err(format!("Unsupported sample format {}", format).into()),
+    };
+    Ok(stream)
 }
 
 /// ----------  Error callback ----------
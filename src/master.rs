@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Master output stage ----------
+/// How peaks above full scale are rounded off after the effects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ClipCurve {
+    Hard,     // straight clamp at ±1
+    #[default]
+    SoftKnee, // untouched up to the knee, then bending smoothly into ±1
+    Tanh,     // saturates gradually from the start
+}
+
+impl ClipCurve {
+    pub const ALL: [ClipCurve; 3] = [ClipCurve::Hard, ClipCurve::SoftKnee, ClipCurve::Tanh];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ClipCurve::Hard => "Hard",
+            ClipCurve::SoftKnee => "Soft Knee",
+            ClipCurve::Tanh => "Tanh",
+        }
+    }
+
+    pub fn apply(self, x: f32) -> f32 {
+        match self {
+            ClipCurve::Hard => x.clamp(-1.0, 1.0),
+            ClipCurve::SoftKnee => {
+                let over = x.abs() - SOFT_KNEE;
+                if over <= 0.0 { x } else { x.signum() * (SOFT_KNEE + (1.0 - SOFT_KNEE) * (over / (1.0 - SOFT_KNEE)).tanh()) }
+            }
+            ClipCurve::Tanh => x.tanh(),
+        }
+    }
+}
+
+/// Level the soft knee starts bending at.
+const SOFT_KNEE: f32 = 0.8;
+
+/// Master bus settings: the output volume, an optional lookahead limiter,
+/// then the clip curve.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Master {
+    #[serde(default)]
+    pub gain: f32,    // dB, -60..+12
+    pub limiter: bool,
+    pub ceiling: f32, // dB, -12..0, the limiter's maximum output
+    pub curve: ClipCurve,
+}
+
+impl Default for Master {
+    fn default() -> Self { Self { gain: 0.0, limiter: false, ceiling: -0.3, curve: ClipCurve::default() } }
+}
+
+/// Limiter lookahead and release time, in seconds.
+const LOOKAHEAD: f32 = 0.005;
+const RELEASE: f32 = 0.1;
+
+/// Stereo-linked lookahead limiter. The signal is delayed by the
+/// lookahead, so the gain can ramp down in time to meet a peak rather than
+/// clip its leading edge.
+pub(crate) struct Limiter {
+    delay: [Vec<f32>; 2],
+    pos: usize,
+    gain: f32,   // applied to the delayed signal
+    target: f32, // lowest gain a peak in the lookahead needs
+    step: f32,   // per-sample ramp down towards `target`
+    hold: usize, // samples until the peak setting `target` has passed
+    release: f32,
+    was_on: bool,
+    clipped: [bool; 2], // per channel: a sample reached the clip curve above full scale
+}
+
+impl Limiter {
+    pub fn new(sr: f32) -> Self {
+        let len = ((LOOKAHEAD * sr) as usize).max(1);
+        Self { delay: [vec![0.0; len], vec![0.0; len]], pos: 0, gain: 1.0, target: 1.0, step: 0.0, hold: 0,
+               release: 1.0 - (-1.0 / (RELEASE * sr)).exp(), was_on: false, clipped: [false; 2] }
+    }
+
+    /// Whether each channel went over full scale since the last call.
+    pub fn take_clips(&mut self) -> [bool; 2] { std::mem::take(&mut self.clipped) }
+
+    /// Runs the output stage over a stereo block in place, leaving every
+    /// sample within ±1.
+    pub fn process(&mut self, settings: &Master, l: &mut [f32], r: &mut [f32]) {
+        let gain = 10f32.powf(settings.gain.clamp(-60.0, 12.0) / 20.0);
+        if gain != 1.0 {
+            for s in l.iter_mut().chain(r.iter_mut()) { *s *= gain; }
+        }
+        if settings.limiter {
+            if !self.was_on {
+                for d in &mut self.delay { d.fill(0.0); }
+                (self.gain, self.target, self.step, self.hold) = (1.0, 1.0, 0.0, 0);
+                self.was_on = true;
+            }
+            let ceiling = 10f32.powf(settings.ceiling.clamp(-12.0, 0.0) / 20.0);
+            let len = self.delay[0].len();
+            for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+                let peak = a.abs().max(b.abs());
+                let needed = if peak > ceiling { ceiling / peak } else { 1.0 };
+                if needed <= self.target {
+                    // Never slower than a ramp already under way, which has
+                    // an earlier peak to meet
+                    let step = (self.gain - needed).max(0.0) / len as f32;
+                    self.step = if self.gain > self.target { self.step.max(step) } else { step };
+                    self.target = needed;
+                    self.hold = len;
+                } else if self.hold > 0 {
+                    self.hold -= 1;
+                } else {
+                    self.target += (1.0 - self.target) * self.release;
+                }
+                self.gain = if self.gain > self.target { (self.gain - self.step).max(self.target) } else { self.target };
+                let (da, db) = (self.delay[0][self.pos], self.delay[1][self.pos]);
+                self.delay[0][self.pos] = *a;
+                self.delay[1][self.pos] = *b;
+                self.pos = (self.pos + 1) % len;
+                *a = da * self.gain;
+                *b = db * self.gain;
+            }
+        } else {
+            self.was_on = false;
+        }
+        for (ch, buf) in [l, r].into_iter().enumerate() {
+            for s in buf.iter_mut() {
+                self.clipped[ch] |= s.abs() > 1.0;
+                *s = settings.curve.apply(*s).clamp(-1.0, 1.0);
+            }
+        }
+    }
+}
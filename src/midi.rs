@@ -0,0 +1,239 @@
+use crossbeam_channel::Sender;
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use crate::params::Param;
+use crate::{Event, Patch};
+
+/// ----------  MIDI messages ----------
+/// Decodes a raw channel message; anything we don't handle yields `None`.
+pub fn parse(msg: &[u8]) -> Option<Event> {
+    let (&status, data) = msg.split_first()?;
+    let channel = status & 0x0F;
+    match (status & 0xF0, data) {
+        (0x90, &[note, 0, ..]) => Some(Event::NoteOff { note }),
+        (0x90, &[note, velocity, ..]) => Some(Event::NoteOn { note, velocity, channel }),
+        (0x80, &[note, _, ..]) => Some(Event::NoteOff { note }),
+        (0xB0, &[1, value, ..]) => Some(Event::ModWheel(value as f32 / 127.0)),
+        (0xB0, &[64, value, ..]) => Some(Event::Sustain(value >= 64)),
+        (0xB0, &[74, value, ..]) => Some(Event::Slide { channel, value: value as f32 / 127.0 }),
+        (0xB0, &[cc, value, ..]) => Some(Event::ControlChange { channel, cc, value }),
+        (0xD0, &[pressure, ..]) => Some(Event::ChannelPressure { channel, value: pressure as f32 / 127.0 }),
+        (0xF0, _) => match status {
+            0xFA => Some(Event::Start),
+            0xFB => Some(Event::Continue),
+            0xFC => Some(Event::Stop),
+            _ => None, // clock pulses are timed by `forward`
+        },
+        (0xE0, &[lsb, msb, ..]) => {
+            let value = ((msb as i32) << 7 | lsb as i32) - 8192;
+            Some(Event::ChannelBend { channel, value: value as f32 / 8192.0 })
+        }
+        _ => None,
+    }
+}
+
+/// Raw bytes for `event`, the reverse of `parse`. Events without a channel
+/// go out on the first; the UI gate, clock, transport and remote parameter
+/// changes yield `None`.
+pub fn encode(event: Event) -> Option<Vec<u8>> {
+    let scale = |v: f32| (v.clamp(0.0, 1.0) * 127.0).round() as u8;
+    let bend = |channel: u8, v: f32| {
+        let value = ((v.clamp(-1.0, 1.0) * 8192.0) as i32 + 8192).clamp(0, 16383);
+        vec![0xE0 | channel & 0x0F, (value & 0x7F) as u8, (value >> 7) as u8]
+    };
+    Some(match event {
+        Event::NoteOn { note, velocity, channel } => vec![0x90 | channel & 0x0F, note & 0x7F, velocity.clamp(1, 127)],
+        Event::NoteOff { note } => vec![0x80, note & 0x7F, 0],
+        Event::ModWheel(v) => vec![0xB0, 1, scale(v)],
+        Event::Sustain(on) => vec![0xB0, 64, if on { 127 } else { 0 }],
+        Event::PitchBend(v) => bend(0, v),
+        Event::ChannelBend { channel, value } => bend(channel, value),
+        Event::ChannelPressure { channel, value } => vec![0xD0 | channel & 0x0F, scale(value)],
+        Event::Slide { channel, value } => vec![0xB0 | channel & 0x0F, 74, scale(value)],
+        Event::ControlChange { channel, cc, value } => vec![0xB0 | channel & 0x0F, cc & 0x7F, value & 0x7F],
+        Event::Gate(_) | Event::ClockTempo(_) | Event::Start | Event::Continue | Event::Stop | Event::SetParam(..)
+        | Event::SetSwitch(..) => {
+            return None
+        }
+    })
+}
+
+/// ----------  MIDI clock ----------
+const CLOCK: u8 = 0xF8;
+/// Clock pulses per quarter note.
+const PPQN: f64 = 24.0;
+/// Longest pulse gap, in µs, still taken as a running clock (10 BPM).
+const MAX_PULSE_GAP: f64 = 250_000.0;
+
+/// Turns clock pulse timestamps into a steady tempo.
+#[derive(Default)]
+struct ClockFollower {
+    last: Option<u64>, // µs
+    period: f64,       // smoothed µs per pulse, 0 until measured
+}
+
+impl ClockFollower {
+    fn pulse(&mut self, stamp: u64) -> Option<f32> {
+        let gap = stamp.saturating_sub(self.last.replace(stamp)?) as f64;
+        if gap <= 0.0 || gap > MAX_PULSE_GAP {
+            self.period = 0.0;
+            return None;
+        }
+        self.period = if self.period == 0.0 { gap } else { self.period + (gap - self.period) * 0.1 };
+        Some((60e6 / (self.period * PPQN)) as f32)
+    }
+}
+
+/// ----------  Receive channel ----------
+const OMNI: u8 = 16;
+
+/// The channel the input listens to, shared between the UI and the MIDI
+/// thread. Starts in Omni mode, which takes every channel.
+#[derive(Clone)]
+pub struct ReceiveChannel(Arc<AtomicU8>);
+
+impl Default for ReceiveChannel {
+    fn default() -> Self { Self(Arc::new(AtomicU8::new(OMNI))) }
+}
+
+impl ReceiveChannel {
+    /// 0-based channel, or `None` for Omni.
+    pub fn get(&self) -> Option<u8> {
+        Some(self.0.load(Ordering::Relaxed)).filter(|&c| c != OMNI)
+    }
+
+    pub fn set(&self, channel: Option<u8>) {
+        self.0.store(channel.map_or(OMNI, |c| c & 0x0F), Ordering::Relaxed);
+    }
+
+    /// System messages always pass; channel messages only on our channel.
+    fn accepts(&self, msg: &[u8]) -> bool {
+        match (msg.first(), self.get()) {
+            (Some(&status), Some(channel)) if status < 0xF0 => status & 0x0F == channel,
+            _ => true,
+        }
+    }
+}
+
+/// ----------  Input connection ----------
+/// Names of the MIDI input ports present right now.
+pub fn ports() -> Result<Vec<String>, Box<dyn Error>> {
+    let input = MidiInput::new("FM Synth Beast")?;
+    Ok(input.ports().iter().filter_map(|p| input.port_name(p).ok()).collect())
+}
+
+/// An open input port; input stops when it is dropped.
+pub struct Connection {
+    pub port: String,
+    _conn: MidiInputConnection<()>,
+}
+
+/// Opens the first input port whose name contains `port` (or simply the first
+/// port) and forwards what `parse` understands as synth events, controller
+/// changes to `controls` and everything else to `events`, dropping messages
+/// on channels `receive` does not listen to.
+pub fn connect(port: Option<&str>, events: Sender<Event>, controls: Sender<Event>, receive: ReceiveChannel)
+    -> Result<Connection, Box<dyn Error>>
+{
+    let mut input = MidiInput::new("FM Synth Beast")?;
+    input.ignore(Ignore::SysexAndActiveSense); // keep clock and transport
+
+    let ports = input.ports();
+    let port = ports.iter()
+        .find(|p| match port {
+            Some(wanted) => input.port_name(p).map(|n| n.contains(wanted)).unwrap_or(false),
+            None => true,
+        })
+        .ok_or("No matching MIDI input port")?;
+    let name = input.port_name(port)?;
+    println!("MIDI input: {}", name);
+
+    let conn = input.connect(port, "fm-synth-in", forward(events, controls, receive), ())
+        .map_err(|e| e.to_string())?;
+    Ok(Connection { port: name, _conn: conn })
+}
+
+/// Name other applications see the virtual port by.
+pub const VIRTUAL_PORT: &str = "FM Synth Beast In";
+
+/// Creates a virtual input port for other software (a DAW, a sequencer) to
+/// play into, forwarding like `connect`. Needs ALSA or CoreMIDI.
+#[cfg(unix)]
+pub fn create_virtual(events: Sender<Event>, controls: Sender<Event>, receive: ReceiveChannel)
+    -> Result<Connection, Box<dyn Error>>
+{
+    use midir::os::unix::VirtualInput;
+    let mut input = MidiInput::new("FM Synth Beast")?;
+    input.ignore(Ignore::SysexAndActiveSense); // keep clock and transport
+    let conn = input.create_virtual(VIRTUAL_PORT, forward(events, controls, receive), ())
+        .map_err(|e| e.to_string())?;
+    Ok(Connection { port: VIRTUAL_PORT.into(), _conn: conn })
+}
+
+/// The input callback: filters by channel, sorts events by destination and
+/// turns clock pulses into tempo updates.
+fn forward(events: Sender<Event>, controls: Sender<Event>, receive: ReceiveChannel)
+    -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static
+{
+    let mut clock = ClockFollower::default();
+    move |stamp, msg, _| {
+        if msg.first() == Some(&CLOCK) {
+            if let Some(bpm) = clock.pulse(stamp) { let _ = events.try_send(Event::ClockTempo(bpm)); }
+            return;
+        }
+        if !receive.accepts(msg) { return; }
+        match parse(msg) {
+            Some(event @ Event::ControlChange { .. }) => { let _ = controls.try_send(event); }
+            Some(event) => { let _ = events.try_send(event); }
+            None => {}
+        }
+    }
+}
+
+/// ----------  CC mapping ----------
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CcBinding {
+    pub cc: u8,
+    pub param: Param,
+}
+
+/// Controller numbers bound to patch parameters, e.g. by MIDI learn. Stored
+/// as JSON so a controller setup can be kept and shared.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CcMap {
+    pub bindings: Vec<CcBinding>,
+}
+
+impl CcMap {
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Binds `cc` to `param`, replacing whatever either was bound to.
+    pub fn bind(&mut self, cc: u8, param: Param) {
+        self.bindings.retain(|b| b.cc != cc && b.param != param);
+        self.bindings.push(CcBinding { cc, param });
+    }
+
+    pub fn unbind(&mut self, param: Param) { self.bindings.retain(|b| b.param != param); }
+
+    pub fn cc_for(&self, param: Param) -> Option<u8> {
+        self.bindings.iter().find(|b| b.param == param).map(|b| b.cc)
+    }
+
+    /// Moves every parameter bound to `cc` to `value` (0..127).
+    pub fn apply(&self, patch: &mut Patch, cc: u8, value: u8) {
+        for b in self.bindings.iter().filter(|b| b.cc == cc) { b.param.set(patch, value as f32 / 127.0); }
+    }
+}
@@ -0,0 +1,126 @@
+use crossbeam_channel::Sender;
+use midly::live::LiveEvent;
+use midly::{Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{midi, Event};
+
+/// ----------  Standard MIDI files ----------
+/// Tempo until a file sets one, µs per quarter note (120 BPM).
+const DEFAULT_TEMPO: f64 = 500_000.0;
+/// Resolution of the files we write.
+const TICKS_PER_BEAT: u16 = 480;
+
+/// The events of a MIDI file, every track merged, timed in seconds.
+#[derive(Default)]
+pub struct Song {
+    pub events: Vec<(f64, Event)>,
+}
+
+impl Song {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> { Self::parse(&fs::read(path)?) }
+
+    /// Decodes a file of any format, following its tempo changes.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let smf = Smf::parse(bytes)?;
+        let mut timed: Vec<(u64, TrackEventKind)> = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for ev in track {
+                tick += ev.delta.as_int() as u64;
+                timed.push((tick, ev.kind));
+            }
+        }
+        timed.sort_by_key(|&(tick, _)| tick); // stable, so each track keeps its order
+
+        let (mut seconds, mut last, mut tempo) = (0.0, 0u64, DEFAULT_TEMPO);
+        let mut events = Vec::new();
+        for (tick, kind) in timed {
+            let per_tick = match smf.header.timing {
+                Timing::Metrical(tpq) => tempo / 1e6 / tpq.as_int().max(1) as f64,
+                Timing::Timecode(fps, sub) => 1.0 / (fps.as_f32() as f64 * sub.max(1) as f64),
+            };
+            seconds += (tick - last) as f64 * per_tick;
+            last = tick;
+            if let TrackEventKind::Meta(MetaMessage::Tempo(t)) = kind { tempo = t.as_int() as f64; }
+            let Some(live) = kind.as_live_event() else { continue };
+            let mut raw = Vec::with_capacity(3);
+            live.write_std(&mut raw)?;
+            if let Some(event) = midi::parse(&raw) { events.push((seconds, event)); }
+        }
+        Ok(Self { events })
+    }
+
+    /// Writes a single-track file at 120 BPM; events are sorted by time.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let per_second = TICKS_PER_BEAT as f64 * 1e6 / DEFAULT_TEMPO;
+        let raw: Vec<(u64, Vec<u8>)> = self.events.iter()
+            .filter_map(|&(t, e)| Some(((t.max(0.0) * per_second).round() as u64, midi::encode(e)?)))
+            .collect();
+        let mut track = vec![TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::Tempo((DEFAULT_TEMPO as u32).into())) }];
+        let mut last = 0;
+        for (tick, bytes) in &raw {
+            let LiveEvent::Midi { channel, message } = LiveEvent::parse(bytes)? else { continue };
+            track.push(TrackEvent { delta: (tick.saturating_sub(last) as u32).into(), kind: TrackEventKind::Midi { channel, message } });
+            last = last.max(*tick);
+        }
+        track.push(TrackEvent { delta: 0.into(), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(TICKS_PER_BEAT.into())));
+        smf.tracks.push(track);
+        smf.save(path)?;
+        Ok(())
+    }
+
+    /// Seconds until the last event.
+    pub fn duration(&self) -> f64 { self.events.last().map_or(0.0, |&(t, _)| t) }
+}
+
+/// ----------  Live playback ----------
+/// Longest the player sleeps before checking whether it was stopped.
+const POLL: Duration = Duration::from_millis(10);
+
+/// A song playing on its own thread; stops when dropped.
+pub struct Playback {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Playback {
+    pub fn is_finished(&self) -> bool { self.thread.as_ref().is_none_or(JoinHandle::is_finished) }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() { let _ = thread.join(); }
+    }
+}
+
+/// Sends `song`'s events to `events` as their times come, like a MIDI input
+/// would, and lets go of every note at the end or when stopped.
+pub fn play(song: Arc<Song>, events: Sender<Event>) -> Playback {
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    let thread = thread::spawn(move || {
+        let start = Instant::now();
+        'song: for &(t, event) in &song.events {
+            let due = start + Duration::from_secs_f64(t);
+            loop {
+                if flag.load(Ordering::Relaxed) { break 'song; }
+                let now = Instant::now();
+                if now >= due { break; }
+                thread::sleep((due - now).min(POLL));
+            }
+            let _ = events.send_timeout(event, POLL);
+        }
+        let _ = events.send_timeout(Event::Sustain(false), POLL);
+        for note in 0..128 { let _ = events.send_timeout(Event::NoteOff { note }, POLL); }
+    });
+    Playback { stop, thread: Some(thread) }
+}
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{PI, TAU};
+
+/// ----------  Modulation effects ----------
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ModFxKind {
+    #[default]
+    Chorus,  // a slowly swept delay around 15 ms, no feedback
+    Flanger, // a short swept delay fed back on itself
+    Phaser,  // a swept allpass cascade mixed with the dry signal
+}
+
+impl ModFxKind {
+    pub const ALL: [ModFxKind; 3] = [ModFxKind::Chorus, ModFxKind::Flanger, ModFxKind::Phaser];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModFxKind::Chorus => "Chorus",
+            ModFxKind::Flanger => "Flanger",
+            ModFxKind::Phaser => "Phaser",
+        }
+    }
+}
+
+/// Modulation effect settings.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ModFx {
+    pub on: bool,
+    pub kind: ModFxKind,
+    pub rate: f32,     // sweep rate, Hz
+    pub depth: f32,    // 0..1, how far the sweep reaches
+    pub feedback: f32, // 0..0.9, flanger and phaser only
+    pub mix: f32,      // 0..1, dry to wet
+}
+
+impl Default for ModFx {
+    fn default() -> Self { Self { on: false, kind: ModFxKind::Chorus, rate: 0.5, depth: 0.5, feedback: 0.5, mix: 0.5 } }
+}
+
+/// Chorus delay at rest and its sweep, in seconds.
+const CHORUS_DELAY: f32 = 0.015;
+const CHORUS_SWEEP: f32 = 0.005;
+/// Flanger's shortest delay and its sweep, in seconds.
+const FLANGER_DELAY: f32 = 0.0005;
+const FLANGER_SWEEP: f32 = 0.004;
+/// Longest delay any algorithm reads.
+const MAX_MOD_DELAY: f32 = 0.025;
+/// The phaser's allpass stages per channel and the range their corner
+/// frequencies sweep over, in Hz.
+const PHASER_STAGES: usize = 6;
+const PHASER_LOW: f32 = 200.0;
+const PHASER_HIGH: f32 = 4000.0;
+
+/// The running effect: delay lines for chorus and flanger, allpass states
+/// for the phaser, and the sweep LFO. The right channel's sweep runs a
+/// quarter cycle behind the left's for width.
+pub(crate) struct ModulationFx {
+    lines: [Vec<f32>; 2],
+    pos: usize,
+    allpass: [[f32; PHASER_STAGES]; 2],
+    last: [f32; 2], // last wet output, fed back
+    phase: f32,
+    kind: ModFxKind,
+    was_on: bool,
+    sr: f32,
+}
+
+impl ModulationFx {
+    pub fn new(sr: f32) -> Self {
+        let len = (MAX_MOD_DELAY * sr) as usize + 2;
+        Self { lines: [vec![0.0; len], vec![0.0; len]], pos: 0, allpass: [[0.0; PHASER_STAGES]; 2],
+               last: [0.0; 2], phase: 0.0, kind: ModFxKind::default(), was_on: false, sr }
+    }
+
+    fn clear(&mut self) {
+        for line in &mut self.lines { line.fill(0.0); }
+        self.allpass = [[0.0; PHASER_STAGES]; 2];
+        self.last = [0.0; 2];
+    }
+
+    /// Sample `delay` seconds back on line `ch`, linearly interpolated.
+    fn tap(&self, ch: usize, delay: f32) -> f32 {
+        let line = &self.lines[ch];
+        let len = line.len();
+        let back = (delay * self.sr).clamp(1.0, (len - 2) as f32);
+        let (whole, frac) = (back as usize, back.fract());
+        let a = line[(self.pos + len - whole) % len];
+        let b = line[(self.pos + len - whole - 1) % len];
+        a + (b - a) * frac
+    }
+
+    /// One sample through the phaser's allpass cascade on channel `ch`,
+    /// with every stage's corner at `freq`.
+    fn phase_shift(&mut self, ch: usize, input: f32, freq: f32) -> f32 {
+        let t = (PI * freq / self.sr).tan();
+        let a = (t - 1.0) / (t + 1.0);
+        let mut x = input;
+        for z in &mut self.allpass[ch] {
+            let y = a * x + *z;
+            *z = x - a * y;
+            x = y;
+        }
+        x
+    }
+
+    /// Runs the effect over a stereo block in place.
+    pub fn process(&mut self, settings: &ModFx, l: &mut [f32], r: &mut [f32]) {
+        if !settings.on { self.was_on = false; return; }
+        if !self.was_on || self.kind != settings.kind { self.clear(); }
+        self.was_on = true;
+        self.kind = settings.kind;
+        let depth = settings.depth.clamp(0.0, 1.0);
+        let feedback = if settings.kind == ModFxKind::Chorus { 0.0 } else { settings.feedback.clamp(0.0, 0.9) };
+        let mix = settings.mix.clamp(0.0, 1.0);
+        let inc = settings.rate.max(0.0) / self.sr;
+        let len = self.lines[0].len();
+        for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+            self.phase = (self.phase + inc).fract();
+            let dry = [*a, *b];
+            let mut wet = [0.0; 2];
+            for ch in 0..2 {
+                // 0..1 triangle-ish sweep from a sine
+                let sweep = 0.5 + 0.5 * (TAU * (self.phase - 0.25 * ch as f32)).sin();
+                let input = dry[ch] + self.last[ch] * feedback;
+                wet[ch] = match settings.kind {
+                    ModFxKind::Chorus => self.tap(ch, CHORUS_DELAY + CHORUS_SWEEP * depth * (2.0 * sweep - 1.0)),
+                    ModFxKind::Flanger => self.tap(ch, FLANGER_DELAY + FLANGER_SWEEP * depth * sweep),
+                    ModFxKind::Phaser => {
+                        let freq = PHASER_LOW * (PHASER_HIGH / PHASER_LOW).powf(depth * sweep);
+                        self.phase_shift(ch, input, freq)
+                    }
+                };
+                self.lines[ch][self.pos] = input;
+                self.last[ch] = wet[ch];
+            }
+            self.pos = (self.pos + 1) % len;
+            *a = dry[0] * (1.0 - mix) + wet[0] * mix;
+            *b = dry[1] * (1.0 - mix) + wet[1] * mix;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Operator, LFO_COUNT, MAX_OPS};
+
+pub const MOD_SLOTS: usize = 8;
+
+/// ----------  Sources & destinations ----------
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ModSource {
+    #[default]
+    None,
+    Lfo(usize),
+    Env(usize), // the voice's envelope level on that operator
+    Velocity,
+    ModWheel,
+    Aftertouch,
+    Slide, // MPE slide / CC74
+}
+
+impl ModSource {
+    /// Every source available in a patch with `ops` operators.
+    pub fn all(ops: usize) -> Vec<ModSource> {
+        let mut all = vec![ModSource::None];
+        all.extend((0..LFO_COUNT).map(ModSource::Lfo));
+        all.extend((0..ops).map(ModSource::Env));
+        all.extend([ModSource::Velocity, ModSource::ModWheel, ModSource::Aftertouch, ModSource::Slide]);
+        all
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            ModSource::None => "—".into(),
+            ModSource::Lfo(i) => format!("LFO {}", i + 1),
+            ModSource::Env(op) => format!("Env {}", op),
+            ModSource::Velocity => "Velocity".into(),
+            ModSource::ModWheel => "Mod Wheel".into(),
+            ModSource::Aftertouch => "Aftertouch".into(),
+            ModSource::Slide => "Slide".into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ModDest {
+    Freq,     // depth in octaves
+    Amp,      // depth as a fraction of the level
+    Ratio,    // ±2 at full depth
+    Feedback, // ±0.5 at full depth
+    BitDepth, // ±8 bits at full depth
+}
+
+impl ModDest {
+    pub const ALL: [ModDest; 5] = [ModDest::Freq, ModDest::Amp, ModDest::Ratio, ModDest::Feedback, ModDest::BitDepth];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModDest::Freq => "Freq",
+            ModDest::Amp => "Amp",
+            ModDest::Ratio => "Ratio",
+            ModDest::Feedback => "Feedback",
+            ModDest::BitDepth => "Bit Depth",
+        }
+    }
+}
+
+/// ----------  Matrix ----------
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct ModSlot {
+    pub source: ModSource,
+    pub dest: ModDest,
+    pub op: usize,  // destination operator
+    pub depth: f32, // -1..1
+}
+
+impl Default for ModSlot {
+    fn default() -> Self { Self { source: ModSource::None, dest: ModDest::Amp, op: 0, depth: 0.0 } }
+}
+
+/// Source values seen by one voice during a control block.
+pub(crate) struct Sources {
+    pub lfo: [f32; LFO_COUNT],
+    pub env: [f32; MAX_OPS],
+    pub velocity: f32,
+    pub mod_wheel: f32,
+    pub aftertouch: f32,
+    pub slide: f32,
+}
+
+impl Sources {
+    fn get(&self, source: ModSource) -> f32 {
+        match source {
+            ModSource::None => 0.0,
+            ModSource::Lfo(i) => self.lfo.get(i).copied().unwrap_or(0.0),
+            ModSource::Env(op) => self.env.get(op).copied().unwrap_or(0.0),
+            ModSource::Velocity => self.velocity,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::Aftertouch => self.aftertouch,
+            ModSource::Slide => self.slide,
+        }
+    }
+}
+
+pub(crate) fn apply(slots: &[ModSlot; MOD_SLOTS], src: &Sources, mods: &mut [OpMod; MAX_OPS]) {
+    for slot in slots.iter().filter(|s| s.source != ModSource::None) {
+        if let Some(m) = mods.get_mut(slot.op) {
+            m.push(slot.dest, src.get(slot.source) * slot.depth);
+        }
+    }
+}
+
+/// ----------  Per-operator offsets ----------
+/// Everything modulation does to one operator during a control block.
+#[derive(Clone, Copy)]
+pub(crate) struct OpMod {
+    freq: f32,     // multiplier
+    amp: f32,      // multiplier
+    ratio: f32,    // offset
+    feedback: f32, // offset
+    bits: f32,     // offset
+}
+
+impl Default for OpMod {
+    fn default() -> Self { Self { freq: 1.0, amp: 1.0, ratio: 0.0, feedback: 0.0, bits: 0.0 } }
+}
+
+impl OpMod {
+    /// Adds a bipolar modulation amount `v` (already scaled by depth).
+    pub(crate) fn push(&mut self, dest: ModDest, v: f32) {
+        match dest {
+            ModDest::Freq => self.freq *= 2.0_f32.powf(v),
+            ModDest::Amp => self.amp *= 1.0 + v,
+            ModDest::Ratio => self.ratio += v * 2.0,
+            ModDest::Feedback => self.feedback += v * 0.5,
+            ModDest::BitDepth => self.bits += v * 8.0,
+        }
+    }
+
+    pub(crate) fn apply(&self, op: &mut Operator) {
+        op.freq *= self.freq;
+        op.amp = (op.amp * self.amp).max(0.0);
+        op.ratio = (op.ratio + self.ratio).max(0.01);
+        op.feedback = (op.feedback + self.feedback).clamp(0.0, 1.0);
+        op.bit_depth = (op.bit_depth as f32 + self.bits).round().clamp(1.0, 16.0) as u8;
+    }
+}
@@ -0,0 +1,120 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// ----------  Output monitoring ----------
+/// Creates a tap for the audio thread and a monitor for the UI that keeps
+/// the latest `len` output samples (for the oscilloscope and friends).
+pub fn monitor(len: usize) -> (Tap, Monitor) {
+    let (tx, rx) = RingBuffer::new(len * 2);
+    (Tap { tx }, Monitor { rx, history: VecDeque::with_capacity(len), len })
+}
+
+/// Audio side. Never blocks: samples that don't fit are dropped.
+pub struct Tap {
+    tx: Producer<f32>,
+}
+
+impl Tap {
+    pub fn write(&mut self, samples: &[f32]) { let _ = self.tx.push_partial_slice(samples); }
+
+    /// Writes the mono downmix of interleaved `frames`.
+    pub fn write_frames(&mut self, frames: &[f32], channels: usize) {
+        if channels <= 1 { return self.write(frames); }
+        for frame in frames.chunks_exact(channels) {
+            if self.tx.push(frame.iter().sum::<f32>() / channels as f32).is_err() { return; }
+        }
+    }
+}
+
+/// UI side.
+pub struct Monitor {
+    rx: Consumer<f32>,
+    history: VecDeque<f32>,
+    len: usize,
+}
+
+impl Monitor {
+    /// Pulls in everything the audio thread has written since the last call.
+    pub fn update(&mut self) {
+        let Ok(chunk) = self.rx.read_chunk(self.rx.slots()) else { return };
+        for s in chunk {
+            if self.history.len() == self.len { self.history.pop_front(); }
+            self.history.push_back(s);
+        }
+    }
+
+    /// Oldest first.
+    pub fn samples(&self) -> &VecDeque<f32> { &self.history }
+}
+
+/// ----------  Meters ----------
+/// Levels the audio thread reports once per block, shared with the UI.
+#[derive(Default)]
+pub struct Meters {
+    gain_reduction: AtomicU32, // f32 bits, dB
+    peak: [AtomicU32; 2],      // f32 bits, linear, per channel
+    rms: [AtomicU32; 2],       // the same
+    clip: [AtomicBool; 2],     // latched until the UI clears it
+    dsp_load: AtomicU32,       // f32 bits, render time over the block's duration
+    xruns: AtomicU32,          // dropouts since the stream started
+}
+
+impl Meters {
+    pub fn gain_reduction(&self) -> f32 { f32::from_bits(self.gain_reduction.load(Ordering::Relaxed)) }
+    pub fn set_gain_reduction(&self, db: f32) { self.gain_reduction.store(db.to_bits(), Ordering::Relaxed); }
+
+    /// Peak level of channel `ch`, falling back slowly after each peak.
+    pub fn peak(&self, ch: usize) -> f32 { f32::from_bits(self.peak[ch].load(Ordering::Relaxed)) }
+    /// RMS level of channel `ch` over roughly the last `RMS_WINDOW`.
+    pub fn rms(&self, ch: usize) -> f32 { f32::from_bits(self.rms[ch].load(Ordering::Relaxed)) }
+    /// Whether channel `ch` went over full scale since the clip indicators
+    /// were last cleared.
+    pub fn clipped(&self, ch: usize) -> bool { self.clip[ch].load(Ordering::Relaxed) }
+    pub fn clear_clips(&self) { for c in &self.clip { c.store(false, Ordering::Relaxed); } }
+
+    /// Time spent rendering as a fraction of the real-time budget; past 1
+    /// the output drops out.
+    pub fn dsp_load(&self) -> f32 { f32::from_bits(self.dsp_load.load(Ordering::Relaxed)) }
+    pub fn set_dsp_load(&self, load: f32) { self.dsp_load.store(load.to_bits(), Ordering::Relaxed); }
+
+    /// Blocks that were late or overran their budget, so the output
+    /// most likely dropped out.
+    pub fn xruns(&self) -> u32 { self.xruns.load(Ordering::Relaxed) }
+    pub fn add_xrun(&self) { self.xruns.fetch_add(1, Ordering::Relaxed); }
+}
+
+/// Seconds the RMS meter averages over, and the peak meter's fall time
+/// to -20 dB.
+const RMS_WINDOW: f32 = 0.3;
+const PEAK_FALL: f32 = 1.5;
+
+/// Audio side of the level meters: peak hold and RMS ballistics.
+pub struct Levels {
+    peak: [f32; 2],
+    mean_square: [f32; 2],
+    sr: f32,
+}
+
+impl Levels {
+    pub fn new(sr: f32) -> Self { Self { peak: [0.0; 2], mean_square: [0.0; 2], sr } }
+
+    /// Measures a block of interleaved `frames` and publishes the levels,
+    /// along with `clips` from the output stage. Mono shows on both meters.
+    pub fn measure(&mut self, frames: &[f32], channels: usize, clips: [bool; 2], meters: &Meters) {
+        let channels = channels.max(1);
+        let n = frames.len() / channels;
+        if n == 0 { return; }
+        let fall = 0.1f32.powf(n as f32 / (PEAK_FALL * self.sr));
+        let keep = (-(n as f32) / (RMS_WINDOW * self.sr)).exp();
+        for (ch, clipped) in clips.into_iter().enumerate() {
+            let samples = frames.iter().skip(ch.min(channels - 1)).step_by(channels);
+            let (peak, sum) = samples.fold((0f32, 0f32), |(p, s), x| (p.max(x.abs()), s + x * x));
+            self.peak[ch] = peak.max(self.peak[ch] * fall);
+            self.mean_square[ch] = sum / n as f32 + (self.mean_square[ch] - sum / n as f32) * keep;
+            meters.peak[ch].store(self.peak[ch].to_bits(), Ordering::Relaxed);
+            meters.rms[ch].store(self.mean_square[ch].sqrt().to_bits(), Ordering::Relaxed);
+            if clipped { meters.clip[ch].store(true, Ordering::Relaxed); }
+        }
+    }
+}
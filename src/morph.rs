@@ -0,0 +1,33 @@
+use crate::params::Param;
+use crate::Patch;
+
+/// ----------  Preset morphing ----------
+/// `a` and `b` mixed at `t` (0 is `a`, 1 is `b`). Every continuous
+/// parameter glides between the two (see `Param::mix`), as do synced
+/// lengths in beats; bit depths, breakpoints and the voice count move by
+/// whole steps. Discrete settings, like sync, waveforms, routing and modes,
+/// and whatever only one preset has, such as extra operators, come from
+/// the nearer preset, flipping over halfway.
+pub fn morph(a: &Patch, b: &Patch, t: f32) -> Patch {
+    let t = t.clamp(0.0, 1.0);
+    let (mut out, mut other, t) = if t < 0.5 { (a.clone(), b.clone(), t) } else { (b.clone(), a.clone(), 1.0 - t) };
+    for param in Param::all() {
+        if let (Some(mine), Some(theirs)) = (param.field(&mut out), param.field(&mut other)) {
+            *mine = param.mix(*mine, *theirs, t);
+        }
+    }
+    let step = |mine: f32, theirs: f32| (mine + (theirs - mine) * t).round();
+    for (op, theirs) in out.ops.iter_mut().zip(&other.ops) {
+        op.bit_depth = step(op.bit_depth as f32, theirs.bit_depth as f32) as u8;
+        let ls = &mut op.level_scaling;
+        ls.breakpoint = step(ls.breakpoint as f32, theirs.level_scaling.breakpoint as f32) as u8;
+    }
+    out.max_voices = step(out.max_voices as f32, other.max_voices as f32) as usize;
+    // Beat lengths move by equal factors, like times
+    let beats = |mine: f32, theirs: f32| if mine > 0.0 && theirs > 0.0 { mine * (theirs / mine).powf(t) } else { mine };
+    for (lfo, theirs) in out.lfos.iter_mut().zip(&other.lfos) { lfo.beats = beats(lfo.beats, theirs.beats); }
+    out.delay.beats = beats(out.delay.beats, other.delay.beats);
+    out.arp.beats = beats(out.arp.beats, other.arp.beats);
+    out.seq.beats = beats(out.seq.beats, other.seq.beats);
+    out
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Noise ----------
+/// Replaces an operator's oscillator with noise when not `Off`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum NoiseKind {
+    #[default]
+    Off,
+    White,
+    Pink,
+}
+
+impl NoiseKind {
+    pub const ALL: [NoiseKind; 3] = [NoiseKind::Off, NoiseKind::White, NoiseKind::Pink];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoiseKind::Off => "Off",
+            NoiseKind::White => "White",
+            NoiseKind::Pink => "Pink",
+        }
+    }
+}
+
+/// Xorshift white noise plus Paul Kellet's three-pole pink filter.
+#[derive(Clone, Copy)]
+pub(crate) struct Noise {
+    state: u32,
+    pink: [f32; 3],
+}
+
+impl Default for Noise {
+    fn default() -> Self { Self::new(1) }
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        Self { state: seed.max(1), pink: [0.0; 3] }
+    }
+
+    /// Uniform in -1..1.
+    fn white(&mut self) -> f32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    pub fn sample(&mut self, kind: NoiseKind) -> f32 {
+        let w = self.white();
+        if kind != NoiseKind::Pink { return w; }
+        let b = &mut self.pink;
+        b[0] = 0.99765 * b[0] + w * 0.0990460;
+        b[1] = 0.96300 * b[1] + w * 0.2965164;
+        b[2] = 0.57000 * b[2] + w * 1.0526913;
+        (b[0] + b[1] + b[2] + w * 0.1848) * 0.25
+    }
+}
@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::noise::Noise;
+use crate::{EnvKind, Envelope, LevelScaling, NoiseKind, RateLevel, Waveform, Wavetable};
+
+/// ----------  Parameter smoothing ----------
+/// Time constant of the glide towards a changed parameter, in seconds.
+const SMOOTH_TIME: f32 = 0.005;
+
+/// Audio-rate copies of the parameters that zipper when they jump.
+#[derive(Clone, Copy, Default)]
+struct Smoothed {
+    freq: f32,
+    amp: f32,
+    ratio: f32,
+    feedback: f32,
+    primed: bool, // false: snap to the targets on the next sample
+}
+
+/// ----------  Ratios ----------
+/// The harmonics a ratio is built on, before its fine part.
+pub const COARSE_RATIOS: [f32; 17] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0];
+
+/// Index into `COARSE_RATIOS` of the harmonic `ratio` sits on or above.
+pub fn coarse_ratio(ratio: f32) -> usize {
+    COARSE_RATIOS.iter().rposition(|&c| c <= ratio + 1e-4).unwrap_or(0)
+}
+
+/// ----------  Modulation mode ----------
+/// Phase offset, in radians, a modulator at full output applies in phase mode.
+const PM_DEPTH: f32 = 2.0 * PI;
+
+/// How modulator outputs drive an operator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ModMode {
+    /// Linear through-zero FM: the input shifts the instantaneous frequency
+    /// by `input × freq`, running the phase backwards when it goes negative.
+    #[default]
+    Frequency,
+    /// DX-style phase modulation: the input offsets the phase read by the
+    /// waveform and never accumulates, so the pitch cannot drift.
+    Phase,
+}
+
+/// ----------  Operator ----------
+/// Phase offset, in radians, that full feedback applies at full output.
+/// Around π the tone reaches a bright sawtooth without breaking into noise.
+const FEEDBACK_DEPTH: f32 = PI;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Operator {
+    pub freq: f32,
+    #[serde(skip)]
+    pub(crate) phase: f32,
+    pub amp: f32,
+    pub envelope: Envelope,
+    pub ratio: f32,       // modulation ratio
+    pub feedback: f32,    // self‑feedback [0..1], DX-style two-sample average
+    #[serde(default)]
+    pub sync: bool,       // hard-sync: restart each cycle of the note's fundamental
+    pub bit_depth: u8,    // 8–16 for bit‑crushing
+    #[serde(default)]
+    pub waveform: Waveform,
+    #[serde(default)]
+    pub velocity_sens: f32, // -1..1; 0 ignores velocity, 1 level follows it, -1 level falls as it rises
+    #[serde(default)]
+    pub detune: f32,        // cents, ±50, on top of the ratio
+    #[serde(default)]
+    pub level_scaling: LevelScaling,
+    #[serde(default)]
+    pub rate_scaling: f32,  // 0..1, how much higher notes shorten the envelope
+    #[serde(skip, default = "unit_rate")]
+    pub(crate) env_rate: f32, // envelope speed for the note playing, set every block
+    #[serde(default)]
+    pub env_kind: EnvKind,
+    #[serde(default)]
+    pub rate_level: RateLevel,
+    #[serde(default)]
+    pub pan: f32,           // -1 left .. 1 right, when routed to the output
+    #[serde(default)]
+    pub noise: NoiseKind,   // replaces the waveform when not Off
+    #[serde(default)]
+    pub table: Option<Wavetable>, // replaces the waveform when loaded
+    #[serde(default)]
+    pub mute: bool,
+    #[serde(default)]
+    pub solo: bool,
+    #[serde(skip)]
+    smooth: Smoothed,
+    #[serde(skip)]
+    fb_hist: [f32; 2], // last two outputs, averaged for self-feedback
+    #[serde(skip)]
+    sync_phase: f32,   // 0..1 through the fundamental's cycle, the sync master
+    #[serde(skip)]
+    pub(crate) noise_gen: Noise,
+}
+
+fn unit_rate() -> f32 { 1.0 }
+
+impl Operator {
+    pub fn new(freq: f32, amp: f32, env: Envelope,
+               ratio: f32, feedback: f32, sync: bool, bit_depth: u8) -> Self {
+        Self { freq, phase: 0.0, amp, envelope: env,
+               ratio, feedback, sync, bit_depth, waveform: Waveform::Sine,
+               velocity_sens: 0.0, detune: 0.0, level_scaling: LevelScaling::default(),
+               rate_scaling: 0.0, env_rate: unit_rate(), env_kind: EnvKind::Adsr, rate_level: RateLevel::default(),
+               pan: 0.0, noise: NoiseKind::Off, table: None, mute: false, solo: false, smooth: Smoothed::default(), fb_hist: [0.0; 2], sync_phase: 0.0,
+               noise_gen: Noise::default() }
+    }
+
+    /// A plain sine at ratio 1 and `amp`, with an organ-like envelope and
+    /// no feedback, sync, crushing or scaling.
+    pub fn init(freq: f32, amp: f32) -> Self {
+        Self::new(freq, amp, Envelope::new(0.005, 0.1, 1.0, 0.1), 1.0, 0.0, false, 16)
+    }
+
+    pub fn note_on(&mut self) {
+        if !self.env_active() { self.smooth.primed = false; }
+        match self.env_kind {
+            EnvKind::Adsr => self.envelope.note_on(),
+            EnvKind::RateLevel => self.rate_level.note_on(),
+        }
+    }
+
+    pub fn note_off(&mut self) {
+        match self.env_kind {
+            EnvKind::Adsr => self.envelope.note_off(),
+            EnvKind::RateLevel => self.rate_level.note_off(),
+        }
+    }
+
+    /// Level of whichever envelope is in use.
+    pub fn env_level(&self) -> f32 {
+        match self.env_kind {
+            EnvKind::Adsr => self.envelope.level,
+            EnvKind::RateLevel => self.rate_level.level,
+        }
+    }
+
+    pub fn env_active(&self) -> bool {
+        match self.env_kind {
+            EnvKind::Adsr => self.envelope.active,
+            EnvKind::RateLevel => self.rate_level.active,
+        }
+    }
+
+    fn crush(&self, sample: f32) -> f32 {
+        let step = 2.0_f32.powi(-(self.bit_depth as i32));
+        (sample / step).round() * step
+    }
+
+    /// Runs the sync master at the note's fundamental; each time it wraps,
+    /// a synced operator's phase restarts, picking up from where it would
+    /// be the fraction of a sample past the wrap.
+    fn hard_sync(&mut self, freq: f32, inc: f32, dt: f32) {
+        self.sync_phase += freq * dt;
+        if self.sync_phase < 1.0 { return; }
+        self.sync_phase = self.sync_phase.fract();
+        if self.sync && freq > 0.0 { self.phase = inc * self.sync_phase / (freq * dt); }
+    }
+
+    /// Moves the smoothed parameters one sample towards their set values.
+    fn smooth(&mut self, dt: f32) -> Smoothed {
+        let s = &mut self.smooth;
+        if !s.primed {
+            *s = Smoothed { freq: self.freq, amp: self.amp, ratio: self.ratio, feedback: self.feedback, primed: true };
+        } else {
+            let k = dt / (SMOOTH_TIME + dt);
+            s.freq += (self.freq - s.freq) * k;
+            s.amp += (self.amp - s.amp) * k;
+            s.ratio += (self.ratio - s.ratio) * k;
+            s.feedback += (self.feedback - s.feedback) * k;
+        }
+        *s
+    }
+
+    pub fn sample(&mut self, dt: f32, mod_in: f32, mode: ModMode) -> f32 {
+        let s = self.smooth(dt);
+        let (freq_in, phase_in) = match mode {
+            ModMode::Frequency => (mod_in, 0.0),
+            ModMode::Phase => (0.0, mod_in * PM_DEPTH),
+        };
+        let mod_freq = s.freq * s.ratio + freq_in * s.freq;
+        let inc = 2.0 * PI * mod_freq * dt;
+        // Wrapped so long notes keep their f32 precision; a negative
+        // increment (through-zero FM) wraps back up from 0
+        self.phase = (self.phase + inc).rem_euclid(2.0 * PI);
+        self.hard_sync(s.freq, inc, dt);
+
+        match self.env_kind {
+            EnvKind::Adsr => self.envelope.advance(dt * self.env_rate),
+            EnvKind::RateLevel => self.rate_level.advance(dt * self.env_rate),
+        }
+        let env = self.env_level();
+
+        // DX-style feedback: the mean of the last two outputs keeps high
+        // settings from flipping between two states every sample
+        let fb = s.feedback * FEEDBACK_DEPTH * (self.fb_hist[0] + self.fb_hist[1]) * 0.5;
+        let phase = self.phase + phase_in + fb;
+        let wave = match (self.noise, &self.table) {
+            (NoiseKind::Off, Some(table)) => table.eval(phase),
+            (NoiseKind::Off, None) => self.waveform.eval(phase, inc / (2.0 * PI)),
+            (kind, _) => self.noise_gen.sample(kind),
+        };
+        let raw = s.amp * env * wave;
+        self.fb_hist = [raw, self.fb_hist[0]];
+        self.crush(raw)
+    }
+
+    /// Level multiplier for a note struck at `velocity` (0..1). Set on a
+    /// modulator, this makes velocity change the timbre rather than the
+    /// loudness; a negative amount softens it on harder hits.
+    pub fn velocity_gain(&self, velocity: f32) -> f32 {
+        let s = self.velocity_sens;
+        if s >= 0.0 { 1.0 - s * (1.0 - velocity) } else { 1.0 + s * velocity }
+    }
+
+    /// Takes over every parameter from `patch` while keeping this operator's
+    /// running oscillator, envelope and smoothing state; `freq` replaces the
+    /// base frequency.
+    pub(crate) fn follow(&mut self, patch: &Operator, freq: f32) {
+        let (phase, env, rl, smooth, fb_hist, noise_gen, sync_phase) =
+            (self.phase, self.envelope, self.rate_level, self.smooth, self.fb_hist, self.noise_gen, self.sync_phase);
+        *self = patch.clone();
+        self.freq = freq;
+        self.phase = phase;
+        self.envelope.take_state(&env);
+        self.rate_level.take_state(&rl);
+        self.smooth = smooth;
+        self.fb_hist = fb_hist;
+        self.noise_gen = noise_gen;
+        self.sync_phase = sync_phase;
+    }
+}
@@ -0,0 +1,131 @@
+use crossbeam_channel::Sender;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::params::{Param, Switch};
+use crate::Event;
+
+/// ----------  OSC messages ----------
+/// Note velocity when `/note/on` leaves it out.
+const DEFAULT_VELOCITY: f32 = 100.0;
+/// How often the server looks for a request to stop.
+const POLL: Duration = Duration::from_millis(100);
+/// Largest packet taken; bigger ones are cut short and fail to decode.
+const MAX_PACKET: usize = 65536;
+
+/// Splits a null-terminated string padded to four bytes off the front of `data`.
+fn string(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.iter().position(|&b| b == 0)?;
+    let s = std::str::from_utf8(&data[..end]).ok()?;
+    Some((s, data.get((end + 4) & !3..)?))
+}
+
+fn word<const N: usize>(data: &[u8]) -> Option<([u8; N], &[u8])> {
+    Some((data.get(..N)?.try_into().ok()?, &data[N..]))
+}
+
+/// Decodes an OSC 1.0 packet into (address, arguments), reading numbers
+/// and booleans as `f32` and skipping strings and blobs. Bundles are
+/// unpacked in order, their time tags ignored.
+pub fn decode(packet: &[u8]) -> Option<Vec<(String, Vec<f32>)>> {
+    let (address, mut data) = string(packet)?;
+    if address == "#bundle" {
+        let mut messages = Vec::new();
+        data = data.get(8..)?; // time tag
+        while !data.is_empty() {
+            let (len, rest) = word::<4>(data)?;
+            let len = u32::from_be_bytes(len) as usize;
+            messages.extend(decode(rest.get(..len)?)?);
+            data = &rest[len..];
+        }
+        return Some(messages);
+    }
+    if !address.starts_with('/') { return None; }
+    let tags = match string(data) {
+        Some((tags, rest)) if tags.starts_with(',') => { data = rest; &tags[1..] }
+        _ => "", // older senders leave the type tags out
+    };
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        match tag {
+            'i' => { let (b, rest) = word::<4>(data)?; args.push(i32::from_be_bytes(b) as f32); data = rest; }
+            'f' => { let (b, rest) = word::<4>(data)?; args.push(f32::from_be_bytes(b)); data = rest; }
+            'h' => { let (b, rest) = word::<8>(data)?; args.push(i64::from_be_bytes(b) as f32); data = rest; }
+            'd' => { let (b, rest) = word::<8>(data)?; args.push(f64::from_be_bytes(b) as f32); data = rest; }
+            'T' => args.push(1.0),
+            'F' => args.push(0.0),
+            's' | 'S' => data = string(data)?.1,
+            'b' => {
+                let (len, rest) = word::<4>(data)?;
+                data = rest.get((u32::from_be_bytes(len) as usize + 3) & !3..)?;
+            }
+            't' => data = word::<8>(data)?.1,
+            'c' | 'r' | 'm' => data = word::<4>(data)?.1,
+            _ => {} // N, I and array brackets carry no data
+        }
+    }
+    Some(vec![(address.to_string(), args)])
+}
+
+/// The event for one message: `/note/on note [velocity]`, `/note/off note`,
+/// or a parameter's or switch's address (see `Param::address` and
+/// `Switch::address`) with its new value.
+pub fn event(address: &str, args: &[f32]) -> Option<Event> {
+    let first = *args.first()?;
+    let byte = |v: f32| v.round().clamp(0.0, 127.0) as u8;
+    match address {
+        "/note/on" => {
+            let velocity = byte(args.get(1).copied().unwrap_or(DEFAULT_VELOCITY));
+            Some(if velocity == 0 { Event::NoteOff { note: byte(first) } }
+                 else { Event::NoteOn { note: byte(first), velocity, channel: 0 } })
+        }
+        "/note/off" => Some(Event::NoteOff { note: byte(first) }),
+        _ => Param::from_address(address).map(|param| Event::SetParam(param, first))
+            .or_else(|| Switch::from_address(address).map(|switch| Event::SetSwitch(switch, first))),
+    }
+}
+
+/// ----------  Server ----------
+/// A UDP port taking OSC; it closes when this is dropped.
+pub struct OscServer {
+    pub port: u16,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() { let _ = thread.join(); }
+    }
+}
+
+/// Listens for OSC on UDP `port`, sending notes to `events` and parameter
+/// and switch changes to `controls`, like the MIDI input does.
+pub fn listen(port: u16, events: Sender<Event>, controls: Sender<Event>) -> io::Result<OscServer> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    socket.set_read_timeout(Some(POLL))?;
+    let port = socket.local_addr()?.port();
+    eprintln!("OSC input: UDP port {}", port);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    let thread = thread::spawn(move || {
+        let mut buf = vec![0; MAX_PACKET];
+        while !flag.load(Ordering::Relaxed) {
+            let Ok(len) = socket.recv(&mut buf) else { continue };
+            for (address, args) in decode(&buf[..len]).unwrap_or_default() {
+                match event(&address, &args) {
+                    Some(e @ (Event::SetParam(..) | Event::SetSwitch(..))) => { let _ = controls.try_send(e); }
+                    Some(e) => { let _ = events.try_send(e); }
+                    None => {}
+                }
+            }
+        }
+    });
+    Ok(OscServer { port, stop, thread: Some(thread) })
+}
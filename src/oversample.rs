@@ -0,0 +1,43 @@
+/// ----------  Decimation ----------
+/// FIR taps per unit of oversampling factor.
+const TAPS_PER_FACTOR: usize = 24;
+
+/// Blackman-windowed sinc lowpass that brings an oversampled signal back down
+/// to the output rate, cutting just below the output Nyquist frequency.
+pub(crate) struct Decimator {
+    factor: usize,
+    taps: Vec<f32>,
+    hist: Vec<f32>, // input history, stored twice so a window is one slice
+    pos: usize,
+}
+
+impl Decimator {
+    pub fn new(factor: usize) -> Self {
+        use std::f32::consts::PI;
+        let n = TAPS_PER_FACTOR * factor + 1;
+        let fc = 0.45 / factor as f32; // cycles per input sample
+        let mid = (n - 1) as f32 / 2.0;
+        let mut taps: Vec<f32> = (0..n).map(|i| {
+            let x = i as f32 - mid;
+            let sinc = if x == 0.0 { 2.0 * fc } else { (2.0 * PI * fc * x).sin() / (PI * x) };
+            let w = i as f32 / (n - 1) as f32;
+            sinc * (0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos())
+        }).collect();
+        let gain: f32 = taps.iter().sum();
+        for t in &mut taps { *t /= gain; }
+        Self { factor, taps, hist: vec![0.0; 2 * n], pos: 0 }
+    }
+
+    /// Filters `input` (`factor` samples per output sample) into `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut [f32]) {
+        let n = self.taps.len();
+        for (o, frame) in out.iter_mut().zip(input.chunks(self.factor)) {
+            for &x in frame {
+                self.pos = (self.pos + n - 1) % n;
+                self.hist[self.pos] = x;
+                self.hist[self.pos + n] = x;
+            }
+            *o = self.taps.iter().zip(&self.hist[self.pos..self.pos + n]).map(|(t, h)| t * h).sum();
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+use crate::{
+    coarse_ratio, macros, ArpMode, ClipCurve, EnvKind, FilterMode, GlideMode, ModDest, ModFxKind, ModMode, NoiseKind, NotePriority, Patch,
+    ScaleCurve, Waveform, EQ_BANDS, LFO_COUNT, MACROS, MAX_HAAS, MAX_OPS, MAX_VOICES, MOD_SLOTS, RL_STAGES, COARSE_RATIOS,
+};
+
+/// ----------  Parameters ----------
+/// Continuous operator settings that can be addressed from outside the UI.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OpParam {
+    Freq,
+    Amp,
+    VelocitySens,
+    Ratio,
+    Fine, // % above the ratio's coarse harmonic, see `COARSE_RATIOS`
+    Detune,
+    Pan,
+    Feedback,
+    BitDepth,
+    LeftDepth,
+    RightDepth,
+    RateScaling,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    AttackCurve,
+    DecayCurve,
+    ReleaseCurve,
+    Breakpoint,       // level scaling, a MIDI note
+    StageTime(usize), // rate/level envelope stage
+    StageLevel(usize),
+}
+
+impl OpParam {
+    /// The settings without a stage number.
+    pub const ALL: [OpParam; 20] = [
+        OpParam::Freq, OpParam::Amp, OpParam::VelocitySens, OpParam::Ratio, OpParam::Fine, OpParam::Detune, OpParam::Pan,
+        OpParam::Feedback, OpParam::BitDepth, OpParam::LeftDepth, OpParam::RightDepth, OpParam::RateScaling,
+        OpParam::Attack, OpParam::Decay, OpParam::Sustain, OpParam::Release,
+        OpParam::AttackCurve, OpParam::DecayCurve, OpParam::ReleaseCurve, OpParam::Breakpoint,
+    ];
+
+    /// Every operator setting, stages included.
+    pub fn all() -> impl Iterator<Item = OpParam> {
+        let stages = (0..RL_STAGES).flat_map(|s| [OpParam::StageTime(s), OpParam::StageLevel(s)]);
+        OpParam::ALL.into_iter().chain(stages)
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            OpParam::StageTime(s) => format!("Stage {} Time", s + 1),
+            OpParam::StageLevel(s) => format!("Stage {} Level", s + 1),
+            p => format!("{:?}", p),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LfoParam {
+    Rate,
+    Depth,
+    Pressure,
+}
+
+impl LfoParam {
+    pub const ALL: [LfoParam; 3] = [LfoParam::Rate, LfoParam::Depth, LfoParam::Pressure];
+}
+
+/// A patch parameter, for MIDI learn and other remote control. Ranges match
+/// the UI's sliders.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Param {
+    Op(usize, OpParam),
+    Lfo(usize, LfoParam),
+    ModDepth(usize), // mod matrix slot
+    Macro(usize),
+    Voices,
+    BendRange,
+    NoteBendRange, // MPE
+    Glide,
+    Spread,
+    A4,
+    Tempo,
+    ArpRate,
+    ArpGate,
+    SubLevel,
+    FilterCutoff,
+    FilterResonance,
+    FilterDrive,
+    FilterEnvAmount,
+    FilterKeyTrack,
+    FilterVowel,
+    EqFreq(usize), // band
+    EqGain(usize),
+    EqQ(usize),
+    ModFxRate,
+    ModFxDepth,
+    ModFxFeedback,
+    ModFxMix,
+    DelayTime,
+    DelayFeedback,
+    DelayMix,
+    ReverbSize,
+    ReverbDamping,
+    ReverbMix,
+    CompThreshold,
+    CompRatio,
+    CompMakeup,
+    CompAttack,
+    CompRelease,
+    CrushBits,
+    CrushRate,
+    CrushMix,
+    Width,
+    Haas,
+    MasterGain,
+    MasterCeiling,
+}
+
+impl Param {
+    /// The parameters outside operators, LFOs, mod slots, macros and EQ bands.
+    const GLOBAL: [Param; 38] = [
+        Param::Voices, Param::BendRange, Param::NoteBendRange, Param::Glide, Param::Spread, Param::A4, Param::Tempo,
+        Param::ArpRate, Param::ArpGate, Param::SubLevel, Param::FilterCutoff, Param::FilterResonance,
+        Param::FilterDrive, Param::FilterEnvAmount, Param::FilterKeyTrack, Param::FilterVowel,
+        Param::ModFxRate, Param::ModFxDepth, Param::ModFxFeedback, Param::ModFxMix,
+        Param::DelayTime, Param::DelayFeedback, Param::DelayMix, Param::ReverbSize, Param::ReverbDamping,
+        Param::ReverbMix, Param::CompThreshold, Param::CompRatio, Param::CompMakeup, Param::CompAttack,
+        Param::CompRelease, Param::CrushBits, Param::CrushRate, Param::CrushMix, Param::Width, Param::Haas,
+        Param::MasterGain, Param::MasterCeiling,
+    ];
+
+    /// Every parameter there is, for any patch size.
+    pub fn all() -> impl Iterator<Item = Param> {
+        let ops = (0..MAX_OPS).flat_map(|i| OpParam::all().map(move |p| Param::Op(i, p)));
+        let lfos = (0..LFO_COUNT).flat_map(|i| LfoParam::ALL.map(move |p| Param::Lfo(i, p)));
+        let slots = (0..MOD_SLOTS).map(Param::ModDepth);
+        let macros = (0..MACROS).map(Param::Macro);
+        let bands = (0..EQ_BANDS).flat_map(|i| [Param::EqFreq(i), Param::EqGain(i), Param::EqQ(i)]);
+        ops.chain(lfos).chain(slots).chain(macros).chain(bands).chain(Param::GLOBAL)
+    }
+
+    /// Remote control address, the name's words in lower case as path
+    /// segments: `/op/1/ratio`, `/filter/cutoff`. Operators, like everything
+    /// else numbered, count from 1.
+    pub fn address(self) -> String {
+        self.name().split(' ').map(|w| format!("/{}", w.to_lowercase())).collect()
+    }
+
+    /// The parameter at `address`, looked up in a table built on first use
+    /// so scripts and OSC can resolve addresses every frame.
+    pub fn from_address(address: &str) -> Option<Param> {
+        static ADDRESSES: OnceLock<HashMap<String, Param>> = OnceLock::new();
+        ADDRESSES.get_or_init(|| Param::all().map(|p| (p.address(), p)).collect()).get(address).copied()
+    }
+
+    pub fn range(self) -> RangeInclusive<f32> {
+        match self {
+            Param::Op(_, p) => match p {
+                OpParam::Freq => 20.0..=2000.0,
+                OpParam::Amp => 0.0..=2.0,
+                OpParam::VelocitySens | OpParam::Pan | OpParam::LeftDepth | OpParam::RightDepth => -1.0..=1.0,
+                OpParam::Ratio => 0.5..=16.0,
+                OpParam::Fine => 0.0..=99.0,
+                OpParam::Detune => -50.0..=50.0,
+                OpParam::Feedback => 0.0..=0.5,
+                OpParam::BitDepth => 8.0..=16.0,
+                OpParam::RateScaling | OpParam::Sustain => 0.0..=1.0,
+                OpParam::Attack | OpParam::Decay | OpParam::Release => 0.001..=2.0,
+                OpParam::AttackCurve | OpParam::DecayCurve | OpParam::ReleaseCurve => -1.0..=1.0,
+                OpParam::Breakpoint => 0.0..=127.0,
+                OpParam::StageTime(_) => 0.001..=10.0,
+                OpParam::StageLevel(_) => 0.0..=1.0,
+            },
+            Param::Lfo(_, LfoParam::Rate) => 0.01..=20.0,
+            Param::Lfo(..) => 0.0..=1.0,
+            Param::ModDepth(_) => -1.0..=1.0,
+            Param::Macro(_) => 0.0..=1.0,
+            Param::Voices => 8.0..=MAX_VOICES as f32,
+            Param::BendRange => 0.0..=24.0,
+            Param::NoteBendRange => 0.0..=96.0,
+            Param::Glide => 0.0..=2.0,
+            Param::Spread => 0.0..=1.0,
+            Param::A4 => 415.0..=466.0,
+            Param::Tempo => 40.0..=300.0,
+            Param::ArpRate => 0.5..=32.0,
+            Param::ArpGate => 0.05..=1.0,
+            Param::SubLevel => 0.0..=1.0,
+            Param::FilterCutoff => 20.0..=20000.0,
+            Param::FilterResonance | Param::FilterDrive | Param::FilterKeyTrack => 0.0..=1.0,
+            Param::FilterEnvAmount => -8.0..=8.0,
+            Param::FilterVowel => 0.0..=4.0,
+            Param::EqFreq(_) => 20.0..=20000.0,
+            Param::EqGain(_) => -18.0..=18.0,
+            Param::EqQ(_) => 0.1..=10.0,
+            Param::ModFxRate => 0.01..=10.0,
+            Param::CompThreshold => -60.0..=0.0,
+            Param::CompRatio => 1.0..=20.0,
+            Param::CompMakeup => 0.0..=24.0,
+            Param::CompAttack => 0.1..=100.0,
+            Param::CompRelease => 10.0..=1000.0,
+            Param::CrushBits => 1.0..=16.0,
+            Param::CrushRate => 100.0..=44100.0,
+            Param::Width => 0.0..=2.0,
+            Param::Haas => 0.0..=MAX_HAAS,
+            Param::MasterGain => -60.0..=12.0,
+            Param::MasterCeiling => -12.0..=0.0,
+            Param::ModFxFeedback => 0.0..=0.9,
+            Param::DelayTime => 1.0..=2000.0,
+            Param::DelayFeedback => 0.0..=0.95,
+            Param::ModFxDepth | Param::ModFxMix | Param::DelayMix | Param::ReverbSize | Param::ReverbDamping | Param::ReverbMix
+                | Param::CrushMix => 0.0..=1.0,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Param::Op(i, p) => format!("Op {} {}", i + 1, p.name()),
+            Param::Lfo(i, p) => format!("LFO {} {:?}", i + 1, p),
+            Param::ModDepth(i) => format!("Mod Slot {} Depth", i + 1),
+            Param::Macro(i) => format!("Macro {}", i + 1),
+            Param::Voices => "Voices".into(),
+            Param::BendRange => "Bend Range".into(),
+            Param::NoteBendRange => "Note Bend Range".into(),
+            Param::Glide => "Glide".into(),
+            Param::Spread => "Spread".into(),
+            Param::A4 => "A4".into(),
+            Param::Tempo => "Tempo".into(),
+            Param::ArpRate => "Arp Rate".into(),
+            Param::ArpGate => "Arp Gate".into(),
+            Param::SubLevel => "Sub Level".into(),
+            Param::FilterCutoff => "Filter Cutoff".into(),
+            Param::FilterResonance => "Filter Resonance".into(),
+            Param::FilterDrive => "Filter Drive".into(),
+            Param::FilterEnvAmount => "Filter Env Amount".into(),
+            Param::FilterKeyTrack => "Filter Key Track".into(),
+            Param::FilterVowel => "Filter Vowel".into(),
+            Param::EqFreq(i) => format!("EQ {} Freq", i + 1),
+            Param::EqGain(i) => format!("EQ {} Gain", i + 1),
+            Param::EqQ(i) => format!("EQ {} Q", i + 1),
+            Param::ModFxRate => "Mod FX Rate".into(),
+            Param::ModFxDepth => "Mod FX Depth".into(),
+            Param::ModFxFeedback => "Mod FX Feedback".into(),
+            Param::ModFxMix => "Mod FX Mix".into(),
+            Param::DelayTime => "Delay Time".into(),
+            Param::DelayFeedback => "Delay Feedback".into(),
+            Param::DelayMix => "Delay Mix".into(),
+            Param::ReverbSize => "Reverb Size".into(),
+            Param::ReverbDamping => "Reverb Damping".into(),
+            Param::ReverbMix => "Reverb Mix".into(),
+            Param::CompThreshold => "Comp Threshold".into(),
+            Param::CompRatio => "Comp Ratio".into(),
+            Param::CompMakeup => "Comp Makeup".into(),
+            Param::CompAttack => "Comp Attack".into(),
+            Param::CompRelease => "Comp Release".into(),
+            Param::CrushBits => "Crush Bits".into(),
+            Param::CrushRate => "Crush Rate".into(),
+            Param::CrushMix => "Crush Mix".into(),
+            Param::Width => "Stereo Width".into(),
+            Param::Haas => "Haas Delay".into(),
+            Param::MasterGain => "Master Volume".into(),
+            Param::MasterCeiling => "Master Ceiling".into(),
+        }
+    }
+
+    /// The setting in `patch`, if it exists there.
+    pub(crate) fn field(self, patch: &mut Patch) -> Option<&mut f32> {
+        Some(match self {
+            Param::Op(i, p) => {
+                let op = patch.ops.get_mut(i)?;
+                match p {
+                    OpParam::Freq => &mut op.freq,
+                    OpParam::Amp => &mut op.amp,
+                    OpParam::VelocitySens => &mut op.velocity_sens,
+                    OpParam::Ratio => &mut op.ratio,
+                    OpParam::Fine => return None, // part of the ratio, see `set`
+                    OpParam::Detune => &mut op.detune,
+                    OpParam::Pan => &mut op.pan,
+                    OpParam::Feedback => &mut op.feedback,
+                    OpParam::BitDepth => return None, // integer, see `set`
+                    OpParam::LeftDepth => &mut op.level_scaling.left_depth,
+                    OpParam::RightDepth => &mut op.level_scaling.right_depth,
+                    OpParam::RateScaling => &mut op.rate_scaling,
+                    OpParam::Attack => &mut op.envelope.attack,
+                    OpParam::Decay => &mut op.envelope.decay,
+                    OpParam::Sustain => &mut op.envelope.sustain,
+                    OpParam::Release => &mut op.envelope.release,
+                    OpParam::AttackCurve => &mut op.envelope.attack_curve,
+                    OpParam::DecayCurve => &mut op.envelope.decay_curve,
+                    OpParam::ReleaseCurve => &mut op.envelope.release_curve,
+                    OpParam::Breakpoint => return None, // a note number, see `set`
+                    OpParam::StageTime(s) => op.rate_level.times.get_mut(s)?,
+                    OpParam::StageLevel(s) => op.rate_level.levels.get_mut(s)?,
+                }
+            }
+            Param::Lfo(i, p) => {
+                let lfo = patch.lfos.get_mut(i)?;
+                match p {
+                    LfoParam::Rate => &mut lfo.rate,
+                    LfoParam::Depth => &mut lfo.depth,
+                    LfoParam::Pressure => &mut lfo.pressure,
+                }
+            }
+            Param::ModDepth(i) => &mut patch.mod_slots.get_mut(i)?.depth,
+            Param::Macro(i) => &mut patch.macros.get_mut(i)?.value,
+            Param::Voices => return None, // a count, see `set`
+            Param::BendRange => &mut patch.bend_range,
+            Param::NoteBendRange => &mut patch.mpe_bend_range,
+            Param::Glide => &mut patch.glide,
+            Param::Spread => &mut patch.spread,
+            Param::A4 => &mut patch.a4,
+            Param::Tempo => &mut patch.tempo,
+            Param::ArpRate => &mut patch.arp.rate,
+            Param::ArpGate => &mut patch.arp.gate,
+            Param::SubLevel => &mut patch.sub.level,
+            Param::FilterCutoff => &mut patch.filter.cutoff,
+            Param::FilterResonance => &mut patch.filter.resonance,
+            Param::FilterDrive => &mut patch.filter.drive,
+            Param::FilterEnvAmount => &mut patch.filter.env_amount,
+            Param::FilterKeyTrack => &mut patch.filter.key_track,
+            Param::FilterVowel => &mut patch.filter.vowel,
+            Param::EqFreq(i) => &mut patch.eq.bands.get_mut(i)?.freq,
+            Param::EqGain(i) => &mut patch.eq.bands.get_mut(i)?.gain,
+            Param::EqQ(i) => &mut patch.eq.bands.get_mut(i)?.q,
+            Param::ModFxRate => &mut patch.mod_fx.rate,
+            Param::ModFxDepth => &mut patch.mod_fx.depth,
+            Param::ModFxFeedback => &mut patch.mod_fx.feedback,
+            Param::ModFxMix => &mut patch.mod_fx.mix,
+            Param::DelayTime => &mut patch.delay.time,
+            Param::DelayFeedback => &mut patch.delay.feedback,
+            Param::DelayMix => &mut patch.delay.mix,
+            Param::ReverbSize => &mut patch.reverb.size,
+            Param::ReverbDamping => &mut patch.reverb.damping,
+            Param::ReverbMix => &mut patch.reverb.mix,
+            Param::CompThreshold => &mut patch.compressor.threshold,
+            Param::CompRatio => &mut patch.compressor.ratio,
+            Param::CompMakeup => &mut patch.compressor.makeup,
+            Param::CompAttack => &mut patch.compressor.attack,
+            Param::CompRelease => &mut patch.compressor.release,
+            Param::CrushBits => &mut patch.crusher.bits,
+            Param::CrushRate => &mut patch.crusher.rate,
+            Param::CrushMix => &mut patch.crusher.mix,
+            Param::Width => &mut patch.widener.width,
+            Param::Haas => &mut patch.widener.haas,
+            Param::MasterGain => &mut patch.master.gain,
+            Param::MasterCeiling => &mut patch.master.ceiling,
+        })
+    }
+
+    /// `a` moved `t` (0..1) of the way to `b`. Parameters over a wide
+    /// positive range, like frequencies, ratios and times, move by equal
+    /// factors, so halfway sounds halfway.
+    pub fn mix(self, a: f32, b: f32, t: f32) -> f32 {
+        let range = self.range();
+        if *range.start() > 0.0 && range.end() / range.start() >= 10.0 && a > 0.0 && b > 0.0 {
+            a * (b / a).powf(t)
+        } else {
+            a + (b - a) * t
+        }
+    }
+
+    /// Sets the parameter to `amount` (0..1) of the way through its range.
+    pub fn set(self, patch: &mut Patch, amount: f32) {
+        let range = self.range();
+        self.set_value(patch, range.start() + (range.end() - range.start()) * amount.clamp(0.0, 1.0));
+    }
+
+    /// Sets the parameter to `value` in its own units, kept within its range.
+    pub fn set_value(self, patch: &mut Patch, value: f32) {
+        let range = self.range();
+        let value = value.clamp(*range.start(), *range.end());
+        if let Param::Op(i, OpParam::BitDepth) = self {
+            if let Some(op) = patch.ops.get_mut(i) { op.bit_depth = value.round() as u8; }
+        } else if let Param::Op(i, OpParam::Fine) = self {
+            if let Some(op) = patch.ops.get_mut(i) { op.ratio = COARSE_RATIOS[coarse_ratio(op.ratio)] * (1.0 + value / 100.0); }
+        } else if let Param::Op(i, OpParam::Breakpoint) = self {
+            if let Some(op) = patch.ops.get_mut(i) { op.level_scaling.breakpoint = value.round() as u8; }
+        } else if let Param::Voices = self {
+            patch.max_voices = value.round() as usize;
+        } else if let Param::Macro(i) = self {
+            macros::apply(patch, i, value);
+        } else if let Some(field) = self.field(patch) {
+            *field = value;
+        }
+    }
+}
+
+/// ----------  Switches ----------
+/// Operator settings picked from a list or turned on and off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpSwitch {
+    Waveform,
+    Sync,
+    Envelope, // ADSR or rate/level
+    Noise,
+    Mute,
+    Solo,
+    LeftCurve, // level scaling
+    RightCurve,
+}
+
+impl OpSwitch {
+    pub const ALL: [OpSwitch; 8] = [
+        OpSwitch::Waveform, OpSwitch::Sync, OpSwitch::Envelope, OpSwitch::Noise, OpSwitch::Mute, OpSwitch::Solo,
+        OpSwitch::LeftCurve, OpSwitch::RightCurve,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoSwitch {
+    Shape,
+    Target,
+    Sync,
+}
+
+impl LfoSwitch {
+    pub const ALL: [LfoSwitch; 3] = [LfoSwitch::Shape, LfoSwitch::Target, LfoSwitch::Sync];
+}
+
+/// A discrete patch setting for remote control. On/off switches take 0 or
+/// 1; the others the number of their choice, counting from 0 in the order
+/// the UI lists them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Switch {
+    Op(usize, OpSwitch),
+    Lfo(usize, LfoSwitch),
+    Route(usize, usize), // destination, source
+    Ring(usize, usize),
+    Output(usize),
+    NoteMode,
+    ModMode,
+    Oversample,
+    DcBlock,
+    GlideMode,
+    Mono,
+    Priority,
+    Legato,
+    Mpe,
+    Arp,
+    ArpMode,
+    ArpSync,
+    Sub,
+    SubWave,
+    Filter,
+    FilterMode,
+    Eq,
+    ModFx,
+    ModFxKind,
+    Delay,
+    DelaySync,
+    PingPong,
+    Reverb,
+    Comp,
+    Crush,
+    Widener,
+    Limiter,
+    ClipCurve,
+}
+
+const MOD_MODES: [ModMode; 2] = [ModMode::Frequency, ModMode::Phase];
+const ENV_KINDS: [EnvKind; 2] = [EnvKind::Adsr, EnvKind::RateLevel];
+const GLIDE_MODES: [GlideMode; 2] = [GlideMode::Always, GlideMode::Legato];
+const PRIORITIES: [NotePriority; 3] = [NotePriority::Last, NotePriority::Low, NotePriority::High];
+const OVERSAMPLING: [usize; 3] = [1, 2, 4];
+
+/// The choice numbered `value` (rounded, kept within the list).
+fn choice<T: Copy>(choices: &[T], value: f32) -> T {
+    choices[(value.round().max(0.0) as usize).min(choices.len() - 1)]
+}
+
+impl Switch {
+    /// The settings outside operators, LFOs and routing.
+    const GLOBAL: [Switch; 28] = [
+        Switch::NoteMode, Switch::ModMode, Switch::Oversample, Switch::DcBlock, Switch::GlideMode, Switch::Mono,
+        Switch::Priority, Switch::Legato, Switch::Mpe, Switch::Arp, Switch::ArpMode, Switch::ArpSync, Switch::Sub,
+        Switch::SubWave, Switch::Filter, Switch::FilterMode, Switch::Eq, Switch::ModFx, Switch::ModFxKind,
+        Switch::Delay, Switch::DelaySync, Switch::PingPong, Switch::Reverb, Switch::Comp, Switch::Crush,
+        Switch::Widener, Switch::Limiter, Switch::ClipCurve,
+    ];
+
+    /// Every switch there is, for any patch size.
+    pub fn all() -> impl Iterator<Item = Switch> {
+        let ops = (0..MAX_OPS).flat_map(|i| OpSwitch::ALL.map(move |s| Switch::Op(i, s)));
+        let lfos = (0..LFO_COUNT).flat_map(|i| LfoSwitch::ALL.map(move |s| Switch::Lfo(i, s)));
+        let routes = (0..MAX_OPS).flat_map(|dst| (0..MAX_OPS).flat_map(move |src| [Switch::Route(dst, src), Switch::Ring(dst, src)]));
+        let outputs = (0..MAX_OPS).map(Switch::Output);
+        ops.chain(lfos).chain(routes).chain(outputs).chain(Switch::GLOBAL)
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Switch::Op(i, s) => format!("Op {} {:?}", i + 1, s),
+            Switch::Lfo(i, s) => format!("LFO {} {:?}", i + 1, s),
+            Switch::Route(dst, src) => format!("Op {} From {}", dst + 1, src + 1),
+            Switch::Ring(dst, src) => format!("Op {} Ring {}", dst + 1, src + 1),
+            Switch::Output(i) => format!("Op {} Output", i + 1),
+            Switch::NoteMode => "Note Mode".into(),
+            Switch::ModMode => "Mod Mode".into(),
+            Switch::Oversample => "Oversample".into(),
+            Switch::DcBlock => "DC Block".into(),
+            Switch::GlideMode => "Glide Mode".into(),
+            Switch::Mono => "Mono".into(),
+            Switch::Priority => "Mono Priority".into(),
+            Switch::Legato => "Legato".into(),
+            Switch::Mpe => "MPE".into(),
+            Switch::Arp => "Arp".into(),
+            Switch::ArpMode => "Arp Mode".into(),
+            Switch::ArpSync => "Arp Sync".into(),
+            Switch::Sub => "Sub".into(),
+            Switch::SubWave => "Sub Wave".into(),
+            Switch::Filter => "Filter".into(),
+            Switch::FilterMode => "Filter Mode".into(),
+            Switch::Eq => "EQ".into(),
+            Switch::ModFx => "Mod FX".into(),
+            Switch::ModFxKind => "Mod FX Kind".into(),
+            Switch::Delay => "Delay".into(),
+            Switch::DelaySync => "Delay Sync".into(),
+            Switch::PingPong => "Delay Ping Pong".into(),
+            Switch::Reverb => "Reverb".into(),
+            Switch::Comp => "Comp".into(),
+            Switch::Crush => "Crush".into(),
+            Switch::Widener => "Stereo Width On".into(),
+            Switch::Limiter => "Master Limiter".into(),
+            Switch::ClipCurve => "Master Clip".into(),
+        }
+    }
+
+    /// Remote control address, built like `Param::address`: `/op/1/waveform`,
+    /// `/op/2/from/1`, `/delay/sync`.
+    pub fn address(self) -> String {
+        self.name().split(' ').map(|w| format!("/{}", w.to_lowercase())).collect()
+    }
+
+    pub fn from_address(address: &str) -> Option<Switch> {
+        static ADDRESSES: OnceLock<HashMap<String, Switch>> = OnceLock::new();
+        ADDRESSES.get_or_init(|| Switch::all().map(|s| (s.address(), s)).collect()).get(address).copied()
+    }
+
+    /// Sets the switch from a remote `value`: on at 0.5 and above, or the
+    /// choice with that number.
+    pub fn set(self, patch: &mut Patch, value: f32) {
+        let on = value >= 0.5;
+        match self {
+            Switch::Op(i, s) => {
+                let Some(op) = patch.ops.get_mut(i) else { return };
+                match s {
+                    OpSwitch::Waveform => op.waveform = choice(&Waveform::ALL, value),
+                    OpSwitch::Sync => op.sync = on,
+                    OpSwitch::Envelope => op.env_kind = choice(&ENV_KINDS, value),
+                    OpSwitch::Noise => op.noise = choice(&NoiseKind::ALL, value),
+                    OpSwitch::Mute => op.mute = on,
+                    OpSwitch::Solo => op.solo = on,
+                    OpSwitch::LeftCurve => op.level_scaling.left_curve = choice(&ScaleCurve::ALL, value),
+                    OpSwitch::RightCurve => op.level_scaling.right_curve = choice(&ScaleCurve::ALL, value),
+                }
+            }
+            Switch::Lfo(i, s) => {
+                let Some(lfo) = patch.lfos.get_mut(i) else { return };
+                match s {
+                    LfoSwitch::Shape => lfo.shape = choice(&Waveform::ALL, value),
+                    LfoSwitch::Target => lfo.target = choice(&ModDest::ALL, value),
+                    LfoSwitch::Sync => lfo.sync = on,
+                }
+            }
+            Switch::Route(dst, src) => {
+                if let Some(m) = patch.routing.mods.get_mut(dst).and_then(|row| row.get_mut(src)) { *m = on; }
+            }
+            Switch::Ring(dst, src) => {
+                if let Some(r) = patch.routing.ring.get_mut(dst).and_then(|row| row.get_mut(src)) { *r = on; }
+            }
+            Switch::Output(i) => if let Some(o) = patch.routing.output.get_mut(i) { *o = on },
+            Switch::NoteMode => patch.note_mode = on,
+            Switch::ModMode => patch.mod_mode = choice(&MOD_MODES, value),
+            Switch::Oversample => patch.oversample = choice(&OVERSAMPLING, value),
+            Switch::DcBlock => patch.dc_block = on,
+            Switch::GlideMode => patch.glide_mode = choice(&GLIDE_MODES, value),
+            Switch::Mono => patch.mono = on,
+            Switch::Priority => patch.priority = choice(&PRIORITIES, value),
+            Switch::Legato => patch.legato = on,
+            Switch::Mpe => patch.mpe = on,
+            Switch::Arp => patch.arp.on = on,
+            Switch::ArpMode => patch.arp.mode = choice(&ArpMode::ALL, value),
+            Switch::ArpSync => patch.arp.sync = on,
+            Switch::Sub => patch.sub.on = on,
+            Switch::SubWave => patch.sub.wave = choice(&Waveform::ALL, value),
+            Switch::Filter => patch.filter.on = on,
+            Switch::FilterMode => patch.filter.mode = choice(&FilterMode::ALL, value),
+            Switch::Eq => patch.eq.on = on,
+            Switch::ModFx => patch.mod_fx.on = on,
+            Switch::ModFxKind => patch.mod_fx.kind = choice(&ModFxKind::ALL, value),
+            Switch::Delay => patch.delay.on = on,
+            Switch::DelaySync => patch.delay.sync = on,
+            Switch::PingPong => patch.delay.ping_pong = on,
+            Switch::Reverb => patch.reverb.on = on,
+            Switch::Comp => patch.compressor.on = on,
+            Switch::Crush => patch.crusher.on = on,
+            Switch::Widener => patch.widener.on = on,
+            Switch::Limiter => patch.master.limiter = on,
+            Switch::ClipCurve => patch.master.curve = choice(&ClipCurve::ALL, value),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::Patch;
+
+/// ----------  Preset ----------
+/// What a preset is and who made it, for finding it again; all optional.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetInfo {
+    pub name: String,     // empty shows the file's name
+    pub category: String, // e.g. "Bass", "Keys", "Pad"
+    pub author: String,
+    pub tags: Vec<String>,
+}
+
+impl PresetInfo {
+    /// Whether every word of `query` turns up in the name, category,
+    /// author or tags, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let text = format!("{} {} {} {}", self.name, self.category, self.author, self.tags.join(" ")).to_lowercase();
+        query.to_lowercase().split_whitespace().all(|word| text.contains(word))
+    }
+}
+
+/// A patch as stored on disk: every operator and envelope parameter, without
+/// the running oscillator/envelope state.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Preset {
+    #[serde(default)]
+    pub info: PresetInfo,
+    #[serde(flatten)]
+    pub patch: Patch,
+}
+
+impl Preset {
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_string_pretty(&self.to_json()?)?)?;
+        Ok(())
+    }
+
+    /// The preset's JSON, stamped with the format version.
+    fn to_json(&self) -> Result<Value, Box<dyn Error>> {
+        let mut json = serde_json::to_value(self)?;
+        if let Some(fields) = json.as_object_mut() { fields.insert("version".into(), FORMAT_VERSION.into()); }
+        Ok(json)
+    }
+
+    /// Reads a preset, sizing its routing and LFO targets to its operators.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// Reads a preset from the contents of its file, bringing one saved in
+    /// an older format up to date first.
+    pub fn parse(json: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut json: Value = serde_json::from_slice(json)?;
+        migrate(json.as_object_mut().ok_or("Preset is not a JSON object")?);
+        Self::checked(serde_json::from_value(json)?)
+    }
+
+    fn checked(mut preset: Preset) -> Result<Self, Box<dyn Error>> {
+        if preset.patch.ops.is_empty() { return Err("Preset has no operators".into()); }
+        preset.patch.set_op_count(preset.patch.ops.len());
+        Ok(preset)
+    }
+
+    /// The preset as one line of text to paste into a chat or forum post:
+    /// its JSON deflated and base64-encoded behind `SHARE_PREFIX`.
+    pub fn to_share_string(&self) -> Result<String, Box<dyn Error>> {
+        let json = serde_json::to_vec(&self.to_json()?)?;
+        Ok(format!("{}{}", SHARE_PREFIX, base64_encode(&miniz_oxide::deflate::compress_to_vec(&json, 9))))
+    }
+
+    /// Reads back `to_share_string`'s text; whitespace, such as line breaks
+    /// a forum added, is ignored.
+    pub fn from_share_string(text: &str) -> Result<Self, Box<dyn Error>> {
+        let text: String = text.split_whitespace().collect();
+        let data = text.strip_prefix(SHARE_PREFIX).ok_or("Not a shared patch")?;
+        let data = base64_decode(data).ok_or("Shared patch is damaged")?;
+        let json = miniz_oxide::inflate::decompress_to_vec_with_limit(&data, MAX_SHARED)
+            .map_err(|_| "Shared patch is damaged")?;
+        Self::parse(&json)
+    }
+
+    /// The preset's name, or failing that its file's.
+    pub fn name(&self, path: &Path) -> String {
+        if !self.info.name.is_empty() { return self.info.name.clone(); }
+        path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+    }
+}
+
+/// ----------  Format versions ----------
+/// Version of the preset format this build writes, saved as `"version"`.
+/// Bump it when a change needs more than new fields taking their defaults
+/// (a rename, a new meaning or unit), and add the step to `MIGRATIONS`.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` turns a version `n` preset's fields into version `n + 1`'s.
+/// Files from before versioning count as version 0.
+const MIGRATIONS: [fn(&mut Map<String, Value>); FORMAT_VERSION as usize] = [
+    // 0 → 1: only stamps the version; every field added until then has a default
+    |_| {},
+];
+
+/// Brings the fields of a preset saved in any earlier format up to date.
+/// A newer file loads as far as this build understands it.
+fn migrate(fields: &mut Map<String, Value>) {
+    let version = fields.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > FORMAT_VERSION as u64 {
+        eprintln!("Preset is format {}, newer than this build's {}; settings added since are left out", version, FORMAT_VERSION);
+    }
+    for step in MIGRATIONS.iter().skip(version as usize) { step(fields); }
+}
+
+/// ----------  Shared patches ----------
+/// Starts every shared patch, naming the format so others can be told apart.
+const SHARE_PREFIX: &str = "fmsynth1:";
+/// Largest unpacked patch accepted, so a crafted string can't eat memory.
+const MAX_SHARED: usize = 1 << 20;
+/// URL-safe base64 digits, so links and markdown leave the text alone.
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Unpadded base64 of `data`.
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(BASE64[(bits >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        bits = bits << 6 | BASE64.iter().position(|&d| d == c)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// ----------  Preset folders ----------
+/// The preset files (`.json`) in `dir`, sorted by name.
+pub fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Saves `preset` into `dir` as `<name>.json`, numbering the name when a
+/// file has it already; returns the file written.
+pub fn add(dir: &Path, name: &str, preset: &Preset) -> Result<PathBuf, Box<dyn Error>> {
+    let name: String = name.chars().map(|c| if c.is_alphanumeric() || " -_".contains(c) { c } else { '_' }).collect();
+    let name = if name.trim().is_empty() { "Preset" } else { name.trim() };
+    let path = (1..).map(|n| dir.join(if n == 1 { format!("{}.json", name) } else { format!("{} {}.json", name, n) }))
+        .find(|p| !p.exists()).expect("some name is free");
+    fs::create_dir_all(dir)?;
+    preset.save(&path)?;
+    Ok(path)
+}
+
+/// Every preset in `dir` that loads, with its file; the rest are reported
+/// and skipped.
+pub fn scan(dir: &Path) -> io::Result<Vec<(PathBuf, Preset)>> {
+    Ok(files(dir)?.into_iter().filter_map(|path| match Preset::load(&path) {
+        Ok(preset) => Some((path, preset)),
+        Err(e) => {
+            eprintln!("Skipping {}: {}", path.display(), e);
+            None
+        }
+    }).collect())
+}
@@ -0,0 +1,142 @@
+use std::ops::RangeInclusive;
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::params::{OpParam, Param};
+use crate::{coarse_ratio, EnvKind, Patch, COARSE_RATIOS};
+
+/// ----------  Random numbers ----------
+/// Xorshift generator for patch randomizing and scripts; quick and
+/// repeatable from a seed, nothing more.
+#[derive(Clone, Copy)]
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self { Self(seed.max(1)) }
+
+    /// Seeded from the clock, so every run differs.
+    pub fn from_time() -> Self {
+        Self::new(SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |d| d.subsec_nanos()))
+    }
+
+    /// Uniform in 0..1.
+    pub fn unit(&mut self) -> f32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x as f32 / u32::MAX as f32
+    }
+
+    pub fn range(&mut self, lo: f32, hi: f32) -> f32 { lo + (hi - lo) * self.unit() }
+
+    /// Between `lo` and `hi`, evenly spread on a log scale: as likely to
+    /// land in 10..100 ms as in 100..1000 ms.
+    pub fn log_range(&mut self, lo: f32, hi: f32) -> f32 { lo * (hi / lo).powf(self.unit()) }
+
+    pub fn chance(&mut self, p: f32) -> bool { self.unit() < p }
+
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[((self.unit() * items.len() as f32) as usize).min(items.len() - 1)]
+    }
+}
+
+/// ----------  Randomize ----------
+/// Ratios a carrier may take; unity most often, so the patch plays in tune.
+const CARRIER_RATIOS: [f32; 6] = [1.0, 1.0, 1.0, 0.5, 2.0, 3.0];
+/// Ratios a modulator may take: harmonic, low ones likelier, or now and
+/// then one of the bell-like inharmonic ones.
+const MODULATOR_RATIOS: [f32; 12] = [0.5, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+const INHARMONIC_RATIOS: [f32; 4] = [1.414, 1.732, 2.618, 3.5];
+const INHARMONIC_CHANCE: f32 = 0.1;
+/// Highest level a modulator gets, i.e. its modulation index.
+const MAX_MOD_LEVEL: f32 = 1.2;
+/// Most self-feedback the randomizer dials in; past this it's noise.
+const MAX_RANDOM_FEEDBACK: f32 = 0.3;
+
+/// Rolls new ratios, levels, envelopes and feedback for every operator,
+/// within bounds that usually make a playable sound: carriers stay near
+/// the played pitch and full level, modulators keep mostly harmonic ratios
+/// and a moderate index, and at most one operator feeds back. Routing,
+/// operator count and everything after the operators stay as they were.
+pub fn randomize(patch: &mut Patch, rng: &mut Rng) {
+    let base = patch.ops[0].freq;
+    let feedback_op = rng.chance(0.6).then(|| (rng.unit() * patch.ops.len() as f32) as usize);
+    for (i, op) in patch.ops.iter_mut().enumerate() {
+        let carrier = patch.routing.outputs(i);
+        // Every operator on the same base, so the ratios alone set the pitches
+        op.freq = base;
+        if carrier {
+            op.ratio = rng.pick(&CARRIER_RATIOS);
+            op.amp = rng.range(0.6, 1.0);
+        } else {
+            let ratios: &[f32] = if rng.chance(INHARMONIC_CHANCE) { &INHARMONIC_RATIOS } else { &MODULATOR_RATIOS };
+            op.ratio = rng.pick(ratios);
+            op.amp = MAX_MOD_LEVEL * rng.unit().powi(2); // low indexes more likely
+        }
+        op.detune = if rng.chance(0.3) { rng.range(-7.0, 7.0) } else { 0.0 };
+        op.feedback = if feedback_op == Some(i) { rng.range(0.0, MAX_RANDOM_FEEDBACK) } else { 0.0 };
+        op.velocity_sens = if carrier { rng.range(0.0, 0.7) } else { rng.range(0.0, 1.0) };
+
+        // Modulators fade sooner than carriers, so notes mellow as they ring
+        op.env_kind = EnvKind::Adsr;
+        let env = &mut op.envelope;
+        env.attack = if rng.chance(0.7) { rng.log_range(0.001, 0.02) } else { rng.log_range(0.02, 0.8) };
+        env.decay = if carrier { rng.log_range(0.1, 2.0) } else { rng.log_range(0.05, 1.0) };
+        env.sustain = if rng.chance(0.3) { 0.0 } else { rng.range(0.2, 1.0) };
+        env.release = rng.log_range(0.05, 1.5);
+    }
+}
+
+/// ----------  Mutate and breed ----------
+/// Nudges the operators and filter of `patch` by up to `amount` (0..1) of
+/// a musically sized step: levels and times move by factors, so short
+/// envelopes stay short, and ratios jump to a neighbouring harmonic,
+/// keeping any fine part, rather than drift out of tune.
+pub fn mutate(patch: &mut Patch, rng: &mut Rng, amount: f32) {
+    let amount = amount.clamp(0.0, 1.0);
+    for op in &mut patch.ops {
+        op.amp = nudge(rng, op.amp, amount, 0.0..=2.0);
+        if rng.chance(amount * 0.3) {
+            let coarse = coarse_ratio(op.ratio);
+            let next = coarse.saturating_add_signed(rng.pick(&[-1, 1])).min(COARSE_RATIOS.len() - 1);
+            let range = Param::Op(0, OpParam::Ratio).range();
+            op.ratio = (op.ratio * COARSE_RATIOS[next] / COARSE_RATIOS[coarse]).clamp(*range.start(), *range.end());
+        }
+        op.detune = (op.detune + rng.range(-5.0, 5.0) * amount).clamp(-50.0, 50.0);
+        if op.feedback > 0.0 || rng.chance(amount * 0.1) {
+            op.feedback = (op.feedback + rng.range(-0.1, 0.1) * amount).clamp(0.0, MAX_RANDOM_FEEDBACK);
+        }
+        let env = &mut op.envelope;
+        env.attack = nudge(rng, env.attack, 2.0 * amount, 0.001..=2.0);
+        env.decay = nudge(rng, env.decay, 2.0 * amount, 0.001..=2.0);
+        env.sustain = (env.sustain + rng.range(-0.3, 0.3) * amount).clamp(0.0, 1.0);
+        env.release = nudge(rng, env.release, 2.0 * amount, 0.001..=2.0);
+    }
+    if patch.filter.on {
+        patch.filter.cutoff = nudge(rng, patch.filter.cutoff, amount, 20.0..=20000.0);
+    }
+}
+
+/// `value` scaled by up to `octaves` either way, kept within `range`.
+fn nudge(rng: &mut Rng, value: f32, octaves: f32, range: RangeInclusive<f32>) -> f32 {
+    (value * 2f32.powf(rng.range(-octaves, octaves))).clamp(*range.start(), *range.end())
+}
+
+/// A child of `a` and `b`. Each operator comes whole from one parent or
+/// the other; every other continuous setting lands somewhere between the
+/// parents' values. Routing, switches and modes follow `a`, and the child
+/// has `a`'s operator count.
+pub fn breed(a: &Patch, b: &Patch, rng: &mut Rng) -> Patch {
+    let mut child = a.clone();
+    let mut other = b.clone();
+    for (op, theirs) in child.ops.iter_mut().zip(&other.ops) {
+        if rng.chance(0.5) { *op = theirs.clone(); }
+    }
+    for param in Param::all().filter(|p| !matches!(p, Param::Op(..))) {
+        if let (Some(mine), Some(theirs)) = (param.field(&mut child), param.field(&mut other)) {
+            *mine += (*theirs - *mine) * rng.unit();
+        }
+    }
+    child
+}
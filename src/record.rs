@@ -0,0 +1,137 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::export::{AudioWriter, ExportOptions};
+
+/// ----------  Live recording ----------
+/// Seconds of output the ring holds while the writer catches up.
+const RING_SECONDS: usize = 2;
+/// How often the writer wakes to empty the ring.
+const DRAIN: Duration = Duration::from_millis(20);
+
+/// Creates the audio side and the UI side of a stereo recorder running at
+/// `sr`. Nothing is captured until `Recorder::start`.
+pub fn recorder(sr: f32) -> (Capture, Recorder) {
+    let (tx, rx) = RingBuffer::new(sr as usize * 2 * RING_SECONDS);
+    let armed = Arc::new(AtomicBool::new(false));
+    (Capture { tx, armed: armed.clone() },
+     Recorder { rx: Some(rx), armed, writer: None, path: None, sr: sr as u32 })
+}
+
+/// Audio side. Never blocks or allocates; frames that don't fit are dropped.
+pub struct Capture {
+    tx: Producer<f32>,
+    armed: Arc<AtomicBool>,
+}
+
+impl Capture {
+    /// Keeps the first two channels of interleaved `frames` (mono twice).
+    pub fn write_frames(&mut self, frames: &[f32], channels: usize) {
+        if !self.armed.load(Ordering::Relaxed) { return; }
+        for frame in frames.chunks_exact(channels.max(1)) {
+            let (l, r) = (frame[0], *frame.get(1).unwrap_or(&frame[0]));
+            if self.tx.slots() < 2 { return; }
+            let _ = self.tx.push(l);
+            let _ = self.tx.push(r);
+        }
+    }
+}
+
+type Writer = JoinHandle<(Consumer<f32>, Result<(), String>)>;
+
+/// UI side: starts and stops takes, each written to its own file by a
+/// writer thread.
+pub struct Recorder {
+    rx: Option<Consumer<f32>>, // with the writer while a take runs
+    armed: Arc<AtomicBool>,
+    writer: Option<Writer>,
+    path: Option<PathBuf>,
+    sr: u32,
+}
+
+impl Recorder {
+    pub fn is_recording(&self) -> bool { self.writer.is_some() }
+
+    /// The file being written, or the last one finished.
+    pub fn path(&self) -> Option<&Path> { self.path.as_deref() }
+
+    /// Rate the next take is written at, once the output changes rate.
+    pub fn set_sample_rate(&mut self, sr: f32) { self.sr = sr as u32; }
+
+    /// Starts writing a stereo file to `path`.
+    pub fn start(&mut self, path: PathBuf, options: &ExportOptions) -> Result<(), Box<dyn Error>> {
+        let Some(mut rx) = self.rx.take() else { return Err("Already recording".into()) };
+        // Whatever the last take left behind is not part of this one
+        if let Ok(stale) = rx.read_chunk(rx.slots()) { stale.commit_all(); }
+        // Some encoders must stay on the thread that made them, so the writer
+        // opens the file itself and reports back whether that worked
+        let (opened_tx, opened) = crossbeam_channel::bounded(1);
+        self.armed.store(true, Ordering::Relaxed);
+        let (armed, sr, options, file) = (self.armed.clone(), self.sr, *options, path.clone());
+        let writer = thread::spawn(move || {
+            let mut out = match AudioWriter::create(&file, sr, &options) {
+                Ok(out) => { let _ = opened_tx.send(Ok(())); out }
+                Err(e) => { let _ = opened_tx.send(Err(e.to_string())); return (rx, Ok(())); }
+            };
+            let mut result = Ok(());
+            let mut buf = Vec::new();
+            loop {
+                let last = !armed.load(Ordering::Relaxed);
+                // Whole frames only: the audio side may be between left and right
+                if let Ok(chunk) = rx.read_chunk(rx.slots() & !1) {
+                    buf.clear();
+                    buf.extend(chunk);
+                    if result.is_ok() { result = out.write(&buf).map_err(|e| e.to_string()); }
+                }
+                if last { break; }
+                thread::sleep(DRAIN);
+            }
+            let result = result.and(out.finish().map_err(|e| e.to_string()));
+            (rx, result)
+        });
+        if let Err(e) = opened.recv().unwrap_or(Err("Recorder thread panicked".into())) {
+            self.armed.store(false, Ordering::Relaxed);
+            self.rx = writer.join().ok().map(|(rx, _)| rx);
+            return Err(e.into());
+        }
+        self.writer = Some(writer);
+        self.path = Some(path);
+        Ok(())
+    }
+
+    /// Ends the take and waits for its file to be complete.
+    pub fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(writer) = self.writer.take() else { return Ok(()) };
+        self.armed.store(false, Ordering::Relaxed);
+        let (rx, result) = writer.join().map_err(|_| "Recorder thread panicked")?;
+        self.rx = Some(rx);
+        Ok(result?)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) { let _ = self.stop(); }
+}
+
+/// `fm_synth_YYYYMMDD_HHMMSS.<ext>` in `dir`, stamped with the current UTC time.
+pub fn timestamped_path(dir: &Path, ext: &str) -> PathBuf {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    dir.join(format!("fm_synth_{:04}{:02}{:02}_{:02}{:02}{:02}.{}",
+                     year, month, day, rem / 3600, rem / 60 % 60, rem % 60, ext))
+}
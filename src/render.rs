@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::export::{self, AudioWriter, ExportOptions};
+use crate::midifile::Song;
+use crate::preset;
+use crate::{midi_to_freq, Effect, Event, FMSynth, Patch, Preset};
+
+const BLOCK: usize = 512;
+/// Seconds rendered after a song's last event, for the release tails.
+const SONG_TAIL: f64 = 2.0;
+/// Seconds a note is held before its cycle is taken, past attack and decay.
+const CYCLE_SETTLE: f32 = 1.0;
+/// Highest rate a cycle is rendered at; shorter periods are stretched to
+/// the cycle's length from there.
+const MAX_CYCLE_RENDER_SR: f32 = 192_000.0;
+/// Sample rate written into single-cycle files; wavetable synths only look
+/// at the sample count.
+const CYCLE_SR: u32 = 44100;
+
+/// ----------  Offline rendering ----------
+/// Renders `seconds` of `synth` playing one note into a stereo file. The
+/// note is held for the first three quarters and released for the rest, so
+/// the file captures both the sustain and the release tail.
+pub fn render_note(synth: &mut FMSynth, path: &Path, options: &ExportOptions, seconds: f32) -> Result<(), Box<dyn Error>> {
+    let total = (seconds * synth.sample_rate()) as usize;
+    let release = total * 3 / 4;
+    render_events(synth, path, options, &[(0, Event::Gate(true)), (release, Event::Gate(false))], total)
+}
+
+/// Renders `song` played by `synth` into a stereo file, with a couple of
+/// seconds after the last event for the notes to ring out.
+pub fn render_song(synth: &mut FMSynth, song: &Song, path: &Path, options: &ExportOptions) -> Result<(), Box<dyn Error>> {
+    let sr = synth.sample_rate() as f64;
+    let events: Vec<(usize, Event)> = song.events.iter().map(|&(t, e)| ((t * sr) as usize, e)).collect();
+    render_events(synth, path, options, &events, ((song.duration() + SONG_TAIL) * sr) as usize)
+}
+
+/// Renders a demo note of every preset (`.json`) in `dir` into `out`, one
+/// file per preset named after it, for patch preview libraries. Presets that
+/// fail to load or render are reported and skipped; returns the files written.
+pub fn render_bank(dir: &Path, out: &Path, options: &ExportOptions, sr: f32, seconds: f32)
+    -> Result<Vec<PathBuf>, Box<dyn Error>>
+{
+    fs::create_dir_all(out)?;
+    let mut written = Vec::new();
+    for preset in preset::files(dir)? {
+        let file = out.join(preset.file_stem().unwrap_or_default()).with_extension(options.format.extension());
+        let mut synth = FMSynth::new(sr);
+        let result = Preset::load(&preset).and_then(|p| {
+            synth.patch = p.patch;
+            render_note(&mut synth, &file, options, seconds)
+        });
+        match result {
+            Ok(()) => written.push(file),
+            Err(e) => eprintln!("Skipping {}: {}", preset.display(), e),
+        }
+    }
+    Ok(written)
+}
+
+/// Renders one file per operator heard at the output, named after `path`
+/// with the operator's number (`take_op0.wav`, …), playing `song` or, without
+/// one, a note of `seconds`. Each stem runs the patch's effects on its own,
+/// so they add back up to the full mix wherever those are linear. Returns
+/// the files written.
+pub fn render_stems(patch: &Patch, sr: f32, song: Option<&Song>, path: &Path, options: &ExportOptions, seconds: f32)
+    -> Result<Vec<PathBuf>, Box<dyn Error>>
+{
+    let soloing = patch.ops.iter().any(|o| o.solo);
+    let heard: Vec<usize> = (0..patch.op_count())
+        .filter(|&i| !patch.ops[i].mute && if soloing { patch.ops[i].solo } else { patch.routing.outputs(i) })
+        .collect();
+    if heard.is_empty() { return Err("No operator reaches the output".into()); }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut written = Vec::new();
+    for (n, &op) in heard.iter().enumerate() {
+        let file = path.with_file_name(format!("{}_op{}.{}", stem, op, options.format.extension()));
+        let mut synth = FMSynth::new(sr);
+        synth.patch = patch.clone();
+        for (i, o) in synth.patch.ops.iter_mut().enumerate() { o.solo = i == op; }
+        // The sub follows the first carrier, so it belongs to that stem
+        synth.patch.sub.on &= n == 0;
+        match song {
+            Some(song) => render_song(&mut synth, song, &file, options)?,
+            None => render_note(&mut synth, &file, options, seconds)?,
+        }
+        written.push(file);
+    }
+    Ok(written)
+}
+
+/// Writes one steady-state cycle of `patch` playing `note` to a mono WAV of
+/// `length` samples for wavetable synths and samplers. Where it can, the
+/// synth runs at exactly `length` samples per period of the note; for high
+/// notes and long cycles that would take a huge rate, so it runs at
+/// `MAX_CYCLE_RENDER_SR` and one period is resampled to `length`. Either
+/// way the cycle loops seamlessly; it starts on a rising zero crossing and
+/// is normalized.
+pub fn render_cycle(patch: &Patch, note: u8, length: usize, bits: u16, path: &Path) -> Result<(), Box<dyn Error>> {
+    let length = length.max(2);
+    let freq = patch.key_freq(note).unwrap_or_else(|| midi_to_freq(note, patch.a4));
+    let (sr, period) = if freq * length as f32 <= MAX_CYCLE_RENDER_SR { (freq * length as f32, length as f32) }
+                       else { (MAX_CYCLE_RENDER_SR, MAX_CYCLE_RENDER_SR / freq) };
+    let mut synth = FMSynth::new(sr);
+    synth.patch = patch.clone();
+    synth.patch.arp.on = false;
+    // Echoes and tails would smear the cycle
+    for effect in [Effect::ModFx, Effect::Delay, Effect::Reverb] { *synth.patch.effect_on(effect) = false; }
+    synth.handle(Event::NoteOn { note, velocity: 100, channel: 0 });
+    let mut settle = vec![0.0; BLOCK];
+    for _ in 0..(CYCLE_SETTLE * sr) as usize / BLOCK { synth.render_block(&mut settle, 1); }
+
+    let span = period.ceil() as usize;
+    let mut two = vec![0.0; span * 2 + 1];
+    synth.render_block(&mut two, 1);
+    let start = (1..span).find(|&i| two[i - 1] < 0.0 && two[i] >= 0.0).unwrap_or(0);
+    let cycle: Vec<f32> = (0..length).map(|i| {
+        let pos = start as f32 + i as f32 * period / length as f32;
+        let (j, frac) = (pos as usize, pos.fract());
+        two[j] + (two[j + 1] - two[j]) * frac
+    }).collect();
+    let peak = cycle.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+    if peak < 1e-6 { return Err("The patch is silent".into()); }
+
+    let bits = if matches!(bits, 16 | 24 | 32) { bits } else { 16 };
+    let sample_format = if bits == 32 { SampleFormat::Float } else { SampleFormat::Int };
+    let spec = WavSpec { channels: 1, sample_rate: CYCLE_SR, bits_per_sample: bits, sample_format };
+    let mut wav = WavWriter::create(path, spec)?;
+    for &s in &cycle {
+        if bits == 32 { wav.write_sample(s / peak)?; } else { wav.write_sample(export::to_int(s / peak, bits))?; }
+    }
+    wav.finalize()?;
+    Ok(())
+}
+
+/// Renders `total` frames, handing `synth` each event (time in frames,
+/// in order) exactly on its frame.
+fn render_events(synth: &mut FMSynth, path: &Path, options: &ExportOptions, events: &[(usize, Event)], total: usize)
+    -> Result<(), Box<dyn Error>>
+{
+    let mut out = AudioWriter::create(path, synth.sample_rate() as u32, options)?;
+    let mut buf = [0.0f32; BLOCK * 2];
+    let mut pending = events.iter().peekable();
+    let mut pos = 0;
+    while pos < total {
+        while let Some(&(_, event)) = pending.next_if(|(at, _)| *at <= pos) { synth.handle(event); }
+        // Stop blocks at the next event so it lands sample-exact
+        let end = pending.peek().map_or(total, |(at, _)| (*at).min(total));
+        let n = BLOCK.min(end - pos);
+        synth.render_block(&mut buf[..n * 2], 2);
+        out.write(&buf[..n * 2])?;
+        pos += n;
+    }
+    out.finish()
+}
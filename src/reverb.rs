@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Reverb ----------
+/// Master reverb settings.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Reverb {
+    pub on: bool,
+    pub size: f32,    // 0..1, room size: how long the tail rings
+    pub damping: f32, // 0..1, how fast the highs die away
+    pub mix: f32,     // 0..1, dry to wet
+}
+
+impl Default for Reverb {
+    fn default() -> Self { Self { on: false, size: 0.5, damping: 0.5, mix: 0.25 } }
+}
+
+/// Freeverb's comb and allpass lengths in samples at 44.1 kHz; the right
+/// channel's are `STEREO_SPREAD` longer so the two decorrelate.
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+/// Keeps the sum of eight combs from clipping.
+const INPUT_GAIN: f32 = 0.015;
+const WET_GAIN: f32 = 3.0;
+
+/// Lowpassed feedback comb filter.
+struct Comb {
+    buf: Vec<f32>,
+    pos: usize,
+    store: f32, // damping filter state
+}
+
+impl Comb {
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let out = self.buf[self.pos];
+        self.store = out * (1.0 - damp) + self.store * damp;
+        self.buf[self.pos] = input + self.store * feedback;
+        self.pos = (self.pos + 1) % self.buf.len();
+        out
+    }
+}
+
+/// Schroeder allpass with Freeverb's fixed 0.5 feedback.
+struct Allpass {
+    buf: Vec<f32>,
+    pos: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buf[self.pos];
+        self.buf[self.pos] = input + delayed * 0.5;
+        self.pos = (self.pos + 1) % self.buf.len();
+        delayed - input
+    }
+}
+
+/// Jezar's Freeverb: per channel, eight parallel combs into four allpasses
+/// in series. Lines are sized for the sample rate up front.
+pub(crate) struct Freeverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    was_on: bool,
+}
+
+impl Freeverb {
+    pub fn new(sr: f32) -> Self {
+        let scale = |len: usize, ch: usize| ((len + ch * STEREO_SPREAD) as f32 * sr / 44100.0).max(1.0) as usize;
+        Self {
+            combs: std::array::from_fn(|ch| COMBS.iter()
+                .map(|&len| Comb { buf: vec![0.0; scale(len, ch)], pos: 0, store: 0.0 }).collect()),
+            allpasses: std::array::from_fn(|ch| ALLPASSES.iter()
+                .map(|&len| Allpass { buf: vec![0.0; scale(len, ch)], pos: 0 }).collect()),
+            was_on: false,
+        }
+    }
+
+    fn clear(&mut self) {
+        for c in self.combs.iter_mut().flatten() { c.buf.fill(0.0); c.store = 0.0; }
+        for a in self.allpasses.iter_mut().flatten() { a.buf.fill(0.0); }
+    }
+
+    /// Adds the reverb to a stereo block in place. Switching it back on
+    /// starts from silence rather than an old tail.
+    pub fn process(&mut self, settings: &Reverb, l: &mut [f32], r: &mut [f32]) {
+        if !settings.on { self.was_on = false; return; }
+        if !self.was_on { self.clear(); self.was_on = true; }
+        let feedback = 0.7 + 0.28 * settings.size.clamp(0.0, 1.0);
+        let damp = 0.4 * settings.damping.clamp(0.0, 1.0);
+        let mix = settings.mix.clamp(0.0, 1.0);
+        for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+            let input = (*a + *b) * INPUT_GAIN;
+            let wet: [f32; 2] = std::array::from_fn(|ch| {
+                let mut out = self.combs[ch].iter_mut().map(|c| c.process(input, feedback, damp)).sum();
+                for ap in &mut self.allpasses[ch] { out = ap.process(out); }
+                out * WET_GAIN
+            });
+            *a = *a * (1.0 - mix) + wet[0] * mix;
+            *b = *b * (1.0 - mix) + wet[1] * mix;
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use crate::params::Param;
+use crate::random::Rng;
+use crate::Patch;
+
+/// ----------  Patch scripts ----------
+/// Most passes all `for` loops together may make in one run, nested ones
+/// included, so a runaway range can't hang the UI.
+const MAX_ITERATIONS: i64 = 10_000;
+
+/// A small language for generating and animating patches, one statement
+/// per line and `#` starting a comment:
+///
+/// ```text
+/// let base = 1 + floor(rand(0, 4))
+/// for i in 0..ops {
+///     /op/{i + 1}/ratio = base * (i + 1)
+///     /op/{i + 1}/pan = sin(t + i)
+/// }
+/// /filter/cutoff = 1200 + 800 * sin(beat * pi / 2)
+/// ```
+///
+/// Parameters are set at their addresses (see `Param::address`) in their
+/// own units, and `{…}` in an address stands for the whole number inside.
+/// `t` is the time the script has been running in seconds, `beat` the same
+/// in beats at the patch's tempo and `ops` the number of operators.
+pub struct Script {
+    statements: Vec<(usize, Statement)>, // with 1-based line numbers
+    rng: Rng,                            // for `rand`
+}
+
+enum Statement {
+    Let(String, Expr),
+    Set(Target, Expr),
+    For(String, Expr, Expr, Vec<(usize, Statement)>),
+}
+
+/// Where a `Set` writes: a fixed address is looked up once when parsed,
+/// one with `{…}` in it on every run.
+enum Target {
+    Param(Param),
+    Address(Vec<Segment>),
+}
+
+enum Segment {
+    Text(String),
+    Number(Expr),
+}
+
+enum Expr {
+    Num(f32),
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl Script {
+    /// Parses `source`; errors name the line they're on.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut lines = source.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let statements = block(&mut lines, false)?;
+        Ok(Self { statements, rng: Rng::from_time() })
+    }
+
+    /// Runs the script over `patch` at `t` seconds in.
+    pub fn run(&mut self, patch: &mut Patch, t: f32) -> Result<(), String> {
+        let mut vars = HashMap::from([
+            ("t".to_string(), t),
+            ("beat".to_string(), t * patch.tempo / 60.0),
+            ("pi".to_string(), PI),
+            ("ops".to_string(), patch.ops.len() as f32),
+        ]);
+        let mut budget = MAX_ITERATIONS;
+        exec(&self.statements, &mut vars, patch, &mut self.rng, &mut budget)
+    }
+}
+
+/// ----------  Parsing ----------
+/// Statements up to the end of the source or, when `nested`, the `}`
+/// closing the block.
+fn block<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, nested: bool)
+    -> Result<Vec<(usize, Statement)>, String>
+{
+    let mut statements = Vec::new();
+    while let Some((n, line)) = lines.next() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let err = |e: String| format!("line {}: {}", n, e);
+        if line.is_empty() { continue; }
+        if line == "}" {
+            return if nested { Ok(statements) } else { Err(err("unexpected }".into())) };
+        }
+        let statement = if let Some(rest) = line.strip_prefix("let ") {
+            let (name, value) = rest.split_once('=').ok_or_else(|| err("expected let name = value".into()))?;
+            Statement::Let(name.trim().to_string(), expr(value).map_err(err)?)
+        } else if let Some(rest) = line.strip_prefix("for ") {
+            let header = rest.strip_suffix('{').ok_or_else(|| err("expected { after the range".into()))?;
+            let (name, range) = header.split_once(" in ").ok_or_else(|| err("expected for name in a..b {".into()))?;
+            let (from, to) = range.split_once("..").ok_or_else(|| err("expected a range a..b".into()))?;
+            let (from, to) = (expr(from).map_err(err)?, expr(to).map_err(err)?);
+            Statement::For(name.trim().to_string(), from, to, block(lines, true)?)
+        } else if line.starts_with('/') {
+            let (address, value) = line.split_once('=').ok_or_else(|| err("expected /address = value".into()))?;
+            Statement::Set(target(address.trim()).map_err(err)?, expr(value).map_err(err)?)
+        } else {
+            return Err(err(format!("expected let, for or a /parameter, found {}", line)));
+        };
+        statements.push((n, statement));
+    }
+    if nested { Err("missing } at the end".into()) } else { Ok(statements) }
+}
+
+fn target(address: &str) -> Result<Target, String> {
+    if !address.contains('{') {
+        return Param::from_address(address).map(Target::Param).ok_or_else(|| format!("no parameter at {}", address));
+    }
+    let mut segments = Vec::new();
+    let mut rest = address;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..].find('}').ok_or("missing } in the address")? + open;
+        segments.push(Segment::Text(rest[..open].to_string()));
+        segments.push(Segment::Number(expr(&rest[open + 1..close])?));
+        rest = &rest[close + 1..];
+    }
+    segments.push(Segment::Text(rest.to_string()));
+    Ok(Target::Address(segments))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f32),
+    Name(String),
+    Sym(char),
+}
+
+fn tokens(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut s = String::new();
+            while let Some(d) = chars.next_if(|d| d.is_ascii_digit() || *d == '.') { s.push(d); }
+            tokens.push(Token::Num(s.parse().map_err(|_| format!("bad number {}", s))?));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut s = String::new();
+            while let Some(d) = chars.next_if(|d| d.is_alphanumeric() || *d == '_') { s.push(d); }
+            tokens.push(Token::Name(s));
+        } else if "+-*/%^(),".contains(c) {
+            tokens.push(Token::Sym(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected {}", c));
+        }
+    }
+    Ok(tokens)
+}
+
+fn expr(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokens(source)?, pos: 0 };
+    let e = parser.sum()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(e),
+        Some(t) => Err(format!("unexpected {:?}", t)),
+    }
+}
+
+/// Recursive descent over one expression's tokens, loosest binding first.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn eat(&mut self, sym: char) -> bool {
+        let found = self.tokens.get(self.pos) == Some(&Token::Sym(sym));
+        if found { self.pos += 1; }
+        found
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut e = self.product()?;
+        while let Some(op) = ['+', '-'].into_iter().find(|&op| self.eat(op)) {
+            e = Expr::Binary(op, Box::new(e), Box::new(self.product()?));
+        }
+        Ok(e)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut e = self.unary()?;
+        while let Some(op) = ['*', '/', '%'].into_iter().find(|&op| self.eat(op)) {
+            e = Expr::Binary(op, Box::new(e), Box::new(self.unary()?));
+        }
+        Ok(e)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') { return Ok(Expr::Neg(Box::new(self.unary()?))); }
+        let base = self.atom()?;
+        if self.eat('^') { return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?))); }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("expression ends too soon")?;
+        self.pos += 1;
+        match token {
+            Token::Num(v) => Ok(Expr::Num(v)),
+            Token::Name(name) if self.eat('(') => {
+                let mut args = Vec::new();
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.sum()?);
+                        if self.eat(')') { break; }
+                        if !self.eat(',') { return Err(format!("expected , or ) in {}()", name)); }
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Token::Name(name) => Ok(Expr::Var(name)),
+            Token::Sym('(') => {
+                let e = self.sum()?;
+                if self.eat(')') { Ok(e) } else { Err("missing )".into()) }
+            }
+            Token::Sym(c) => Err(format!("unexpected {}", c)),
+        }
+    }
+}
+
+/// ----------  Running ----------
+/// `budget` is the number of loop passes left for the whole run.
+fn exec(statements: &[(usize, Statement)], vars: &mut HashMap<String, f32>, patch: &mut Patch, rng: &mut Rng,
+        budget: &mut i64) -> Result<(), String>
+{
+    for (n, statement) in statements {
+        let err = |e: String| format!("line {}: {}", n, e);
+        match statement {
+            Statement::Let(name, value) => {
+                let v = value.eval(vars, rng).map_err(err)?;
+                vars.insert(name.clone(), v);
+            }
+            Statement::Set(target, value) => {
+                let param = match target {
+                    Target::Param(param) => *param,
+                    Target::Address(segments) => {
+                        let mut address = String::new();
+                        for segment in segments {
+                            match segment {
+                                Segment::Text(s) => address.push_str(s),
+                                Segment::Number(e) => address.push_str(&(e.eval(vars, rng).map_err(err)?.round() as i64).to_string()),
+                            }
+                        }
+                        Param::from_address(&address).ok_or_else(|| err(format!("no parameter at {}", address)))?
+                    }
+                };
+                let v = value.eval(vars, rng).map_err(err)?;
+                if !v.is_finite() { return Err(err(format!("{} comes to {}", param.address(), v))); }
+                param.set_value(patch, v);
+            }
+            Statement::For(name, from, to, body) => {
+                // Bounds past what f32 counts exactly can't make a sensible loop anyway
+                let bound = |v: f32| v.round().clamp(-(1 << 24) as f32, (1 << 24) as f32) as i64;
+                let from = bound(from.eval(vars, rng).map_err(err)?);
+                let to = bound(to.eval(vars, rng).map_err(err)?);
+                let passes = to.saturating_sub(from).max(0);
+                if passes > *budget { return Err(err(format!("more than {} loop passes in all", MAX_ITERATIONS))); }
+                *budget -= passes;
+                for i in from..to {
+                    vars.insert(name.clone(), i as f32);
+                    exec(body, vars, patch, rng, budget)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Expr {
+    fn eval(&self, vars: &HashMap<String, f32>, rng: &mut Rng) -> Result<f32, String> {
+        Ok(match self {
+            Expr::Num(v) => *v,
+            Expr::Var(name) => *vars.get(name).ok_or_else(|| format!("unknown name {}", name))?,
+            Expr::Neg(e) => -e.eval(vars, rng)?,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(vars, rng)?, b.eval(vars, rng)?);
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    '%' => a.rem_euclid(b),
+                    _ => a.powf(b),
+                }
+            }
+            Expr::Call(name, args) => {
+                let args = args.iter().map(|e| e.eval(vars, rng)).collect::<Result<Vec<_>, _>>()?;
+                match (name.as_str(), args.as_slice()) {
+                    ("sin", &[x]) => x.sin(),
+                    ("cos", &[x]) => x.cos(),
+                    ("tan", &[x]) => x.tan(),
+                    ("abs", &[x]) => x.abs(),
+                    ("floor", &[x]) => x.floor(),
+                    ("ceil", &[x]) => x.ceil(),
+                    ("round", &[x]) => x.round(),
+                    ("sqrt", &[x]) => x.sqrt(),
+                    ("exp", &[x]) => x.exp(),
+                    ("ln", &[x]) => x.ln(),
+                    ("min", &[a, b]) => a.min(b),
+                    ("max", &[a, b]) => a.max(b),
+                    ("clamp", &[x, lo, hi]) => x.max(lo.min(hi)).min(hi.max(lo)),
+                    ("lerp", &[a, b, x]) => a + (b - a) * x,
+                    ("rand", &[]) => rng.unit(),
+                    ("rand", &[lo, hi]) => rng.range(lo, hi),
+                    _ => return Err(format!("no function {} taking {} arguments", name, args.len())),
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use crate::arp::NoteChange;
+
+pub const SEQ_STEPS: usize = 16;
+
+/// ----------  Step sequencer ----------
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Step {
+    pub on: bool,     // off steps are rests
+    pub note: u8,
+    pub velocity: u8,
+    pub gate: f32,    // 0..1, part of the step the note is held for
+    pub tie: bool,    // hold on into the next step: the same note carries on, another overlaps it
+                      // (which slides without retriggering only in mono legato)
+}
+
+impl Default for Step {
+    fn default() -> Self { Self { on: true, note: 60, velocity: 100, gate: 0.5, tie: false } }
+}
+
+/// Euclidean rhythm: `pulses` hits spread as evenly as possible over the
+/// pattern's steps, turned `rotation` steps to the left. While `on` it
+/// decides which steps play in place of their own on switches.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Euclid {
+    pub on: bool,
+    pub pulses: usize,
+    pub rotation: usize,
+}
+
+impl Default for Euclid {
+    fn default() -> Self { Self { on: false, pulses: 5, rotation: 0 } }
+}
+
+impl Euclid {
+    /// Whether step `i` of `steps` is a hit (the Bresenham form of Bjorklund).
+    pub fn hit(&self, i: usize, steps: usize) -> bool {
+        let pulses = self.pulses.min(steps);
+        steps > 0 && (i + self.rotation) * pulses % steps < pulses
+    }
+}
+
+/// A pattern of up to `SEQ_STEPS` steps. It plays while `on` and the
+/// transport runs, at the synth's tempo: the patch's or an external clock's.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sequence {
+    pub on: bool,
+    pub steps: [Step; SEQ_STEPS],
+    pub length: usize, // steps before it wraps, 1..=SEQ_STEPS
+    pub beats: f32,    // step length in quarter notes
+    #[serde(default)]
+    pub euclid: Euclid,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Self { on: false, steps: [Step::default(); SEQ_STEPS], length: SEQ_STEPS, beats: 0.25, euclid: Euclid::default() }
+    }
+}
+
+impl Sequence {
+    /// Whether step `i` plays or rests.
+    pub fn plays(&self, i: usize) -> bool {
+        let len = self.length.clamp(1, SEQ_STEPS);
+        if self.euclid.on { self.euclid.hit(i % len, len) } else { self.steps[i % len].on }
+    }
+}
+
+/// The running sequencer: transport state and position in the pattern.
+#[derive(Default)]
+pub(crate) struct SeqPlayer {
+    running: bool,
+    next: usize,          // step to play next
+    tied: bool,           // the step playing ties into the next one
+    phase: f32,           // 0..1 through the current step
+    sounding: Option<u8>,
+}
+
+impl SeqPlayer {
+    /// Runs the pattern from its first step, starting right away.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.next = 0;
+        self.phase = 1.0;
+    }
+
+    pub fn resume(&mut self) { self.running = true; }
+
+    /// Halts the transport; returns the note left sounding, if any.
+    pub fn stop(&mut self) -> Option<u8> {
+        self.running = false;
+        self.stop_note()
+    }
+
+    /// Moves `dt` seconds on at `bpm`, closing the gate and playing the next
+    /// step when their times come.
+    pub fn advance(&mut self, seq: &Sequence, dt: f32, bpm: f32) -> NoteChange {
+        let mut change = NoteChange::default();
+        if !seq.on || !self.running { change.off = self.stop_note(); return change; }
+        let len = seq.length.clamp(1, SEQ_STEPS);
+        let gate = seq.steps[(self.next + len - 1) % len].gate.clamp(0.01, 1.0);
+        self.phase += bpm / 60.0 / seq.beats.max(1.0 / 64.0) * dt;
+        if self.phase >= gate && self.phase < 1.0 && !self.tied { change.off = self.sounding.take(); }
+        if self.phase < 1.0 { return change; }
+
+        self.phase = self.phase.fract();
+        let (step, on) = (seq.steps[self.next % len], seq.plays(self.next));
+        self.next = (self.next + 1) % len;
+        let tied = std::mem::replace(&mut self.tied, on && step.tie);
+        if !on {
+            change.off = self.sounding.take();
+        } else if !(tied && self.sounding == Some(step.note)) {
+            change.on = Some((step.note.min(127), step.velocity.clamp(1, 127)));
+            change.off = self.sounding.replace(step.note.min(127));
+        }
+        change
+    }
+
+    fn stop_note(&mut self) -> Option<u8> {
+        self.tied = false;
+        self.sounding.take()
+    }
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+use crate::Waveform;
+
+/// ----------  Sub oscillator ----------
+/// A plain oscillator an octave or two under the carrier, added to each
+/// voice after the FM and before the filter. It follows the carrier's
+/// pitch and envelope.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct SubOsc {
+    pub on: bool,
+    pub octaves: u8, // 1 or 2 below the carrier
+    pub wave: Waveform,
+    pub level: f32,  // 0..1
+}
+
+impl Default for SubOsc {
+    fn default() -> Self { Self { on: false, octaves: 1, wave: Waveform::Square, level: 0.5 } }
+}
+
+impl SubOsc {
+    /// Frequency under a carrier running at `carrier` Hz.
+    pub fn freq(&self, carrier: f32) -> f32 { carrier / (1u32 << self.octaves.clamp(1, 2)) as f32 }
+}
+
+/// One voice's sub oscillator phase, in cycles.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct SubPhase(f32);
+
+impl SubPhase {
+    pub fn reset(&mut self) { self.0 = 0.0; }
+
+    /// The next sample at `freq`, `dt` seconds on.
+    pub fn sample(&mut self, sub: &SubOsc, freq: f32, dt: f32) -> f32 {
+        let step = freq * dt;
+        self.0 = (self.0 + step).fract();
+        sub.wave.eval(self.0 * TAU, step)
+    }
+}
@@ -0,0 +1,825 @@
+use serde::{Deserialize, Serialize};
+
+use crate::arp::{Arp, Arpeggiator, NoteChange};
+use crate::eq::{Eq, Equalizer};
+use crate::filter::{Filter, VoiceFilter};
+use crate::fx::{Effect, FxChain};
+use crate::keyscale;
+use crate::lfo::{self, Lfo, LFO_COUNT};
+use crate::macros::{Macro, MACROS};
+use crate::master::{Limiter, Master};
+use crate::modfx::{ModFx, ModulationFx};
+use crate::modmatrix::{self, ModDest, ModSlot, ModSource, Sources, MOD_SLOTS};
+use crate::noise::Noise;
+use crate::compressor::{BusCompressor, Compressor};
+use crate::crusher::{Bitcrusher, Crusher};
+use crate::delay::{Delay, StereoDelay};
+use crate::oversample::Decimator;
+use crate::reverb::{Freeverb, Reverb};
+use crate::seq::{SeqPlayer, Sequence};
+use crate::sub::{SubOsc, SubPhase};
+use crate::params::Param;
+use crate::tuning::Tuning;
+use crate::widener::{StereoWidener, Widener};
+use crate::{EnvKind, Envelope, Event, ModMode, Operator};
+
+/// ----------  Tempo ----------
+/// Seconds without clock pulses after which the patch tempo takes over again.
+const CLOCK_TIMEOUT: f32 = 0.5;
+
+/// ----------  Routing ----------
+/// Which operators modulate which. Operators are evaluated from the highest
+/// index down, so a source with a lower index than its destination is heard
+/// one sample late. Missing entries count as unconnected. A connection
+/// marked in `ring` multiplies the destination's output by the source's
+/// (ring modulation) instead of modulating its phase or frequency.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Routing {
+    pub mods: Vec<Vec<bool>>, // mods[dst][src]: src modulates dst
+    pub output: Vec<bool>,    // operators summed into the voice output
+    #[serde(default)]
+    pub ring: Vec<Vec<bool>>, // ring[dst][src]: that connection ring-modulates
+}
+
+impl Default for Routing {
+    fn default() -> Self { Self::stack(4) }
+}
+
+impl Routing {
+    /// The classic stack: op n-1 → … → op1 → op0 → out
+    pub fn stack(n: usize) -> Self {
+        let mut r = Self { mods: Vec::new(), output: Vec::new(), ring: Vec::new() };
+        r.resize(n);
+        for dst in 0..n.saturating_sub(1) { r.mods[dst][dst + 1] = true; }
+        if n > 0 { r.output[0] = true; }
+        r
+    }
+
+    pub fn modulates(&self, dst: usize, src: usize) -> bool {
+        self.mods.get(dst).and_then(|row| row.get(src)).copied().unwrap_or(false)
+    }
+
+    pub fn rings(&self, dst: usize, src: usize) -> bool {
+        self.ring.get(dst).and_then(|row| row.get(src)).copied().unwrap_or(false)
+    }
+
+    pub fn outputs(&self, op: usize) -> bool { self.output.get(op).copied().unwrap_or(false) }
+
+    /// Makes room for exactly `n` operators; new connections start off.
+    pub fn resize(&mut self, n: usize) {
+        self.mods.resize(n, Vec::new());
+        self.ring.resize(n, Vec::new());
+        for row in self.mods.iter_mut().chain(&mut self.ring) { row.resize(n, false); }
+        self.output.resize(n, false);
+    }
+
+    /// Exchanges operators `a` and `b`'s places: their rows, columns and
+    /// output flags.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        for grid in [&mut self.mods, &mut self.ring] {
+            if a.max(b) >= grid.len() { continue; }
+            grid.swap(a, b);
+            for row in grid.iter_mut().filter(|row| a.max(b) < row.len()) { row.swap(a, b); }
+        }
+        if a.max(b) < self.output.len() { self.output.swap(a, b); }
+    }
+}
+
+/// The routing as voices read it for one block: fixed size, so the audio
+/// thread never allocates, with mute and solo applied.
+#[derive(Clone, Copy)]
+struct LiveRouting {
+    ops: usize,
+    mods: [[bool; MAX_OPS]; MAX_OPS],
+    ring: [[bool; MAX_OPS]; MAX_OPS],
+    output: [bool; MAX_OPS],
+}
+
+/// ----------  Voices ----------
+#[derive(Clone)]
+struct Voice {
+    ops: [Operator; MAX_OPS], // only the patch's operator count is used
+    out: [f32; MAX_OPS],      // latest output of each operator
+    note: Option<u8>,         // None: triggered from the UI button
+    velocity: f32,            // 0..1
+    age: u64,                 // trigger order, used for voice stealing
+    pan: f32,                 // -1..1 place in the stereo spread
+    glide: f32,               // semitones still to slide, decays to 0
+    channel: u8,              // MIDI channel the note came in on
+    filter: VoiceFilter,
+    sub: SubPhase,
+}
+
+impl Voice {
+    /// `index` seeds each operator's noise differently so voices don't
+    /// play the same noise in unison.
+    fn new(template: &Operator, index: usize) -> Self {
+        let ops = std::array::from_fn(|i| {
+            let mut op = template.clone();
+            op.noise_gen = Noise::new(0x9E37_79B9u32.wrapping_mul((index * MAX_OPS + i + 1) as u32));
+            op
+        });
+        Self { ops, out: [0.0; MAX_OPS], note: None, velocity: 1.0, age: 0, pan: 0.0, glide: 0.0, channel: 0,
+               filter: VoiceFilter::new(&Filter::default()), sub: SubPhase::default() }
+    }
+
+    fn is_active(&self, n: usize) -> bool { self.ops[..n].iter().any(|o| o.env_active()) }
+
+    /// Starts the first `n` operators' envelopes, of the `kinds` the patch
+    /// has now: a silent voice last followed the patch before it changed.
+    fn note_on(&mut self, n: usize, kinds: &[EnvKind; MAX_OPS]) {
+        if !self.is_active(n) { self.sub.reset(); }
+        for (o, &kind) in self.ops[..n].iter_mut().zip(kinds) {
+            o.env_kind = kind;
+            o.note_on();
+        }
+        self.filter.note_on();
+    }
+
+    fn note_off(&mut self) {
+        for o in &mut self.ops { o.note_off(); }
+        self.filter.note_off();
+    }
+
+    /// One stereo sample; `pan` holds each operator's left/right gains.
+    fn sample(&mut self, dt: f32, routing: &LiveRouting, mode: ModMode, pan: &[[f32; 2]; MAX_OPS]) -> [f32; 2] {
+        let n = routing.ops;
+        let mut mix = [0.0; 2];
+        for dst in (0..n).rev() {
+            let (mods, ring) = (&routing.mods[dst], &routing.ring[dst]);
+            let mod_in: f32 = (0..n).filter(|&src| mods[src] && !ring[src]).map(|src| self.out[src]).sum();
+            let ring_gain: f32 = (0..n).filter(|&src| mods[src] && ring[src]).map(|src| self.out[src]).product();
+            self.out[dst] = self.ops[dst].sample(dt, mod_in, mode) * ring_gain;
+            if routing.output[dst] {
+                mix[0] += self.out[dst] * pan[dst][0];
+                mix[1] += self.out[dst] * pan[dst][1];
+            }
+        }
+        mix
+    }
+}
+
+/// ----------  Patch ----------
+/// When a new note slides from the previous one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum GlideMode {
+    #[default]
+    Always,
+    Legato, // only while another key is still held
+}
+
+/// Which held key mono mode plays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum NotePriority {
+    #[default]
+    Last,
+    Low,
+    High,
+}
+
+pub const MAX_VOICES: usize = 32;
+pub const MAX_OPS: usize = 8;
+
+/// Everything the user edits. The UI keeps its own copy and publishes it to
+/// the audio thread, see `control`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Patch {
+    pub ops: Vec<Operator>, // 1..=MAX_OPS; 0 is the carrier of the default stack
+    #[serde(default)]
+    pub routing: Routing,
+    #[serde(default = "default_voices")]
+    pub max_voices: usize,  // polyphony in use, 8..=MAX_VOICES
+    #[serde(default)]
+    pub lfos: [Lfo; LFO_COUNT],
+    #[serde(default)]
+    pub mod_slots: [ModSlot; MOD_SLOTS],
+    #[serde(default)]
+    pub macros: [Macro; MACROS],
+    #[serde(default = "default_bend_range")]
+    pub bend_range: f32,    // semitones at full bend
+    #[serde(default)]
+    pub note_mode: bool,    // every operator runs at the key's pitch × its ratio
+    #[serde(default = "default_a4")]
+    pub a4: f32,            // reference pitch for note mode, Hz
+    #[serde(default = "default_oversample")]
+    pub oversample: usize,  // 1, 2 or 4 voice samples per output sample
+    #[serde(default = "default_dc_block")]
+    pub dc_block: bool,     // high-pass the output to remove DC offset
+    #[serde(default)]
+    pub mod_mode: ModMode,
+    #[serde(default)]
+    pub spread: f32,        // 0..1, how far voices fan out across the stereo field
+    #[serde(default)]
+    pub glide: f32,         // portamento time constant in seconds, 0 is off
+    #[serde(default)]
+    pub glide_mode: GlideMode,
+    #[serde(default)]
+    pub mono: bool,         // one voice, following `priority`
+    #[serde(default)]
+    pub priority: NotePriority,
+    #[serde(default)]
+    pub legato: bool,       // mono: overlapping notes change pitch without retriggering
+    #[serde(default)]
+    pub mpe: bool,          // member channels bend, press and slide their own notes
+    #[serde(default = "default_mpe_bend_range")]
+    pub mpe_bend_range: f32, // semitones at full per-note bend
+    #[serde(default = "default_tempo")]
+    pub tempo: f32,         // BPM for synced features without an external clock
+    #[serde(default)]
+    pub arp: Arp,
+    #[serde(default)]
+    pub seq: Sequence,
+    #[serde(default)]
+    pub sub: SubOsc,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub fx_chain: FxChain,
+    #[serde(default)]
+    pub eq: Eq,
+    #[serde(default)]
+    pub mod_fx: ModFx,
+    #[serde(default)]
+    pub delay: Delay,
+    #[serde(default)]
+    pub reverb: Reverb,
+    #[serde(default)]
+    pub compressor: Compressor,
+    #[serde(default)]
+    pub crusher: Bitcrusher,
+    #[serde(default)]
+    pub widener: Widener,
+    #[serde(default)]
+    pub master: Master,
+    #[serde(default)]
+    pub tuning: Option<Tuning>, // a Scala scale in place of equal temperament
+}
+
+fn default_voices() -> usize { 16 }
+fn default_bend_range() -> f32 { 2.0 }
+fn default_a4() -> f32 { 440.0 }
+fn default_oversample() -> usize { 1 }
+fn default_dc_block() -> bool { true }
+fn default_mpe_bend_range() -> f32 { 48.0 }
+fn default_tempo() -> f32 { 120.0 }
+
+impl Default for Patch {
+    fn default() -> Self {
+        let env = Envelope::new(0.01, 0.05, 0.6, 0.2);
+        let ratios = [1.0, 1.618, 2.414, 3.732];
+        let ops = vec![
+            Operator::new(440.0, 1.0, env, ratios[0], 0.0, false, 16),
+            Operator::new(220.0, 0.8, env, ratios[1], 0.05, true, 12),
+            Operator::new(110.0, 0.6, env, ratios[2], 0.1, true, 10),
+            Operator::new( 55.0, 0.4, env, ratios[3], 0.15, true, 8),
+        ];
+        // Like on the hardware, the mod wheel drives the first modulator harder
+        let mut mod_slots: [ModSlot; MOD_SLOTS] = Default::default();
+        mod_slots[0] = ModSlot { source: ModSource::ModWheel, dest: ModDest::Amp, op: 1, depth: 0.5 };
+        Self { ops, routing: Routing::default(), max_voices: default_voices(), lfos: Default::default(),
+               mod_slots, macros: Default::default(), bend_range: default_bend_range(),
+               note_mode: false, a4: default_a4(), oversample: default_oversample(),
+               dc_block: default_dc_block(), mod_mode: ModMode::default(), spread: 0.0,
+               glide: 0.0, glide_mode: GlideMode::default(), mono: false,
+               priority: NotePriority::default(), legato: false, mpe: false,
+               mpe_bend_range: default_mpe_bend_range(), tempo: default_tempo(), arp: Arp::default(),
+               seq: Sequence::default(), sub: SubOsc::default(), filter: Filter::default(), fx_chain: FxChain::default(), eq: Eq::default(), mod_fx: ModFx::default(), delay: Delay::default(), reverb: Reverb::default(),
+               compressor: Compressor::default(), crusher: Bitcrusher::default(),
+               widener: Widener::default(), master: Master::default(), tuning: None }
+    }
+}
+
+impl Patch {
+    /// A clean starting point: the default stack with a single sine
+    /// carrier at full level, its modulators silent, and nothing in the
+    /// mod matrix.
+    pub fn init() -> Self {
+        let mut patch = Self::default();
+        let base = patch.ops[0].freq;
+        for (i, op) in patch.ops.iter_mut().enumerate() {
+            *op = Operator::init(base, if i == 0 { 1.0 } else { 0.0 });
+        }
+        patch.mod_slots = Default::default();
+        patch
+    }
+
+    /// Pitch of `note` in the patch's tuning; None for keys it leaves unplayed.
+    pub fn key_freq(&self, note: u8) -> Option<f32> {
+        match &self.tuning {
+            Some(tuning) => tuning.freq(note),
+            None => Some(midi_to_freq(note, self.a4)),
+        }
+    }
+
+    fn env_kinds(&self) -> [EnvKind; MAX_OPS] {
+        std::array::from_fn(|i| self.ops.get(i).map_or(EnvKind::Adsr, |o| o.env_kind))
+    }
+
+    /// Operators the engine runs; extras beyond MAX_OPS are ignored.
+    pub fn op_count(&self) -> usize { self.ops.len().min(MAX_OPS) }
+
+    /// Grows or shrinks the patch to `n` operators (1..=MAX_OPS), keeping
+    /// routing and LFO targets in step. New operators copy the last one and
+    /// start unconnected.
+    pub fn set_op_count(&mut self, n: usize) {
+        let n = n.clamp(1, MAX_OPS);
+        let last = self.ops.last().cloned().unwrap_or_else(|| Patch::default().ops[0].clone());
+        self.ops.resize(n, last);
+        self.routing.resize(n);
+        for lfo in &mut self.lfos { lfo.ops.resize(n, false); }
+    }
+
+    /// Exchanges operators `a` and `b` along with everything pointing at
+    /// them (routing, LFO reach, mod slots and macro targets), so the two
+    /// are renumbered rather than rewired. Outside note mode keys still
+    /// transpose relative to whichever operator ends up first.
+    pub fn swap_ops(&mut self, a: usize, b: usize) {
+        if a.max(b) >= self.ops.len() { return; }
+        self.ops.swap(a, b);
+        self.routing.swap(a, b);
+        for lfo in &mut self.lfos {
+            if lfo.ops.len() <= a.max(b) { lfo.ops.resize(a.max(b) + 1, false); }
+            lfo.ops.swap(a, b);
+        }
+        let other = |i: usize| if i == a { b } else if i == b { a } else { i };
+        for slot in &mut self.mod_slots {
+            slot.op = other(slot.op);
+            if let ModSource::Env(i) = slot.source { slot.source = ModSource::Env(other(i)); }
+        }
+        for target in self.macros.iter_mut().flat_map(|m| &mut m.targets) {
+            if let Param::Op(i, p) = target.param { target.param = Param::Op(other(i), p); }
+        }
+    }
+
+    /// Whether `effect` is in use; off means bypassed.
+    pub fn effect_on(&mut self, effect: Effect) -> &mut bool {
+        match effect {
+            Effect::Eq => &mut self.eq.on,
+            Effect::ModFx => &mut self.mod_fx.on,
+            Effect::Delay => &mut self.delay.on,
+            Effect::Reverb => &mut self.reverb.on,
+            Effect::Compressor => &mut self.compressor.on,
+            Effect::Bitcrusher => &mut self.crusher.on,
+            Effect::Widener => &mut self.widener.on,
+        }
+    }
+
+    fn live_routing(&self) -> LiveRouting {
+        let mut r = LiveRouting { ops: self.op_count(), mods: [[false; MAX_OPS]; MAX_OPS],
+                                  ring: [[false; MAX_OPS]; MAX_OPS], output: [false; MAX_OPS] };
+        let solo = self.ops.iter().any(|o| o.solo);
+        for (i, op) in self.ops[..r.ops].iter().enumerate() {
+            for dst in 0..r.ops {
+                r.mods[dst][i] = self.routing.modulates(dst, i) && !op.mute;
+                r.ring[dst][i] = self.routing.rings(dst, i);
+            }
+            // A soloed operator is heard straight at the output
+            r.output[i] = !op.mute && if solo { op.solo } else { self.routing.outputs(i) };
+        }
+        r
+    }
+
+    /// Base frequency of operator `i` in a voice playing `note`; `None` is the
+    /// UI gate, which plays the patch as dialled in (or A4 in note mode).
+    /// Outside note mode a key transposes the whole patch so the carrier
+    /// lands on it. The operator's detune is applied last.
+    fn op_freq(&self, i: usize, note: Option<u8>) -> f32 {
+        let key = note.and_then(|n| self.key_freq(n));
+        let base = match key {
+            _ if self.note_mode => key.unwrap_or(self.a4),
+            Some(hz) => self.ops[i].freq * hz / self.ops[0].freq,
+            None => self.ops[i].freq,
+        };
+        base * 2.0_f32.powf(self.ops[i].detune / 1200.0)
+    }
+}
+
+/// ----------  Synth ----------
+/// Samples between LFO updates.
+const CONTROL_BLOCK: usize = 32;
+/// Time constant of the pitch bend glide, in seconds.
+const BEND_SMOOTHING: f32 = 0.01;
+/// Corner frequency of the output DC blocker, in Hz.
+const DC_CUTOFF: f32 = 20.0;
+
+/// Left/right gains for `pan` (-1..1), equal power and unity at the centre.
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    [angle.cos() * std::f32::consts::SQRT_2, angle.sin() * std::f32::consts::SQRT_2]
+}
+
+/// Latest expression received on one MIDI channel.
+#[derive(Clone, Copy, Default)]
+struct Expression {
+    bend: f32,     // -1..1
+    pressure: f32, // 0..1
+    slide: f32,    // 0..1
+}
+
+/// Equal-tempered pitch of MIDI `note` with A4 (note 69) at `a4` Hz.
+pub fn midi_to_freq(note: u8, a4: f32) -> f32 {
+    a4 * 2.0_f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+pub struct FMSynth {
+    pub patch: Patch,
+    voices: Vec<Voice>, // preallocated pool, MAX_VOICES long
+    lfo_phase: [f32; LFO_COUNT],
+    mod_wheel: f32,     // 0..1
+    aftertouch: f32,    // 0..1
+    slide: f32,         // 0..1, CC74 outside MPE
+    channels: [Expression; 16], // per-channel state for MPE
+    key_channel: [u8; 128], // channel each key last went down on
+    bend: f32,          // -1..1 as received
+    bend_smooth: f32,   // what is actually applied
+    clock: u64,
+    held: u128,         // one bit per MIDI key currently down
+    pressed_at: [u64; 128], // clock when each key last went down
+    sustain: bool,      // pedal down
+    sustained: u128,    // keys let go while the pedal was down, release pending
+    last_note: Option<u8>, // glide starting point
+    ext_tempo: Option<f32>, // BPM of the MIDI clock or Link session we follow
+    since_clock: f32,       // seconds since it last reported
+    arp: Arpeggiator,
+    seq: SeqPlayer,
+    eq: Equalizer,
+    mod_fx: ModulationFx,
+    delay: StereoDelay,
+    reverb: Freeverb,
+    compressor: BusCompressor,
+    crusher: Crusher,
+    widener: StereoWidener,
+    limiter: Limiter,
+    sr: f32,
+    scratch: [Vec<f32>; 2], // one control block per channel at the oversampled rate
+    block: [[f32; CONTROL_BLOCK]; 2], // the same block at the output rate
+    down2: [Decimator; 2],
+    down4: [Decimator; 2],
+    dc: [(f32, f32); 2],    // DC blockers' previous input and output
+}
+
+impl FMSynth {
+    pub fn new(sr: f32) -> Self {
+        let patch = Patch::default();
+        let voices = (0..MAX_VOICES).map(|i| Voice::new(&patch.ops[0], i)).collect();
+        Self { patch, voices, lfo_phase: [0.0; LFO_COUNT], mod_wheel: 0.0, aftertouch: 0.0,
+               slide: 0.0, channels: [Expression::default(); 16], key_channel: [0; 128],
+               bend: 0.0, bend_smooth: 0.0, clock: 0, held: 0,
+               pressed_at: [0; 128], sustain: false, sustained: 0, last_note: None,
+               ext_tempo: None, since_clock: 0.0, arp: Arpeggiator::default(), seq: SeqPlayer::default(),
+               eq: Equalizer::new(sr), mod_fx: ModulationFx::new(sr), delay: StereoDelay::new(sr), reverb: Freeverb::new(sr),
+               compressor: BusCompressor::new(sr), crusher: Crusher::new(sr), widener: StereoWidener::new(sr),
+               limiter: Limiter::new(sr), sr,
+               scratch: std::array::from_fn(|_| vec![0.0; CONTROL_BLOCK * 4]), block: [[0.0; CONTROL_BLOCK]; 2],
+               down2: std::array::from_fn(|_| Decimator::new(2)), down4: std::array::from_fn(|_| Decimator::new(4)),
+               dc: [(0.0, 0.0); 2] }
+    }
+
+    pub fn sample_rate(&self) -> f32 { self.sr }
+
+    /// The compressor's deepest gain reduction since the last call, in dB.
+    pub fn take_gain_reduction(&mut self) -> f32 { self.compressor.take_reduction() }
+
+    /// Whether each output channel went over full scale since the last call.
+    pub fn take_clips(&mut self) -> [bool; 2] { self.limiter.take_clips() }
+
+    /// BPM tempo-synced features run at: the external clock's while it
+    /// runs, otherwise the patch's.
+    pub fn tempo(&self) -> f32 { self.ext_tempo.unwrap_or(self.patch.tempo) }
+
+    /// Locks onto a shared timeline such as Ableton Link: the tempo, plus
+    /// synced LFOs' phases taken from the `beat` position.
+    #[cfg_attr(not(feature = "link"), allow(dead_code))]
+    pub(crate) fn follow_beat(&mut self, bpm: f32, beat: f64) {
+        self.ext_tempo = Some(bpm);
+        self.since_clock = 0.0;
+        for (lfo, phase) in self.patch.lfos.iter().zip(&mut self.lfo_phase).filter(|(l, _)| l.sync) {
+            *phase = (beat / lfo.cycle_beats() as f64).rem_euclid(1.0) as f32;
+        }
+    }
+
+    /// Picks a voice for a new note: the one already playing `note`, else a
+    /// silent one, else the oldest.
+    fn allocate(&mut self, note: Option<u8>) -> &mut Voice {
+        let ops = self.patch.op_count();
+        let pool = &self.voices[..self.patch.max_voices.min(self.voices.len())];
+        let idx = pool.iter().position(|v| v.note == note && v.is_active(ops))
+            .or_else(|| pool.iter().position(|v| !v.is_active(ops)))
+            .unwrap_or_else(|| {
+                (0..pool.len()).min_by_key(|&i| pool[i].age).unwrap_or(0)
+            });
+        &mut self.voices[idx]
+    }
+
+    /// Semitones a note `to` should slide in from `from`, given the glide
+    /// settings and whether other keys are down.
+    fn glide_amount(&self, from: Option<f32>, to: u8) -> f32 {
+        match from {
+            Some(from) if self.patch.glide > 0.0 => {
+                let others = self.held & !(1u128 << to) != 0;
+                if self.patch.glide_mode == GlideMode::Always || others { from - to as f32 } else { 0.0 }
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn start_voice(&mut self, note: Option<u8>, velocity: f32) {
+        if note.is_some_and(|n| self.patch.key_freq(n).is_none()) { return; }
+        let glide = note.map_or(0.0, |to| self.glide_amount(self.last_note.map(f32::from), to));
+        self.clock += 1;
+        let age = self.clock;
+        let ops = self.patch.op_count();
+        let kinds = self.patch.env_kinds();
+        let channel = note.map_or(0, |n| self.key_channel[n as usize]);
+        let voice = self.allocate(note);
+        voice.note = note;
+        voice.velocity = velocity;
+        voice.age = age;
+        voice.glide = glide;
+        voice.channel = channel;
+        // Golden-ratio steps spread successive notes evenly across the field
+        voice.pan = (age as f32 * 0.618_034).fract() * 2.0 - 1.0;
+        voice.note_on(ops, &kinds);
+    }
+
+    fn release_voices(&mut self, note: Option<u8>) {
+        for v in self.voices.iter_mut().filter(|v| v.note == note) { v.note_off(); }
+    }
+
+    /// The held key mono mode should be playing.
+    fn priority_note(&self) -> Option<u8> {
+        if self.held == 0 { return None; }
+        match self.patch.priority {
+            NotePriority::Low => Some(self.held.trailing_zeros() as u8),
+            NotePriority::High => Some(127 - self.held.leading_zeros() as u8),
+            NotePriority::Last => (0..128u8).filter(|&n| self.held >> n & 1 == 1)
+                .max_by_key(|&n| self.pressed_at[n as usize]),
+        }
+    }
+
+    /// Mono mode: moves the one voice to `note`, sliding from wherever it is,
+    /// and restarts its envelopes if `retrigger` or it had gone silent.
+    fn mono_to(&mut self, note: u8, velocity: Option<f32>, retrigger: bool) {
+        if self.patch.key_freq(note).is_none() { return; }
+        let ops = self.patch.op_count();
+        let v = &self.voices[0];
+        let from = match v.note {
+            Some(n) if v.is_active(ops) => Some(n as f32 + v.glide),
+            _ => self.last_note.map(f32::from),
+        };
+        let glide = self.glide_amount(from, note);
+        self.clock += 1;
+        let age = self.clock;
+        let channel = self.key_channel[note as usize];
+        let kinds = self.patch.env_kinds();
+        let v = &mut self.voices[0];
+        v.note = Some(note);
+        v.channel = channel;
+        v.glide = glide;
+        v.pan = 0.0;
+        if let Some(velocity) = velocity { v.velocity = velocity; }
+        if retrigger || !v.is_active(ops) {
+            v.age = age;
+            v.note_on(ops, &kinds);
+        }
+    }
+
+    pub fn note_on(&mut self, velocity: u8) { self.start_voice(None, velocity as f32 / 127.0); }
+    pub fn note_off(&mut self) { self.release_voices(None); }
+
+    pub fn note_on_key(&mut self, note: u8, velocity: u8) {
+        let note = note.min(127);
+        let velocity = velocity as f32 / 127.0;
+        let overlapping = self.held != 0;
+        self.clock += 1;
+        self.pressed_at[note as usize] = self.clock;
+        self.held |= 1 << note;
+        if !self.patch.mono {
+            self.start_voice(Some(note), velocity);
+        } else if self.priority_note() == Some(note) {
+            self.mono_to(note, Some(velocity), !(self.patch.legato && overlapping));
+        }
+        self.last_note = Some(note);
+    }
+
+    pub fn note_off_key(&mut self, note: u8) {
+        self.held &= !(1u128 << note.min(127));
+        if !self.patch.mono { return self.release_voices(Some(note)); }
+        // Voices left over from poly mode still end normally
+        for v in self.voices[1..].iter_mut().filter(|v| v.note == Some(note)) { v.note_off(); }
+        match self.priority_note() {
+            Some(next) if self.voices[0].note != Some(next) => self.mono_to(next, None, !self.patch.legato),
+            Some(_) => {}
+            None => self.voices[0].note_off(),
+        }
+    }
+
+    /// A key going down: played, or handed to the arpeggiator while it runs.
+    fn key_down(&mut self, note: u8, velocity: u8) {
+        if self.patch.arp.on { self.arp.press(note.min(127), velocity) } else { self.note_on_key(note, velocity) }
+    }
+
+    fn key_up(&mut self, note: u8) {
+        if self.patch.arp.on { self.arp.release(note.min(127)) } else { self.note_off_key(note) }
+    }
+
+    /// Runs the arpeggiator and sequencer for `dt` seconds; the arpeggiator
+    /// goes quiet once switched off.
+    fn run_patterns(&mut self, dt: f32, bpm: f32) {
+        let arp = if self.patch.arp.on {
+            self.arp.advance(&self.patch.arp, dt, bpm)
+        } else {
+            NoteChange { off: self.arp.clear(), on: None }
+        };
+        self.play(arp);
+        let seq = self.seq.advance(&self.patch.seq, dt, bpm);
+        self.play(seq);
+    }
+
+    /// Starts the new note before releasing the old one, so mono legato
+    /// slides between them, unless the note simply repeats.
+    fn play(&mut self, change: NoteChange) {
+        let repeat = change.on.map(|(n, _)| n) == change.off;
+        if let Some(note) = change.off.filter(|_| repeat) { self.note_off_key(note); }
+        if let Some((note, velocity)) = change.on { self.note_on_key(note, velocity); }
+        if let Some(note) = change.off.filter(|_| !repeat) { self.note_off_key(note); }
+    }
+
+    /// Whether `channel` is an MPE member channel, owned by its notes.
+    fn member(&self, channel: u8) -> bool { self.patch.mpe && (1..16).contains(&channel) }
+
+    pub fn handle(&mut self, event: Event) {
+        match event {
+            Event::NoteOn { note, velocity, channel } => {
+                self.sustained &= !(1u128 << note.min(127));
+                self.key_channel[note.min(127) as usize] = channel & 0x0F;
+                self.key_down(note, velocity);
+            }
+            Event::NoteOff { note } if self.sustain => self.sustained |= 1 << note.min(127),
+            Event::NoteOff { note } => self.key_up(note),
+            Event::Sustain(on) => {
+                self.sustain = on;
+                if on { return; }
+                let pending = std::mem::take(&mut self.sustained);
+                for note in (0..128u8).filter(|&n| pending >> n & 1 == 1) { self.key_up(note); }
+            }
+            Event::Gate(true) => self.note_on(127),
+            Event::Gate(false) => self.note_off(),
+            Event::ModWheel(v) => self.mod_wheel = v.clamp(0.0, 1.0),
+            Event::ChannelBend { channel, value } if self.member(channel) => {
+                self.channels[channel as usize].bend = value.clamp(-1.0, 1.0);
+            }
+            Event::ChannelPressure { channel, value } if self.member(channel) => {
+                self.channels[channel as usize].pressure = value.clamp(0.0, 1.0);
+            }
+            Event::Slide { channel, value } if self.member(channel) => {
+                self.channels[channel as usize].slide = value.clamp(0.0, 1.0);
+            }
+            Event::ChannelBend { value, .. } => self.bend = value.clamp(-1.0, 1.0),
+            Event::ChannelPressure { value, .. } => self.aftertouch = value.clamp(0.0, 1.0),
+            Event::Slide { value, .. } => self.slide = value.clamp(0.0, 1.0),
+            Event::ControlChange { .. } | Event::SetParam(..) | Event::SetSwitch(..) => {} // the UI's business
+            Event::ClockTempo(bpm) => {
+                self.ext_tempo = Some(bpm.clamp(20.0, 999.0));
+                self.since_clock = 0.0;
+            }
+            // Synced LFOs and the arpeggiator restart with the sequencer so
+            // they stay in phase
+            Event::Start => {
+                self.lfo_phase = [0.0; LFO_COUNT];
+                self.arp.restart();
+                self.seq.start();
+            }
+            Event::Continue => self.seq.resume(),
+            Event::Stop => if let Some(note) = self.seq.stop() { self.note_off_key(note); },
+            Event::PitchBend(v) => self.bend = v.clamp(-1.0, 1.0),
+        }
+    }
+
+    /// Fills `out` with interleaved frames of `channels` channels: left and
+    /// right on the first two, silence on any others, a downmix when mono.
+    pub fn render_block(&mut self, out: &mut [f32], channels: usize) {
+        let channels = channels.max(1);
+        for frames in out.chunks_mut(CONTROL_BLOCK * channels) {
+            let len = frames.len() / channels;
+            self.render_control_block(len);
+            let [l, r] = &self.block;
+            for (f, frame) in frames.chunks_exact_mut(channels).enumerate() {
+                match frame {
+                    [m] => *m = 0.5 * (l[f] + r[f]),
+                    [a, b, rest @ ..] => { *a = l[f]; *b = r[f]; rest.fill(0.0); }
+                    [] => {}
+                }
+            }
+        }
+    }
+
+    /// Renders `len` (at most CONTROL_BLOCK) stereo frames into `block`.
+    fn render_control_block(&mut self, len: usize) {
+        let dt = 1.0 / self.sr;
+        let block_dt = len as f32 * dt;
+        self.since_clock += block_dt;
+        if self.since_clock > CLOCK_TIMEOUT { self.ext_tempo = None; }
+        let bpm = self.tempo();
+        self.run_patterns(block_dt, bpm);
+        let patch = &self.patch;
+        let n = patch.max_voices.min(self.voices.len());
+        let os = match patch.oversample { 2 => 2, 4 => 4, _ => 1 };
+        let routing = patch.live_routing();
+        let ops = routing.ops;
+        let lfo = lfo::advance(&patch.lfos, &mut self.lfo_phase, block_dt, bpm);
+        self.bend_smooth += (self.bend - self.bend_smooth) * (1.0 - (-block_dt / BEND_SMOOTHING).exp());
+        let [over_l, over_r] = &mut self.scratch;
+        let (over_l, over_r) = (&mut over_l[..len * os], &mut over_r[..len * os]);
+        over_l.fill(0.0);
+        over_r.fill(0.0);
+        let mut global = lfo::targets(&patch.lfos, &lfo, self.aftertouch);
+        for m in &mut global { m.push(ModDest::Freq, self.bend_smooth * patch.bend_range / 12.0); }
+        for v in self.voices[..n].iter_mut().filter(|v| v.is_active(ops)) {
+            let mpe = patch.mpe && (1..16).contains(&v.channel);
+            let expr = if mpe { self.channels[v.channel as usize] }
+                       else { Expression { bend: 0.0, pressure: self.aftertouch, slide: self.slide } };
+            let src = Sources {
+                lfo,
+                env: std::array::from_fn(|i| v.ops[i].env_level()),
+                velocity: v.velocity,
+                mod_wheel: self.mod_wheel,
+                aftertouch: expr.pressure,
+                slide: expr.slide,
+            };
+            let mut mods = global;
+            if expr.bend != 0.0 {
+                for m in &mut mods { m.push(ModDest::Freq, expr.bend * patch.mpe_bend_range / 12.0); }
+            }
+            if v.glide != 0.0 {
+                v.glide *= (-block_dt / patch.glide.max(1e-4)).exp();
+                if v.glide.abs() < 1e-3 { v.glide = 0.0; }
+                for m in &mut mods { m.push(ModDest::Freq, v.glide / 12.0); }
+            }
+            modmatrix::apply(&patch.mod_slots, &src, &mut mods);
+            for (i, ((o, p), m)) in v.ops[..ops].iter_mut().zip(&patch.ops).zip(&mods).enumerate() {
+                o.follow(p, patch.op_freq(i, v.note));
+                o.amp *= p.velocity_gain(v.velocity) * p.level_scaling.gain(v.note);
+                o.env_rate = keyscale::rate_factor(p.rate_scaling, v.note);
+                m.apply(o);
+            }
+            let pan = std::array::from_fn(|i| pan_gains(patch.ops.get(i).map_or(0.0, |o| o.pan) + v.pan * patch.spread));
+            v.filter.follow(&patch.filter);
+            let pitch = v.note.map(|n| n as f32 + v.glide);
+            // The sub follows the first operator heard at the output
+            let carrier = (0..ops).find(|&i| routing.output[i]).filter(|_| patch.sub.on);
+            let sub_freq = carrier.map_or(0.0, |c| patch.sub.freq(v.ops[c].freq * v.ops[c].ratio));
+            for (l, r) in over_l.iter_mut().zip(over_r.iter_mut()) {
+                let mut out = v.sample(dt / os as f32, &routing, patch.mod_mode, &pan);
+                if let Some(c) = carrier {
+                    let level = patch.sub.level.clamp(0.0, 1.0) * v.ops[c].amp * v.ops[c].env_level();
+                    let s = v.sub.sample(&patch.sub, sub_freq, dt / os as f32) * level;
+                    out = [out[0] + s, out[1] + s];
+                }
+                if patch.filter.on { out = v.filter.process(&patch.filter, out, dt / os as f32, pitch); }
+                *l += out[0];
+                *r += out[1];
+            }
+        }
+        let dc_r = 1.0 - 2.0 * std::f32::consts::PI * DC_CUTOFF / self.sr;
+        for (ch, over) in [over_l, over_r].into_iter().enumerate() {
+            let out = &mut self.block[ch][..len];
+            match os {
+                2 => self.down2[ch].process(over, out),
+                4 => self.down4[ch].process(over, out),
+                _ => out.copy_from_slice(over),
+            }
+            if patch.dc_block {
+                let (mut x1, mut y1) = self.dc[ch];
+                for s in out.iter_mut() {
+                    y1 = *s - x1 + dc_r * y1;
+                    x1 = *s;
+                    *s = y1;
+                }
+                self.dc[ch] = (x1, y1);
+            }
+        }
+        self.effects(len, bpm);
+    }
+
+    /// The effects chain, in the patch's order, then the master output
+    /// stage: run on the first `len` frames of `block`, at the output rate,
+    /// and leave them within ±1.
+    fn effects(&mut self, len: usize, bpm: f32) {
+        let [l, r] = &mut self.block;
+        let (l, r) = (&mut l[..len], &mut r[..len]);
+        let patch = &self.patch;
+        for effect in patch.fx_chain.order() {
+            match effect {
+                Effect::Eq => self.eq.process(&patch.eq, l, r),
+                Effect::ModFx => self.mod_fx.process(&patch.mod_fx, l, r),
+                Effect::Delay => self.delay.process(&patch.delay, bpm, l, r),
+                Effect::Reverb => self.reverb.process(&patch.reverb, l, r),
+                Effect::Compressor => self.compressor.process(&patch.compressor, l, r),
+                Effect::Bitcrusher => self.crusher.process(&patch.crusher, l, r),
+                Effect::Widener => self.widener.process(&patch.widener, l, r),
+            }
+        }
+        self.limiter.process(&patch.master, l, r);
+    }
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+
+use crate::params::{LfoParam, OpParam, Param};
+use crate::{EnvKind, LevelScaling, ModDest, ModMode, Patch, Preset, Routing, Waveform};
+
+/// ----------  4-op Yamaha SysEx ----------
+/// Yamaha's SysEx manufacturer ID.
+const YAMAHA: u8 = 0x43;
+/// Format bytes of the dumps read: one voice (VCED), a bank of 32 (VMEM),
+/// and the universal format carrying the TX81Z's extra voice settings.
+const VCED: u8 = 0x03;
+const VMEM: u8 = 0x04;
+const UNIVERSAL: u8 = 0x7e;
+/// Header of the TX81Z's additional voice settings (ACED) in the universal format.
+const ACED_HEADER: &[u8] = b"LM  8976AE";
+const VCED_LEN: usize = 93;
+const ACED_LEN: usize = 23;
+const VMEM_VOICE: usize = 128;
+const VMEM_VOICES: usize = 32;
+/// Operators are stored OP4, OP2, OP3, OP1; these are their 0-based numbers.
+const STORED_ORDER: [usize; 4] = [3, 1, 2, 0];
+
+/// Frequency ratios of the 64 coarse settings, rounded as the front panel
+/// shows them, π multiples included.
+#[allow(clippy::approx_constant)]
+const COARSE_RATIOS: [f32; 64] = [
+    0.50, 0.71, 0.78, 0.87, 1.00, 1.41, 1.57, 1.73, 2.00, 2.82, 3.00, 3.14, 3.46, 4.00, 4.24, 4.71,
+    5.00, 5.19, 5.65, 6.00, 6.28, 6.92, 7.00, 7.07, 7.85, 8.00, 8.48, 8.65, 9.00, 9.42, 9.89, 10.00,
+    10.38, 10.99, 11.00, 11.30, 12.00, 12.11, 12.56, 12.72, 13.00, 13.84, 14.00, 14.10, 14.13, 15.00, 15.55, 15.57,
+    15.70, 16.96, 17.27, 17.30, 18.37, 18.84, 19.03, 19.78, 20.41, 20.76, 21.20, 21.98, 22.49, 23.55, 24.22, 25.95,
+];
+/// The TX81Z's eight operator waves, as the nearest waveform here.
+const WAVES: [Waveform; 8] = [
+    Waveform::Sine, Waveform::Sine, Waveform::HalfSine, Waveform::AbsSine,
+    Waveform::AltSine, Waveform::AltSine, Waveform::CamelSine, Waveform::CamelSine,
+];
+/// Cents per detune step either side of the centre (3).
+const DETUNE_CENTS: f32 = 2.5;
+/// Seconds a decay at the fastest rate (31) takes from full level to
+/// silence; every two steps slower doubles it.
+const FASTEST_DECAY: f32 = 0.007;
+/// Seconds the fastest attack below the instant one takes, likewise doubling.
+const FASTEST_ATTACK: f32 = 0.001;
+/// Range of the envelope in dB; a decay crosses it in its full time.
+const EG_RANGE_DB: f32 = 96.0;
+/// Pitch LFO depth in octaves at full depth for each sensitivity (0..7).
+const PITCH_SENS: [f32; 8] = [0.0, 0.01, 0.02, 0.04, 0.07, 0.14, 0.3, 0.6];
+/// Amplitude LFO depth as a fraction of the level for each sensitivity (0..3).
+const AMP_SENS: [f32; 4] = [0.0, 0.15, 0.4, 0.9];
+/// Note level scaling works up from; the 4-op units attenuate upper notes.
+const SCALING_BREAKPOINT: u8 = 48;
+
+/// One operator's settings, unpacked.
+#[derive(Clone, Copy, Default)]
+struct OpData {
+    ar: u8,  // attack rate 0..31
+    d1r: u8, // decay 1 rate 0..31
+    d2r: u8, // decay 2 rate 0..31, 0 holds at the decay 1 level
+    rr: u8,  // release rate 1..15
+    d1l: u8, // decay 1 level 0..15
+    ls: u8,  // level scaling 0..99
+    rs: u8,  // rate scaling 0..3
+    ame: bool, // amplitude LFO on
+    kvs: u8, // velocity sensitivity 0..7
+    out: u8, // output level 0..99
+    crs: u8, // coarse frequency 0..63
+    det: u8, // detune 0..6, 3 is none
+    fix: bool,
+    fixrg: u8, // fixed frequency range 0..7
+    fine: u8,  // 0..15
+    osw: u8,   // wave 0..7
+}
+
+/// A whole voice, unpacked; `ops` run OP1..OP4.
+#[derive(Clone, Default)]
+struct Voice {
+    ops: [OpData; 4],
+    alg: u8, // 0..7
+    fbl: u8, // feedback 0..7
+    lfs: u8, // LFO speed 0..99
+    pmd: u8, // pitch and amplitude LFO depths 0..99
+    amd: u8,
+    pms: u8, // and sensitivities, 0..7 and 0..3
+    ams: u8,
+    lfw: u8, // LFO wave: saw up, square, triangle, sample and hold
+    mono: bool,
+    pbr: u8,  // pitch bend range in semitones
+    port: u8, // portamento time 0..99
+    name: String,
+}
+
+/// Reads every voice in a TX81Z, DX21, DX27 or DX100 SysEx file: banks of
+/// 32 and single voices, the TX81Z's extra wave and fine frequency
+/// settings included when the dump has them. Pitch envelopes, the LFO
+/// delay, transpose and controller assignments aren't carried over.
+pub fn import(data: &[u8]) -> Result<Vec<Preset>, Box<dyn Error>> {
+    let mut voices = Vec::new();
+    let mut aced = None; // precedes the VCED it belongs to
+    for message in data.split(|&b| b == 0xf7) {
+        let Some(start) = message.iter().position(|&b| b == 0xf0) else { continue };
+        let message = &message[start..];
+        if message.len() < 7 || message[1] != YAMAHA { continue; }
+        let (format, len) = (message[3], (message[4] as usize) << 7 | message[5] as usize);
+        let (body, checksum) = (message.get(6..6 + len).ok_or("SysEx message is cut short")?, message.get(6 + len));
+        let sum = body.iter().fold(0u8, |s, &b| s.wrapping_add(b)).wrapping_add(*checksum.unwrap_or(&0));
+        if checksum.is_none() || sum & 0x7f != 0 { return Err("SysEx checksum doesn't match".into()); }
+        match (format, len) {
+            (VMEM, _) if len == VMEM_VOICE * VMEM_VOICES => voices.extend(body.chunks(VMEM_VOICE).map(vmem_voice)),
+            (VCED, VCED_LEN) => voices.push(vced_voice(body, aced.take())),
+            (UNIVERSAL, _) if body.starts_with(ACED_HEADER) && body.len() >= ACED_HEADER.len() + ACED_LEN => {
+                aced = Some(&body[ACED_HEADER.len()..]);
+            }
+            _ => {} // performances, system settings, other models
+        }
+    }
+    if voices.is_empty() { return Err("No 4-op Yamaha voices found".into()); }
+    Ok(voices.iter().map(Voice::preset).collect())
+}
+
+/// A voice from a 32-voice bank, with its settings packed into bit fields.
+fn vmem_voice(v: &[u8]) -> Voice {
+    let mut voice = Voice::default();
+    for (k, &i) in STORED_ORDER.iter().enumerate() {
+        let (o, a) = (&v[k * 10..k * 10 + 10], &v[73 + k * 2..75 + k * 2]);
+        voice.ops[i] = OpData {
+            ar: o[0], d1r: o[1], d2r: o[2], rr: o[3], d1l: o[4], ls: o[5],
+            ame: o[6] >> 6 & 1 != 0, kvs: o[6] & 7, out: o[7], crs: o[8], rs: o[9] >> 3 & 3, det: o[9] & 7,
+            fix: a[0] >> 3 & 1 != 0, fixrg: a[0] & 7, osw: a[1] >> 4 & 7, fine: a[1] & 15,
+        };
+    }
+    Voice {
+        alg: v[40] & 7, fbl: v[40] >> 3 & 7, lfs: v[41], pmd: v[43], amd: v[44],
+        pms: v[45] >> 4 & 7, ams: v[45] >> 2 & 3, lfw: v[45] & 3, pbr: v[47], mono: v[48] >> 3 & 1 != 0, port: v[49],
+        name: name(&v[57..67]),
+        ..voice
+    }
+}
+
+/// A single voice, one setting a byte, with the TX81Z's additional
+/// settings if they came just before it.
+fn vced_voice(v: &[u8], aced: Option<&[u8]>) -> Voice {
+    let mut voice = Voice::default();
+    for (k, &i) in STORED_ORDER.iter().enumerate() {
+        let o = &v[k * 13..k * 13 + 13];
+        let a = aced.map_or([0; 5], |a| [a[k * 5], a[k * 5 + 1], a[k * 5 + 2], a[k * 5 + 3], a[k * 5 + 4]]);
+        voice.ops[i] = OpData {
+            ar: o[0], d1r: o[1], d2r: o[2], rr: o[3], d1l: o[4], ls: o[5], rs: o[6],
+            ame: o[8] != 0, kvs: o[9], out: o[10], crs: o[11], det: o[12],
+            fix: a[0] != 0, fixrg: a[1], fine: a[2], osw: a[3],
+        };
+    }
+    Voice {
+        alg: v[52], fbl: v[53], lfs: v[54], pmd: v[56], amd: v[57], lfw: v[59], pms: v[60], ams: v[61],
+        mono: v[63] != 0, pbr: v[64], port: v[66], name: name(&v[77..87]),
+        ..voice
+    }
+}
+
+fn name(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { ' ' }).collect::<String>().trim().to_string()
+}
+
+/// ----------  Conversion ----------
+/// Seconds a decay at `rate` (0..31) takes across the whole envelope range.
+fn decay_time(rate: u8) -> f32 { FASTEST_DECAY * 2f32.powf((31.0 - rate.min(31) as f32) / 2.0) }
+
+/// Level 0..99 as gain, -0.75 dB a step below 99.
+fn output_gain(level: u8) -> f32 {
+    if level == 0 { 0.0 } else { 2f32.powf((level.min(99) as f32 - 99.0) / 8.0) }
+}
+
+/// The routing of algorithm `alg` (0..7), with op 0 as OP1. Feedback is on OP4.
+fn algorithm(alg: u8) -> Routing {
+    let (mods, carriers): (&[(usize, usize)], &[usize]) = match alg & 7 {
+        0 => (&[(2, 3), (1, 2), (0, 1)], &[0]),
+        1 => (&[(1, 3), (1, 2), (0, 1)], &[0]),
+        2 => (&[(0, 3), (1, 2), (0, 1)], &[0]),
+        3 => (&[(2, 3), (0, 2), (0, 1)], &[0]),
+        4 => (&[(2, 3), (0, 1)], &[0, 2]),
+        5 => (&[(0, 3), (1, 3), (2, 3)], &[0, 1, 2]),
+        6 => (&[(2, 3)], &[0, 1, 2]),
+        _ => (&[], &[0, 1, 2, 3]),
+    };
+    let mut routing = Routing::stack(4);
+    routing.mods.iter_mut().flatten().for_each(|m| *m = false);
+    routing.output = vec![false; 4];
+    for &(dst, src) in mods { routing.mods[dst][src] = true; }
+    for &op in carriers { routing.output[op] = true; }
+    routing
+}
+
+impl Voice {
+    fn preset(&self) -> Preset {
+        let mut patch = Patch::init();
+        patch.set_op_count(4);
+        patch.routing = algorithm(self.alg);
+        patch.mod_mode = ModMode::Phase;
+        patch.note_mode = false;
+        let carriers = patch.routing.output.iter().filter(|&&c| c).count() as f32;
+        let base = patch.ops[0].freq;
+        for (i, (op, d)) in patch.ops.iter_mut().zip(&self.ops).enumerate() {
+            let carrier = patch.routing.outputs(i);
+            let ratio = Param::Op(i, OpParam::Ratio).range();
+            op.freq = base;
+            op.ratio = if d.fix {
+                // Fixed frequencies become the ratio that plays them at A4
+                (16 * (d.crs as u32 >> 2).max(1) + d.fine as u32) as f32 * (1 << d.fixrg) as f32 / 440.0
+            } else {
+                COARSE_RATIOS[d.crs as usize & 63] + d.fine.min(15) as f32 / 16.0
+            }.clamp(*ratio.start(), *ratio.end());
+            // Carriers share out full level; modulators reach an index of 4π
+            op.amp = output_gain(d.out) * if carrier { 1.0 / carriers } else { 2.0 };
+            op.detune = (d.det.min(6) as f32 - 3.0) * DETUNE_CENTS;
+            op.waveform = WAVES[d.osw as usize & 7];
+            op.velocity_sens = d.kvs.min(7) as f32 / 7.0;
+            op.rate_scaling = [0.125, 0.25, 0.5, 1.0][d.rs as usize & 3];
+            op.level_scaling = LevelScaling { breakpoint: SCALING_BREAKPOINT, right_depth: -(d.ls.min(99) as f32) / 99.0,
+                                              ..LevelScaling::default() };
+            if i == 3 && self.fbl > 0 { op.feedback = 0.5 * 2f32.powi(self.fbl.min(7) as i32 - 7); }
+
+            // Attack, decay 1 to its level, then decay 2 towards silence or a hold
+            op.env_kind = EnvKind::RateLevel;
+            let env = &mut op.rate_level;
+            let d1l_db = (15 - d.d1l.min(15)) as f32 * 3.0;
+            let d1l = if d.d1l == 0 { 0.0 } else { 10f32.powf(-d1l_db / 20.0) };
+            let held = |rate: u8, db: f32| if rate == 0 { None } else { Some(decay_time(rate) * db / EG_RANGE_DB) };
+            env.times[0] = if d.ar >= 31 { 0.0 } else { FASTEST_ATTACK * 2f32.powf((31.0 - d.ar as f32) / 2.0) };
+            env.levels[0] = 1.0;
+            (env.times[1], env.levels[1]) = held(d.d1r, d1l_db).map_or((0.0, 1.0), |t| (t, d1l));
+            let from = env.levels[1];
+            // Without a decay 1 the level never reaches decay 2
+            let d2r = if d.d1r == 0 { 0 } else { d.d2r };
+            (env.times[2], env.levels[2]) = held(d2r, EG_RANGE_DB - d1l_db).map_or((0.0, from), |t| (t, 0.0));
+            env.times[3] = decay_time(d.rr.clamp(1, 15) * 2 + 1) * (EG_RANGE_DB - d1l_db) / EG_RANGE_DB;
+            env.levels[3] = 0.0;
+            env.loop_to = None;
+        }
+
+        let rate = Param::Lfo(0, LfoParam::Rate).range();
+        let hz = (0.06 * (50.0f32 / 0.06).powf(self.lfs.min(99) as f32 / 99.0)).clamp(*rate.start(), *rate.end());
+        let shape = [Waveform::Saw, Waveform::Square, Waveform::Triangle, Waveform::Square][self.lfw as usize & 3];
+        let depths = [
+            (ModDest::Freq, self.pmd.min(99) as f32 / 99.0 * PITCH_SENS[self.pms as usize & 7], vec![true; 4]),
+            (ModDest::Amp, self.amd.min(99) as f32 / 99.0 * AMP_SENS[self.ams as usize & 3],
+             self.ops.iter().map(|d| d.ame).collect()),
+        ];
+        for (lfo, (target, depth, ops)) in patch.lfos.iter_mut().zip(depths) {
+            (lfo.rate, lfo.shape, lfo.target, lfo.depth, lfo.ops, lfo.sync) = (hz, shape, target, depth, ops, false);
+        }
+        patch.mono = self.mono;
+        patch.bend_range = self.pbr.min(12) as f32;
+        patch.glide = (self.port.min(99) as f32 / 99.0).powi(2) * 2.0;
+
+        let mut preset = Preset { patch, ..Preset::default() };
+        preset.info.name = self.name.clone();
+        preset
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// ----------  Scala scales ----------
+/// Most notes a scale may have.
+const MAX_DEGREES: usize = 1024;
+
+/// A scale from a Scala `.scl` file: the pitch of each degree above the
+/// tonic in cents, the last one being the period the scale repeats at
+/// (usually, but not always, the octave).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scale {
+    pub description: String,
+    pub cents: Vec<f32>, // degrees 1..=n; degree 0 is the tonic at 0
+}
+
+impl Default for Scale {
+    /// Twelve-tone equal temperament.
+    fn default() -> Self { Self { description: "12-TET".into(), cents: (1..=12).map(|i| i as f32 * 100.0).collect() } }
+}
+
+impl Scale {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> { Self::parse(&fs::read_to_string(path)?) }
+
+    /// Reads a `.scl` file: `!` starts a comment line, then come the
+    /// description, the number of notes and one pitch per line, in cents
+    /// when it has a `.`, else as a ratio like `3/2` or `2`.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.starts_with('!'));
+        let description = lines.next().ok_or("Scale file is empty")?.to_string();
+        let count: usize = lines.next().and_then(|l| l.split_whitespace().next()).ok_or("Scale has no note count")?
+            .parse().map_err(|_| "Scale's note count isn't a number")?;
+        if count == 0 || count > MAX_DEGREES { return Err(format!("Scale has {} notes", count).into()); }
+        let cents = lines.take(count).map(pitch).collect::<Result<Vec<_>, _>>()?;
+        if cents.len() < count { return Err(format!("Scale lists {} of its {} notes", cents.len(), count).into()); }
+        if cents[count - 1] <= 0.0 { return Err("Scale doesn't rise to its period".into()); }
+        Ok(Self { description, cents })
+    }
+
+    /// The period the scale repeats at, in cents.
+    pub fn period(&self) -> f32 { self.cents.last().copied().unwrap_or(1200.0) }
+
+    /// Cents above the tonic of `degree`, which may run past either end
+    /// into other periods.
+    fn degree_cents(&self, degree: i32) -> f32 {
+        let n = self.cents.len().max(1) as i32;
+        let (periods, step) = (degree.div_euclid(n), degree.rem_euclid(n));
+        periods as f32 * self.period() + if step == 0 { 0.0 } else { self.cents[step as usize - 1] }
+    }
+}
+
+/// One pitch line of a `.scl` file, in cents.
+fn pitch(line: &str) -> Result<f32, Box<dyn Error>> {
+    let value = line.split_whitespace().next().ok_or("Scale has an empty pitch line")?;
+    let bad = || format!("Bad pitch in scale: {}", value);
+    let cents = if value.contains('.') {
+        value.parse::<f32>().map_err(|_| bad())?
+    } else {
+        let (num, den) = value.split_once('/').unwrap_or((value, "1"));
+        let (num, den): (f64, f64) = (num.parse().map_err(|_| bad())?, den.parse().map_err(|_| bad())?);
+        if num <= 0.0 || den <= 0.0 { return Err(bad().into()); }
+        (1200.0 * (num / den).log2()) as f32
+    };
+    if cents.is_finite() { Ok(cents) } else { Err(bad().into()) }
+}
+
+/// ----------  Keyboard mappings ----------
+/// How MIDI notes land on a scale, from a Scala `.kbm` file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyMap {
+    pub first: u8,             // lowest and highest notes played
+    pub last: u8,
+    pub middle: u8,            // the note playing the tonic
+    pub reference: u8,         // the note tuned to `freq`
+    pub freq: f32,             // Hz
+    pub octave_degree: usize,  // degree each repeat of `keys` moves up; 0 is the scale's period
+    pub keys: Vec<Option<usize>>, // degree of each key from `middle` on, None unplayed; empty maps every key in turn
+}
+
+impl Default for KeyMap {
+    /// Every key a step of the scale, C4 on the tonic and A4 at 440 Hz.
+    fn default() -> Self {
+        Self { first: 0, last: 127, middle: 60, reference: 69, freq: 440.0, octave_degree: 0, keys: Vec::new() }
+    }
+}
+
+impl KeyMap {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> { Self::parse(&fs::read_to_string(path)?) }
+
+    /// Reads a `.kbm` file: map size, first and last note, middle note,
+    /// reference note and its frequency, the formal octave degree, then a
+    /// degree (or `x` for none) per key of the map.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut values = text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('!'))
+            .map(|l| l.split_whitespace().next().unwrap_or(""));
+        let mut number = |what: &str| -> Result<f64, Box<dyn Error>> {
+            let value = values.next().ok_or(format!("Keyboard mapping has no {}", what))?;
+            value.parse().map_err(|_| format!("Keyboard mapping's {} isn't a number: {}", what, value).into())
+        };
+        let size = number("map size")? as usize;
+        let note = |v: f64| v.clamp(0.0, 127.0) as u8;
+        let (first, last, middle) = (note(number("first note")?), note(number("last note")?), note(number("middle note")?));
+        let reference = note(number("reference note")?);
+        let freq = number("reference frequency")? as f32;
+        let octave_degree = number("octave degree")? as usize;
+        if !freq.is_finite() || freq <= 0.0 { return Err("Keyboard mapping's reference frequency must be above 0".into()); }
+        if size > MAX_DEGREES { return Err(format!("Keyboard mapping has {} keys", size).into()); }
+        // Keys the file leaves out at the end aren't played
+        let keys = (0..size).map(|_| values.next().and_then(|v| v.parse().ok())).collect();
+        Ok(Self { first, last, middle, reference, freq, octave_degree, keys })
+    }
+
+    /// The scale degree `note` plays, or None when it isn't mapped.
+    fn degree(&self, note: u8, scale: &Scale) -> Option<i32> {
+        if note < self.first || note > self.last { return None; }
+        let from_middle = note as i32 - self.middle as i32;
+        if self.keys.is_empty() { return Some(from_middle); }
+        let size = self.keys.len() as i32;
+        let octave = if self.octave_degree == 0 { scale.cents.len() } else { self.octave_degree } as i32;
+        let key = self.keys[from_middle.rem_euclid(size) as usize]? as i32;
+        Some(from_middle.div_euclid(size) * octave + key)
+    }
+}
+
+/// ----------  Tuning ----------
+/// A scale laid over the keyboard; patches without one play equal
+/// temperament from their A4.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Tuning {
+    pub name: String, // the scale file's, for the UI
+    pub scale: Scale,
+    pub map: KeyMap,
+}
+
+impl Tuning {
+    /// Frequency of `note`, or None when the mapping leaves it unplayed.
+    pub fn freq(&self, note: u8) -> Option<f32> {
+        let cents = self.scale.degree_cents(self.map.degree(note, &self.scale)?);
+        // An unmapped reference still anchors the keys either side of it
+        let reference = self.map.degree(self.map.reference, &self.scale)
+            .unwrap_or(self.map.reference as i32 - self.map.middle as i32);
+        Some(self.map.freq * 2f32.powf((cents - self.scale.degree_cents(reference)) / 1200.0))
+    }
+}
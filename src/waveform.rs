@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::f32::consts::TAU;
+use std::path::Path;
+use std::sync::Arc;
+
+/// ----------  Waveforms ----------
+/// Operator output shapes. The last four are sine variants in the spirit of
+/// the TX81Z's W3/W4/W5/W7 waves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    HalfSine,  // negative half silenced
+    AbsSine,   // full-wave rectified
+    AltSine,   // double-speed sine, silent second half
+    CamelSine, // rectified AltSine
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 8] = [
+        Waveform::Sine, Waveform::Triangle, Waveform::Saw, Waveform::Square,
+        Waveform::HalfSine, Waveform::AbsSine, Waveform::AltSine, Waveform::CamelSine,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Sine => "Sine",
+            Waveform::Triangle => "Triangle",
+            Waveform::Saw => "Saw",
+            Waveform::Square => "Square",
+            Waveform::HalfSine => "Half Sine",
+            Waveform::AbsSine => "Abs Sine",
+            Waveform::AltSine => "Alt Sine",
+            Waveform::CamelSine => "Camel Sine",
+        }
+    }
+
+    /// `phase` in radians (any range); `step` is this sample's phase advance
+    /// in cycles, which sets the PolyBLEP width for the discontinuous shapes.
+    pub fn eval(self, phase: f32, step: f32) -> f32 {
+        let t = (phase / TAU).rem_euclid(1.0);
+        let dt = step.abs().clamp(1e-6, 0.5);
+        match self {
+            Waveform::Sine => phase.sin(),
+            Waveform::Triangle => 1.0 - 4.0 * ((t + 0.25).fract() - 0.5).abs(),
+            Waveform::Saw => 2.0 * t - 1.0 - poly_blep(t, dt),
+            Waveform::Square => {
+                let sq = if t < 0.5 { 1.0 } else { -1.0 };
+                sq + poly_blep(t, dt) - poly_blep((t + 0.5).fract(), dt)
+            }
+            Waveform::HalfSine => phase.sin().max(0.0),
+            Waveform::AbsSine => phase.sin().abs(),
+            Waveform::AltSine => if t < 0.5 { (2.0 * phase).sin() } else { 0.0 },
+            Waveform::CamelSine => if t < 0.5 { (2.0 * phase).sin().abs() } else { 0.0 },
+        }
+    }
+}
+
+/// Polynomial band-limited step correction around a unit jump at t = 0.
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// ----------  Custom waveforms ----------
+/// Longest table kept; longer files are resampled down to it.
+pub const MAX_TABLE_LEN: usize = 4096;
+
+/// A single cycle loaded from a WAV file, read with linear interpolation.
+/// Shared, so voices copying their operator settings never allocate.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "Table", into = "Table")]
+pub struct Wavetable(Arc<Table>);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Table {
+    name: String,
+    samples: Vec<f32>,
+}
+
+impl From<Table> for Wavetable {
+    fn from(mut table: Table) -> Self {
+        if table.samples.is_empty() { table.samples.push(0.0); }
+        Self(Arc::new(table))
+    }
+}
+
+impl From<Wavetable> for Table {
+    fn from(w: Wavetable) -> Self { (*w.0).clone() }
+}
+
+impl Wavetable {
+    /// Reads the first channel of a WAV file as one cycle, normalized.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let channels = spec.channels.max(1) as usize;
+        let interleaved: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1i64 << (spec.bits_per_sample.clamp(1, 32) - 1)) as f32;
+                reader.samples::<i32>().map(|s| s.map(|s| s as f32 * scale)).collect::<Result<_, _>>()?
+            }
+        };
+        let mut samples: Vec<f32> = interleaved.iter().step_by(channels).copied().collect();
+        if samples.len() < 2 { return Err("Too short for a waveform".into()); }
+        if samples.len() > MAX_TABLE_LEN {
+            let source = Self::from(Table { name: String::new(), samples });
+            samples = (0..MAX_TABLE_LEN).map(|i| source.eval(TAU * i as f32 / MAX_TABLE_LEN as f32)).collect();
+        }
+        let peak = samples.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        if peak < 1e-6 { return Err("The file is silent".into()); }
+        for s in &mut samples { *s /= peak; }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        Ok(Self::from(Table { name, samples }))
+    }
+
+    pub fn name(&self) -> &str { &self.0.name }
+
+    /// `phase` in radians (any range).
+    pub fn eval(&self, phase: f32) -> f32 {
+        let s = &self.0.samples;
+        let pos = (phase / TAU).rem_euclid(1.0) * s.len() as f32;
+        let i = (pos as usize).min(s.len() - 1);
+        let frac = pos - i as f32;
+        s[i] + (s[(i + 1) % s.len()] - s[i]) * frac
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+/// ----------  Stereo widener ----------
+/// Longest Haas delay, in ms.
+pub const MAX_HAAS: f32 = 30.0;
+
+/// Stereo width settings: mid/side scaling, plus an optional Haas delay
+/// that holds the right channel back a few milliseconds.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Widener {
+    pub on: bool,
+    pub width: f32, // 0 mono, 1 unchanged, 2 sides doubled
+    pub haas: f32,  // ms, 0..MAX_HAAS; 0 is off
+}
+
+impl Default for Widener {
+    fn default() -> Self { Self { on: false, width: 1.5, haas: 0.0 } }
+}
+
+/// The running widener: the right channel's Haas delay line.
+pub(crate) struct StereoWidener {
+    line: Vec<f32>,
+    pos: usize,
+    sr: f32,
+    was_on: bool,
+}
+
+impl StereoWidener {
+    pub fn new(sr: f32) -> Self {
+        Self { line: vec![0.0; (MAX_HAAS * 0.001 * sr) as usize + 1], pos: 0, sr, was_on: false }
+    }
+
+    /// Widens a stereo block in place.
+    pub fn process(&mut self, settings: &Widener, l: &mut [f32], r: &mut [f32]) {
+        if !settings.on { self.was_on = false; return; }
+        if !self.was_on { self.line.fill(0.0); self.was_on = true; }
+        let width = settings.width.clamp(0.0, 2.0);
+        let len = self.line.len();
+        let delay = ((settings.haas.clamp(0.0, MAX_HAAS) * 0.001 * self.sr) as usize).min(len - 1);
+        for (a, b) in l.iter_mut().zip(r.iter_mut()) {
+            let (mid, side) = ((*a + *b) * 0.5, (*a - *b) * 0.5 * width);
+            *a = mid + side;
+            self.line[self.pos] = mid - side;
+            *b = self.line[(self.pos + len - delay) % len];
+            self.pos = (self.pos + 1) % len;
+        }
+    }
+}